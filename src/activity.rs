@@ -1,7 +1,66 @@
-use chrono::{DateTime, Duration, Local};
+use crate::error::AnalyserError;
+use crate::measurements::{
+    Altitude, Cadence, Distance, FractionalCadence, Grade, HeartRate, LeftRightBalance,
+    PedalSmoothness, Power, Speed, TorqueEffectiveness,
+};
+use chrono::{DateTime, Duration, Local, NaiveDate, TimeZone};
 use fitparser::profile::field_types::MesgNum;
-use fitparser::{self, Error, FitDataRecord, Value};
+use fitparser::{self, FitDataField, FitDataRecord, Value};
+use flate2::read::GzDecoder;
+use std::fmt::{Display, Formatter};
+use std::fs;
 use std::io::Read;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+
+/// Magic bytes at the start of gzip compressed data
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Intervals between records longer than this are treated as pauses, e.g. when the duration has
+/// to be computed from the records because the file has no session totals
+/// Shared by everything that has to tell gaps in the data apart from sparse samples (smart
+/// recording), like resampling, time windows or power coverage. Smart recording can leave more
+/// than 10 seconds between samples at a steady effort.
+pub const MOVING_TIME_GAP_THRESHOLD: Duration = Duration::seconds(15);
+
+/// Earliest plausible timestamp in unix seconds
+/// The FIT profile treats timestamps below 0x10000000 as seconds since the device powered up
+/// instead of absolute times; they also show up from corrupt GPS fixes at the FIT epoch (1989).
+const MIN_VALID_TIMESTAMP: i64 = FIT_EPOCH + 0x1000_0000;
+
+/// Unix timestamp of the FIT epoch (1989-12-31T00:00:00Z)
+const FIT_EPOCH: i64 = 631_065_600;
+
+/// Timestamps further in the future than this are treated as corrupt, leaving some room for
+/// device clocks running ahead
+const MAX_FUTURE_CLOCK_SKEW: Duration = Duration::days(1);
+
+/// Number of semicircles in 180 degrees, FIT positions are stored in semicircles
+const SEMICIRCLES_PER_180_DEGREES: f64 = 2_147_483_648.0;
+
+/// Largest overlap between the records of concatenated activities, e.g. samples recorded again
+/// after restarting the device
+pub const MAX_CONCAT_OVERLAP: Duration = Duration::seconds(60);
+
+/// Session totals summed up when concatenating activities
+const SUMMED_SESSION_FIELDS: [&str; 4] = [
+    "total_timer_time",
+    "total_moving_time",
+    "total_distance",
+    "total_calories",
+];
+
+/// Mean radius of the Earth in meters, used for distances between GPS positions
+const EARTH_RADIUS: f64 = 6_371_000.0;
+
+/// Sub sports of the FIT profile recorded indoors, e.g. on a trainer or a treadmill
+const INDOOR_SUB_SPORTS: [&str; 5] = [
+    "indoor_cycling",
+    "indoor_running",
+    "treadmill",
+    "indoor_rowing",
+    "indoor_walking",
+];
 
 /// Parsed activity data with some basic fields
 #[derive(Debug)]
@@ -11,35 +70,702 @@ pub struct Activity {
     pub duration: Option<Duration>,
     pub records: Vec<FitDataRecord>,
     pub bytes: Vec<u8>,
+    /// Path of the file the activity was read from, None if it was parsed from memory
+    pub source_path: Option<PathBuf>,
+}
+
+/// A single session of an activity, multisport files contain one for each sport
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Session {
+    pub sport: Option<String>,
+    pub sub_sport: Option<String>,
+    pub start_time: Option<DateTime<Local>>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "crate::serialization::option_duration_seconds")
+    )]
+    pub duration: Option<Duration>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "crate::serialization::option_duration_seconds")
+    )]
+    pub elapsed_time: Option<Duration>,
+}
+
+impl Session {
+    /// Read a session from a session message
+    fn from_record(record: &FitDataRecord) -> Self {
+        let records = std::slice::from_ref(record);
+        Self {
+            sport: find_one_value(records, &MesgNum::Session, "sport")
+                .and_then(value_to_str)
+                .cloned(),
+            sub_sport: find_one_value(records, &MesgNum::Session, "sub_sport")
+                .and_then(value_to_str)
+                .cloned(),
+            start_time: find_one_value(records, &MesgNum::Session, "start_time")
+                .and_then(value_to_timestamp)
+                .filter(|start_time| is_valid_timestamp(start_time))
+                .cloned(),
+            duration: find_duration(records),
+            elapsed_time: find_session_time(records, "total_elapsed_time")
+                .or_else(|| find_session_time(records, "total_timer_time")),
+        }
+    }
+
+    /// Check if a timestamp is within the session
+    fn contains(&self, timestamp: &DateTime<Local>) -> bool {
+        match (self.start_time, self.elapsed_time) {
+            (Some(start_time), Some(elapsed_time)) => {
+                start_time <= *timestamp && *timestamp < start_time + elapsed_time
+            }
+            _ => false,
+        }
+    }
+}
+
+/// The device that recorded an activity
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DeviceInfo {
+    /// Manufacturer as named by the FIT profile (e.g. garmin)
+    pub manufacturer: Option<String>,
+    /// Product as named by the FIT profile (e.g. edge_530), or its raw id if it's unknown
+    pub product: Option<String>,
+    /// Product name stored by the device itself, mostly used by non-Garmin devices
+    pub product_name: Option<String>,
+    pub serial_number: Option<i64>,
+    pub software_version: Option<f64>,
+}
+
+impl Display for DeviceInfo {
+    /// Human readable device name, e.g. Garmin Edge 530
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        let names = [
+            self.manufacturer.as_deref().map(title_case),
+            self.product_name
+                .clone()
+                .or_else(|| self.product.as_deref().map(title_case)),
+        ];
+        let names = names.into_iter().flatten().collect::<Vec<_>>();
+        write!(f, "{}", names.join(" "))
+    }
+}
+
+/// Convert a snake case FIT profile name to title case (edge_530 to Edge 530)
+fn title_case(name: &str) -> String {
+    name.split('_')
+        .filter(|word| !word.is_empty())
+        .map(|word| {
+            let mut chars = word.chars();
+            chars
+                .next()
+                .map(|first| first.to_uppercase().chain(chars).collect::<String>())
+                .unwrap_or_default()
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Raw value of the calibration event in the FIT profile, used if it isn't decoded into a name
+const CALIBRATION_EVENT: i64 = 36;
+
+/// A sensor calibration (e.g. a power meter zero offset) recorded during the activity
+/// These help to explain anomalous power data, e.g. a drift that ends after a calibration.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CalibrationEvent {
+    pub timestamp: Option<DateTime<Local>>,
+    pub event_type: Option<String>,
+    /// Raw event data, for power meters this is usually the zero offset
+    pub data: Option<i64>,
+}
+
+impl CalibrationEvent {
+    /// Read a calibration event from an event message, None for other kinds of events
+    fn from_record(record: &FitDataRecord) -> Option<Self> {
+        let records = std::slice::from_ref(record);
+        let is_calibration = match find_one_value(records, &MesgNum::Event, "event")? {
+            Value::String(event) => event == "calibration",
+            value => value
+                .clone()
+                .try_into()
+                .is_ok_and(|event: i64| event == CALIBRATION_EVENT),
+        };
+        if !is_calibration {
+            return None;
+        }
+
+        Some(Self {
+            timestamp: find_one_value(records, &MesgNum::Event, "timestamp")
+                .and_then(value_to_timestamp)
+                .cloned(),
+            event_type: find_one_value(records, &MesgNum::Event, "event_type")
+                .and_then(value_to_str)
+                .cloned(),
+            data: find_one_value(records, &MesgNum::Event, "data")
+                .and_then(|value| value.clone().try_into().ok()),
+        })
+    }
+}
+
+/// Common measurements of a single record, missing or invalid samples are None
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RecordPoint {
+    pub timestamp: DateTime<Local>,
+    pub power: Option<Power>,
+    pub heart_rate: Option<HeartRate>,
+    pub cadence: Option<Cadence>,
+    pub speed: Option<Speed>,
+    pub altitude: Option<Altitude>,
+    pub distance: Option<Distance>,
+    pub grade: Option<Grade>,
+    /// Cadence including `fractional_cadence`, a missing or invalid fractional part counts as 0
+    pub fractional_cadence: Option<FractionalCadence>,
+    pub left_right_balance: Option<LeftRightBalance>,
+    pub left_torque_effectiveness: Option<TorqueEffectiveness>,
+    pub right_torque_effectiveness: Option<TorqueEffectiveness>,
+    pub left_pedal_smoothness: Option<PedalSmoothness>,
+    pub right_pedal_smoothness: Option<PedalSmoothness>,
+}
+
+impl RecordPoint {
+    /// Read the measurements of a record message, None if it has no timestamp
+    fn from_record(record: &FitDataRecord) -> Option<Self> {
+        let mut point = Self {
+            timestamp: *record_timestamp(record)?,
+            power: None,
+            heart_rate: None,
+            cadence: None,
+            speed: None,
+            altitude: None,
+            distance: None,
+            grade: None,
+            fractional_cadence: None,
+            left_right_balance: None,
+            left_torque_effectiveness: None,
+            right_torque_effectiveness: None,
+            left_pedal_smoothness: None,
+            right_pedal_smoothness: None,
+        };
+        let mut cadence_fraction = None;
+        for field in record.fields() {
+            let value = field.value();
+            if !is_finite_value(value) {
+                continue;
+            }
+            match field.name() {
+                "power" => point.power = value.clone().try_into().ok(),
+                "heart_rate" => point.heart_rate = value.clone().try_into().ok(),
+                "cadence" => point.cadence = value.clone().try_into().ok(),
+                // Enhanced fields take precedence over their legacy counterparts
+                "enhanced_speed" => point.speed = value.clone().try_into().ok(),
+                "speed" if point.speed.is_none() => point.speed = value.clone().try_into().ok(),
+                "enhanced_altitude" => point.altitude = value.clone().try_into().ok(),
+                "altitude" if point.altitude.is_none() => {
+                    point.altitude = value.clone().try_into().ok()
+                }
+                "distance" => point.distance = value.clone().try_into().ok(),
+                "grade" => point.grade = value.clone().try_into().ok(),
+                "fractional_cadence" => cadence_fraction = value.clone().try_into().ok(),
+                "left_right_balance" => point.left_right_balance = value.clone().try_into().ok(),
+                "left_torque_effectiveness" => {
+                    point.left_torque_effectiveness = value.clone().try_into().ok()
+                }
+                "right_torque_effectiveness" => {
+                    point.right_torque_effectiveness = value.clone().try_into().ok()
+                }
+                "left_pedal_smoothness" => {
+                    point.left_pedal_smoothness = value.clone().try_into().ok()
+                }
+                "right_pedal_smoothness" => {
+                    point.right_pedal_smoothness = value.clone().try_into().ok()
+                }
+                _ => {}
+            }
+        }
+        point.fractional_cadence = point.cadence.map(|Cadence(cadence)| {
+            FractionalCadence(cadence as f64 + cadence_fraction.unwrap_or(0.0))
+        });
+        Some(point)
+    }
+}
+
+/// Sane bounds of record samples, outside of which samples are treated as sensor errors
+#[derive(Debug, Clone, PartialEq)]
+pub struct SampleBounds {
+    /// Power in Watts
+    pub power: RangeInclusive<f64>,
+    /// Heart rate in bpm
+    pub heart_rate: RangeInclusive<f64>,
+    /// Speed in m/s
+    pub speed: RangeInclusive<f64>,
+}
+
+impl Default for SampleBounds {
+    /// Permissive bounds, only excluding physiologically impossible values
+    fn default() -> Self {
+        Self {
+            power: 0.0..=2500.0,
+            heart_rate: 30.0..=230.0,
+            speed: 0.0..=40.0,
+        }
+    }
+}
+
+impl SampleBounds {
+    /// Check if a record field is within bounds
+    /// Fields without bounds are always accepted.
+    fn contains(&self, field_name: &str, value: &Value) -> bool {
+        let bounds = match field_name {
+            "power" => &self.power,
+            "heart_rate" => &self.heart_rate,
+            "speed" | "enhanced_speed" => &self.speed,
+            _ => return true,
+        };
+        let value: Result<f64, _> = value.clone().try_into();
+        value.map_or(true, |value| bounds.contains(&value))
+    }
 }
 
 impl Activity {
     /// Parse a slice of bytes into an Activity
-    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
-        let records = fitparser::from_bytes(bytes)?;
+    /// Files without any record data (e.g. workouts or courses) are rejected. Records with
+    /// implausible timestamps (before 1998 or in the future) are dropped, a session start time
+    /// like that is replaced by the first valid record timestamp.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, AnalyserError> {
+        let records = fitparser::from_bytes(bytes)?
+            .into_iter()
+            .filter(|record| {
+                record.kind() != MesgNum::Record
+                    || record_timestamp(record).is_none_or(is_valid_timestamp)
+            })
+            .collect::<Vec<_>>();
+        if !records
+            .iter()
+            .any(|record| record.kind() == MesgNum::Record)
+        {
+            let file_type = find_one_value(&records, &MesgNum::FileId, "type")
+                .and_then(value_to_str)
+                .map_or("unknown", |file_type| file_type.as_str());
+            return Err(AnalyserError::EmptyActivity(format!(
+                "not an activity file, no records found (file type: {})",
+                file_type
+            )));
+        }
         let workout_name = find_one_value(&records, &MesgNum::Workout, "wkt_name")
             .and_then(value_to_str)
             .cloned();
         let start_time = find_one_value(&records, &MesgNum::Session, "start_time")
             .and_then(value_to_timestamp)
+            .filter(|start_time| is_valid_timestamp(start_time))
+            .or_else(|| {
+                records
+                    .iter()
+                    .filter(|record| record.kind() == MesgNum::Record)
+                    .find_map(record_timestamp)
+            })
             .cloned();
-        let duration = find_duration(&records);
+        let duration = find_duration(&records)
+            .or_else(|| computed_moving_time(&records, MOVING_TIME_GAP_THRESHOLD));
         Ok(Self {
             workout_name,
             start_time,
             duration,
             records,
             bytes: bytes.to_vec(),
+            source_path: None,
         })
     }
 
     /// Parse a file into an Activity
-    pub fn from_reader<T: Read>(source: &mut T) -> Result<Self, Error> {
+    /// Gzip compressed files (e.g. .fit.gz from bulk exports) are decompressed transparently.
+    pub fn from_reader<T: Read>(source: &mut T) -> Result<Self, AnalyserError> {
         let mut buffer = Vec::new();
         source.read_to_end(&mut buffer)?;
+        if buffer.starts_with(&GZIP_MAGIC) {
+            let mut decompressed = Vec::new();
+            GzDecoder::new(buffer.as_slice()).read_to_end(&mut decompressed)?;
+            buffer = decompressed;
+        }
         Self::from_bytes(&buffer)
     }
 
+    /// Read an activity file, keeping its path for reporting
+    /// FIT files are supported, gzip compressed or not. Other activity formats are rejected
+    /// based on their extension.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, AnalyserError> {
+        let path = path.as_ref();
+        let extension = path
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase());
+        if let Some(extension @ ("gpx" | "tcx")) = extension.as_deref() {
+            return Err(AnalyserError::InvalidValue(format!(
+                "unsupported activity file format: {}",
+                extension
+            )));
+        }
+
+        let mut activity = Self::from_reader(&mut fs::File::open(path)?)?;
+        activity.source_path = Some(path.to_path_buf());
+        Ok(activity)
+    }
+
+    /// Drop record samples outside of the given bounds (e.g. power spikes or heart rate dropouts)
+    /// The rest of the record is kept, so other measurements at the same timestamp remain available.
+    pub fn clean(&mut self, bounds: &SampleBounds) {
+        self.records = std::mem::take(&mut self.records)
+            .into_iter()
+            .map(|record| {
+                if record.kind() != MesgNum::Record {
+                    return record;
+                }
+                let mut cleaned = FitDataRecord::new(record.kind());
+                cleaned.extend(
+                    record
+                        .into_vec()
+                        .into_iter()
+                        .filter(|field| bounds.contains(field.name(), field.value()))
+                        .collect(),
+                );
+                cleaned
+            })
+            .collect();
+    }
+
+    /// Sessions of the activity, multisport activities (e.g. triathlons) have more than one
+    pub fn sessions(&self) -> Vec<Session> {
+        self.records
+            .iter()
+            .filter(|record| record.kind() == MesgNum::Session)
+            .map(Session::from_record)
+            .collect()
+    }
+
+    /// The device that recorded the activity, from the file id and device info messages
+    /// Only the device info message of the creator is used, files also list connected sensors
+    /// there. None if neither the manufacturer nor the product is known.
+    pub fn device_info(&self) -> Option<DeviceInfo> {
+        let manufacturer = self
+            .find_one_value(&MesgNum::FileId, "manufacturer")
+            .and_then(value_to_str)
+            .cloned();
+        let product = self
+            .find_one_value(&MesgNum::FileId, "garmin_product")
+            .or_else(|| self.find_one_value(&MesgNum::FileId, "product"))
+            .map(|value| match value {
+                Value::String(product) => product.clone(),
+                value => value.to_string(),
+            });
+        if manufacturer.is_none() && product.is_none() {
+            return None;
+        }
+
+        let creator = self
+            .records
+            .iter()
+            .find(|record| {
+                record.kind() == MesgNum::DeviceInfo
+                    && find_one_value(
+                        std::slice::from_ref(record),
+                        &MesgNum::DeviceInfo,
+                        "device_index",
+                    )
+                    .is_some_and(is_creator_device_index)
+            })
+            .map(std::slice::from_ref)
+            .unwrap_or_default();
+
+        Some(DeviceInfo {
+            manufacturer,
+            product,
+            product_name: find_one_value(creator, &MesgNum::DeviceInfo, "product_name")
+                .and_then(value_to_str)
+                .cloned(),
+            serial_number: self
+                .find_one_value(&MesgNum::FileId, "serial_number")
+                .and_then(|value| value.clone().try_into().ok()),
+            software_version: find_one_value(creator, &MesgNum::DeviceInfo, "software_version")
+                .and_then(|value| value.clone().try_into().ok()),
+        })
+    }
+
+    /// Sensor calibration events of the activity, e.g. power meter zero offsets
+    pub fn calibration_events(&self) -> Vec<CalibrationEvent> {
+        self.records
+            .iter()
+            .filter(|record| record.kind() == MesgNum::Event)
+            .filter_map(CalibrationEvent::from_record)
+            .collect()
+    }
+
+    /// Split the activity into one activity per session, so each one can be analysed separately
+    /// Records are assigned to sessions based on their timestamps, other messages are kept as is.
+    pub fn split_sessions(&self) -> Vec<Activity> {
+        self.records
+            .iter()
+            .enumerate()
+            .filter(|(_, record)| record.kind() == MesgNum::Session)
+            .map(|(session_index, session_record)| {
+                let session = Session::from_record(session_record);
+                let records = self
+                    .records
+                    .iter()
+                    .enumerate()
+                    .filter(|(index, record)| match record.kind() {
+                        MesgNum::Session => *index == session_index,
+                        MesgNum::Record => record_timestamp(record)
+                            .is_some_and(|timestamp| session.contains(timestamp)),
+                        _ => true,
+                    })
+                    .map(|(_, record)| record.clone())
+                    .collect();
+                Activity {
+                    workout_name: self.workout_name.clone(),
+                    start_time: session.start_time,
+                    duration: session.duration,
+                    records,
+                    bytes: self.bytes.clone(),
+                    source_path: self.source_path.clone(),
+                }
+            })
+            .collect()
+    }
+
+    /// Concatenate the parts of a recording split into multiple files (e.g. after a battery swap)
+    /// Parts are ordered by their start times and their records are concatenated. The first
+    /// session of each part is merged into one, with the totals summed up and the elapsed time
+    /// spanning all parts, and so are their activity messages (see `merge_activity_messages`).
+    /// Laps and events are kept from all parts, other messages are taken from the first part.
+    /// Parts overlapping by more than MAX_CONCAT_OVERLAP are rejected, records within smaller
+    /// overlaps are dropped. Records without a timestamp are kept from all parts.
+    pub fn concat(mut parts: Vec<Activity>) -> Result<Activity, AnalyserError> {
+        if parts.is_empty() {
+            return Err(AnalyserError::EmptyActivity(
+                "no activities to concatenate".to_string(),
+            ));
+        }
+        parts.sort_by_key(|part| part.start_time);
+
+        let mut records = Vec::new();
+        let mut end: Option<DateTime<Local>> = None;
+        for part in &parts {
+            let timestamps = part
+                .records
+                .iter()
+                .filter(|record| record.kind() == MesgNum::Record)
+                .filter_map(record_timestamp);
+            if let (Some(end), Some(first)) = (end, timestamps.clone().min()) {
+                if end - first > MAX_CONCAT_OVERLAP {
+                    return Err(AnalyserError::InvalidValue(format!(
+                        "activities overlap by {} seconds",
+                        (end - first).num_seconds()
+                    )));
+                }
+            }
+            let part_end = timestamps.max();
+            records.extend(
+                part.records
+                    .iter()
+                    .filter(|record| record.kind() == MesgNum::Record)
+                    .filter(|record| {
+                        record_timestamp(record)
+                            .is_none_or(|timestamp| end.is_none_or(|end| *timestamp > end))
+                    })
+                    .cloned(),
+            );
+            end = end.max(part_end.copied());
+        }
+
+        let first = &parts[0];
+        let mut merged_records = parts
+            .iter()
+            .enumerate()
+            .flat_map(|(index, part)| {
+                part.records
+                    .iter()
+                    .filter(move |record| match record.kind() {
+                        MesgNum::Record | MesgNum::Session | MesgNum::Activity => false,
+                        MesgNum::Lap | MesgNum::Event => true,
+                        _ => index == 0,
+                    })
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        merged_records.extend(records);
+        merged_records.extend(merge_sessions(&parts));
+        merged_records.extend(merge_activity_messages(&parts));
+
+        Ok(Activity {
+            workout_name: first.workout_name.clone(),
+            start_time: first.start_time,
+            duration: find_duration(&merged_records)
+                .or_else(|| computed_moving_time(&merged_records, MOVING_TIME_GAP_THRESHOLD)),
+            records: merged_records,
+            bytes: parts
+                .iter()
+                .flat_map(|part| part.bytes.iter().copied())
+                .collect(),
+            source_path: first.source_path.clone(),
+        })
+    }
+
+    /// Slice of the activity between two points in time (start inclusive, end exclusive)
+    /// Records are filtered by their timestamps. Sessions are clipped to the bounds, keeping only
+    /// their sport and times, see `slice_session`. Laps are dropped, as their totals would cover
+    /// the whole laps. Other messages are kept as is. The start time and duration of the slice are
+    /// taken from the bounds.
+    pub fn slice(&self, from: DateTime<Local>, to: DateTime<Local>) -> Activity {
+        let mut records = self
+            .records
+            .iter()
+            .filter(|record| match record.kind() {
+                MesgNum::Record => record_timestamp(record)
+                    .is_some_and(|timestamp| from <= *timestamp && *timestamp < to),
+                MesgNum::Session | MesgNum::Lap => false,
+                _ => true,
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        let sessions = self
+            .records
+            .iter()
+            .filter(|record| record.kind() == MesgNum::Session)
+            .filter_map(|session| slice_session(session, &records, from, to))
+            .collect::<Vec<_>>();
+        records.extend(sessions);
+        Activity {
+            workout_name: self.workout_name.clone(),
+            start_time: Some(from),
+            duration: Some((to - from).max(Duration::zero())),
+            records,
+            bytes: self.bytes.clone(),
+            source_path: self.source_path.clone(),
+        }
+    }
+
+    /// Calendar date of the activity start in a given timezone
+    /// Fixed offsets do not follow daylight saving time, so an offset describing summer time
+    /// shifts winter activities by an hour, which matters for activities started around midnight.
+    pub fn start_date<Tz: TimeZone>(&self, tz: &Tz) -> Option<NaiveDate> {
+        self.start_time
+            .map(|start_time| start_time.with_timezone(tz).date_naive())
+    }
+
+    /// Type of the FIT file (e.g. activity, workout or course)
+    pub fn file_type(&self) -> Option<&String> {
+        self.find_one_value(&MesgNum::FileId, "type")
+            .and_then(value_to_str)
+    }
+
+    /// Sport of the activity (e.g. cycling or running), taken from the first session
+    pub fn sport(&self) -> Option<&String> {
+        self.find_one_value(&MesgNum::Session, "sport")
+            .and_then(value_to_str)
+    }
+
+    /// Check if the activity was recorded indoors (e.g. on a trainer), where speed and elevation
+    /// are meaningless
+    /// Either the sub sport is an indoor one, or there is no GPS position and no movement at all.
+    pub fn is_indoor(&self) -> bool {
+        let indoor_sub_sport = self
+            .find_one_value(&MesgNum::Session, "sub_sport")
+            .and_then(value_to_str)
+            .is_some_and(|sub_sport| INDOOR_SUB_SPORTS.contains(&sub_sport.as_str()));
+        let stationary = self
+            .find_many_values(&MesgNum::Record, "position_lat")
+            .is_empty()
+            && self
+                .record_series()
+                .iter()
+                .all(|point| point.speed.is_none_or(|Speed(speed)| speed <= 0.0));
+        indoor_sub_sport || stationary
+    }
+
+    /// Position (latitude, longitude) in degrees where the first session started
+    pub fn start_position(&self) -> Option<(f64, f64)> {
+        self.session_position("start_position")
+    }
+
+    /// Position (latitude, longitude) in degrees where the first session ended
+    pub fn end_position(&self) -> Option<(f64, f64)> {
+        self.session_position("end_position")
+    }
+
+    fn session_position(&self, field_prefix: &str) -> Option<(f64, f64)> {
+        let coordinate = |axis: &str| {
+            let semicircles: i64 = self
+                .find_one_value(&MesgNum::Session, &format!("{}_{}", field_prefix, axis))?
+                .clone()
+                .try_into()
+                .ok()?;
+            Some(semicircles_to_degrees(semicircles))
+        };
+        Some((coordinate("lat")?, coordinate("long")?))
+    }
+
+    /// Total moving time of the activity, excluding stoppages
+    pub fn moving_time(&self) -> Option<Duration> {
+        find_session_time(&self.records, "total_moving_time")
+    }
+
+    /// Moving time computed from record timestamps, for files without (or with bad) session
+    /// totals
+    /// Intervals between consecutive records are summed, except for the ones of at least
+    /// `gap_threshold`, which are treated as auto-pause gaps.
+    pub fn computed_moving_time(&self, gap_threshold: Duration) -> Option<Duration> {
+        computed_moving_time(&self.records, gap_threshold)
+    }
+
+    /// Total distance of the activity
+    /// Taken from the session totals, falling back to the last distance sample of the records,
+    /// then to the distance between the GPS positions.
+    pub fn distance(&self) -> Option<Distance> {
+        self.find_one_value(&MesgNum::Session, "total_distance")
+            .and_then(|value| value.clone().try_into().ok())
+            .or_else(|| self.get_data::<Distance>("distance").last().copied())
+            .or_else(|| self.gps_distance())
+    }
+
+    /// Distance along the GPS positions of the records, for devices not recording distance
+    /// Haversine distances between consecutive fixes are summed. Records without a valid fix are
+    /// skipped, bridging the gap with a straight line between the surrounding fixes.
+    pub fn gps_distance(&self) -> Option<Distance> {
+        let positions = self
+            .records
+            .iter()
+            .filter(|record| record.kind() == MesgNum::Record)
+            .filter_map(record_position)
+            .collect::<Vec<_>>();
+        if positions.len() < 2 {
+            return None;
+        }
+        Some(Distance(
+            positions
+                .windows(2)
+                .map(|pair| haversine_distance(pair[0], pair[1]))
+                .sum(),
+        ))
+    }
+
+    /// Energy expenditure of the activity in kcal, as recorded by the device
+    pub fn total_calories(&self) -> Option<i64> {
+        self.find_one_value(&MesgNum::Session, "total_calories")
+            .and_then(|value| value.clone().try_into().ok())
+    }
+
+    /// Total elapsed time of the activity, including stoppages
+    pub fn elapsed_time(&self) -> Option<Duration> {
+        find_session_time(&self.records, "total_elapsed_time")
+    }
+
+    /// Total time the timer of the device was running
+    pub fn timer_time(&self) -> Option<Duration> {
+        find_session_time(&self.records, "total_timer_time")
+    }
+
     /// Find a singular raw FIT value
     pub fn find_one_value(&self, mesg_num: &MesgNum, field_name: &str) -> Option<&Value> {
         find_one_value(&self.records, mesg_num, field_name)
@@ -97,31 +823,259 @@ impl Activity {
             .collect()
     }
 
+    /// Common measurements of all records with a timestamp, aligned by record
+    /// Walks the records only once, unlike calling `get_data` for each measurement.
+    pub fn record_series(&self) -> Vec<RecordPoint> {
+        self.records
+            .iter()
+            .filter(|record| record.kind() == MesgNum::Record)
+            .filter_map(RecordPoint::from_record)
+            .collect()
+    }
+
+    /// Resample the record series onto a uniform grid of `period`, starting at the first record
+    /// Each measurement is linearly interpolated between the surrounding samples that have it.
+    /// Points within a gap longer than MOVING_TIME_GAP_THRESHOLD between samples (e.g. pauses),
+    /// or before the first sample of a measurement, have no value. The grid ends at the last full
+    /// period before the last record.
+    pub fn resample(&self, period: Duration) -> Vec<RecordPoint> {
+        let series = self.record_series();
+        let (Some(first), Some(last)) = (series.first(), series.last()) else {
+            return Vec::new();
+        };
+        // Periods under a millisecond can't be stepped by
+        let period_milliseconds = period.num_milliseconds();
+        if period_milliseconds <= 0 {
+            return Vec::new();
+        }
+        let steps = (last.timestamp - first.timestamp).num_milliseconds() / period_milliseconds;
+        let grid = (0..=steps)
+            .map(|step| first.timestamp + Duration::milliseconds(period_milliseconds * step))
+            .collect::<Vec<_>>();
+
+        let interpolate_measurement = |measurement: fn(&RecordPoint) -> Option<f64>| {
+            let samples = series
+                .iter()
+                .filter_map(|point| Some((point.timestamp, measurement(point)?)))
+                .collect::<Vec<_>>();
+            interpolate(&samples, &grid)
+        };
+        let power = interpolate_measurement(|point| Some(point.power?.0 as f64));
+        let heart_rate = interpolate_measurement(|point| Some(point.heart_rate?.0 as f64));
+        let cadence = interpolate_measurement(|point| Some(point.cadence?.0 as f64));
+        let speed = interpolate_measurement(|point| Some(point.speed?.0));
+        let altitude = interpolate_measurement(|point| Some(point.altitude?.0));
+        let distance = interpolate_measurement(|point| Some(point.distance?.0));
+        let grade = interpolate_measurement(|point| Some(point.grade?.0));
+        let fractional_cadence = interpolate_measurement(|point| Some(point.fractional_cadence?.0));
+        let left_right_balance =
+            interpolate_measurement(|point| Some(point.left_right_balance?.0 as f64));
+        let left_torque_effectiveness =
+            interpolate_measurement(|point| Some(point.left_torque_effectiveness?.0));
+        let right_torque_effectiveness =
+            interpolate_measurement(|point| Some(point.right_torque_effectiveness?.0));
+        let left_pedal_smoothness =
+            interpolate_measurement(|point| Some(point.left_pedal_smoothness?.0));
+        let right_pedal_smoothness =
+            interpolate_measurement(|point| Some(point.right_pedal_smoothness?.0));
+
+        grid.iter()
+            .enumerate()
+            .map(|(index, timestamp)| RecordPoint {
+                timestamp: *timestamp,
+                power: power[index].map(|power| Power(power.round() as i64)),
+                heart_rate: heart_rate[index]
+                    .map(|heart_rate| HeartRate(heart_rate.round() as i64)),
+                cadence: cadence[index].map(|cadence| Cadence(cadence.round() as i64)),
+                speed: speed[index].map(Speed),
+                altitude: altitude[index].map(Altitude),
+                distance: distance[index].map(Distance),
+                grade: grade[index].map(Grade),
+                fractional_cadence: fractional_cadence[index].map(FractionalCadence),
+                left_right_balance: left_right_balance[index]
+                    .map(|balance| LeftRightBalance(balance.round() as u8)),
+                left_torque_effectiveness: left_torque_effectiveness[index]
+                    .map(TorqueEffectiveness),
+                right_torque_effectiveness: right_torque_effectiveness[index]
+                    .map(TorqueEffectiveness),
+                left_pedal_smoothness: left_pedal_smoothness[index].map(PedalSmoothness),
+                right_pedal_smoothness: right_pedal_smoothness[index].map(PedalSmoothness),
+            })
+            .collect()
+    }
+
+    /// Cadence of all records, including the sub-rpm precision of `fractional_cadence` where the
+    /// device records it
+    /// Records without cadence are skipped, a missing or invalid fractional part counts as 0.
+    pub fn get_fractional_cadence_data(&self) -> Vec<FractionalCadence> {
+        self.record_series()
+            .iter()
+            .filter_map(|point| point.fractional_cadence)
+            .collect()
+    }
+
+    /// Number of records with a finite value of the field, and the total number of records
+    /// Helps to spot sensor dropouts, e.g. power present in 3200 of 3600 records.
+    pub fn field_coverage(&self, field_name: &str) -> (usize, usize) {
+        self.records
+            .iter()
+            .filter(|record| record.kind() == MesgNum::Record)
+            .fold((0, 0), |(present, total), record| {
+                let has_field = record
+                    .fields()
+                    .iter()
+                    .any(|field| field.name() == field_name && is_finite_value(field.value()));
+                (present + usize::from(has_field), total + 1)
+            })
+    }
+
     /// Get a vector of converted data from an activity
+    /// Non-finite (NaN or infinite) samples are skipped.
     pub fn get_data<T>(&self, field_name: &str) -> Vec<T>
     where
         T: TryFrom<Value>,
     {
         self.find_many_values(&MesgNum::Record, field_name)
             .iter()
+            .filter(|v| is_finite_value(v))
             .filter_map(|v| (*v).clone().try_into().ok())
             .collect()
     }
 
+    /// Get a vector of converted data from an activity, failing on the first sample that can't be
+    /// converted instead of dropping it, so a mis-typed field is not mistaken for missing data
+    /// Non-finite (NaN or infinite) samples are skipped. Fails with MissingField if none of the
+    /// records has the field.
+    pub fn try_get_data<T>(&self, field_name: &str) -> Result<Vec<T>, AnalyserError>
+    where
+        T: TryFrom<Value>,
+        AnalyserError: From<T::Error>,
+    {
+        let values = self.find_many_values(&MesgNum::Record, field_name);
+        if values.is_empty() {
+            return Err(AnalyserError::MissingField(field_name.to_string()));
+        }
+        values
+            .into_iter()
+            .filter(|v| is_finite_value(v))
+            .map(|v| Ok(v.clone().try_into()?))
+            .collect()
+    }
+
     /// Get a vector of converted data from an activity with their respective timestamps
+    /// Non-finite (NaN or infinite) samples are skipped.
     pub fn get_data_with_timestamps<T>(&self, field_name: &str) -> Vec<(T, &DateTime<Local>)>
     where
         T: TryFrom<Value>,
     {
         self.find_many_values_with_timestamps(&MesgNum::Record, field_name)
             .iter()
+            .filter(|(v, _)| is_finite_value(v))
             .filter_map(|(v, t)| Some(((*v).clone().try_into().ok()?, *t)))
             .collect()
     }
 }
 
+/// Convert a FIT position from semicircles to degrees
+pub fn semicircles_to_degrees(semicircles: i64) -> f64 {
+    semicircles as f64 * 180.0 / SEMICIRCLES_PER_180_DEGREES
+}
+
+/// Merge the first sessions of concatenated activities into the first one, None if the first
+/// activity has no session
+/// SUMMED_SESSION_FIELDS are summed up, the elapsed time is extended to the end of the last
+/// session.
+fn merge_sessions(parts: &[Activity]) -> Option<FitDataRecord> {
+    let sessions = parts
+        .iter()
+        .filter_map(|part| {
+            part.records
+                .iter()
+                .find(|record| record.kind() == MesgNum::Session)
+        })
+        .collect::<Vec<_>>();
+    let session = Session::from_record(sessions.first()?);
+    let end = sessions
+        .iter()
+        .map(|record| Session::from_record(record))
+        .filter_map(|session| Some(session.start_time? + session.elapsed_time?))
+        .max();
+
+    let mut merged = FitDataRecord::new(MesgNum::Session);
+    merged.extend(
+        sessions[0]
+            .fields()
+            .iter()
+            .map(|field| {
+                let value = if SUMMED_SESSION_FIELDS.contains(&field.name()) {
+                    let total = sessions
+                        .iter()
+                        .filter_map(|record| {
+                            let value: f64 = record
+                                .fields()
+                                .iter()
+                                .find(|other| other.name() == field.name())?
+                                .value()
+                                .clone()
+                                .try_into()
+                                .ok()?;
+                            Some(value)
+                        })
+                        .sum::<f64>();
+                    match field.value() {
+                        Value::Float32(_) | Value::Float64(_) => Value::Float64(total),
+                        _ => Value::SInt64(total.round() as i64),
+                    }
+                } else if field.name() == "total_elapsed_time" {
+                    match (session.start_time, end) {
+                        (Some(start_time), Some(end)) => {
+                            Value::Float64((end - start_time).num_milliseconds() as f64 / 1000.0)
+                        }
+                        _ => field.value().clone(),
+                    }
+                } else {
+                    field.value().clone()
+                };
+                FitDataField::new(
+                    field.name().to_string(),
+                    field.number(),
+                    value,
+                    field.units().to_string(),
+                )
+            })
+            .collect(),
+    );
+    Some(merged)
+}
+
+/// Position (latitude, longitude) of a record in degrees, None without a valid GPS fix
+fn record_position(record: &FitDataRecord) -> Option<(f64, f64)> {
+    let coordinate = |field_name: &str| {
+        let semicircles: i64 = record
+            .fields()
+            .iter()
+            .find(|field| field.name() == field_name)?
+            .value()
+            .clone()
+            .try_into()
+            .ok()?;
+        Some(semicircles_to_degrees(semicircles))
+    };
+    let (lat, long) = (coordinate("position_lat")?, coordinate("position_long")?);
+    (lat.abs() <= 90.0 && long.abs() <= 180.0).then_some((lat, long))
+}
+
+/// Great-circle distance in meters between two positions given in degrees
+fn haversine_distance((lat_a, long_a): (f64, f64), (lat_b, long_b): (f64, f64)) -> f64 {
+    let (lat_a, lat_b) = (lat_a.to_radians(), lat_b.to_radians());
+    let half_lat_diff = (lat_b - lat_a) / 2.0;
+    let half_long_diff = (long_b - long_a).to_radians() / 2.0;
+    let a = half_lat_diff.sin().powi(2) + lat_a.cos() * lat_b.cos() * half_long_diff.sin().powi(2);
+    2.0 * EARTH_RADIUS * a.sqrt().asin()
+}
+
 /// Find a singular value
-fn find_one_value<'a>(
+pub(crate) fn find_one_value<'a>(
     records: &'a [FitDataRecord],
     mesg_num: &MesgNum,
     field_name: &str,
@@ -146,33 +1100,878 @@ fn find_one_value<'a>(
 }
 
 /// Convert a Value to a String
-fn value_to_str(value: &Value) -> Option<&String> {
+pub(crate) fn value_to_str(value: &Value) -> Option<&String> {
     match value {
         Value::String(str) => Some(str),
         _ => None,
     }
 }
 
+/// Check if a device index refers to the device creating the file, index 0 in the FIT profile
+fn is_creator_device_index(value: &Value) -> bool {
+    match value {
+        Value::String(device_index) => device_index == "creator",
+        value => value
+            .clone()
+            .try_into()
+            .is_ok_and(|device_index: i64| device_index == 0),
+    }
+}
+
+/// Check if a timestamp is plausible for an activity, see MIN_VALID_TIMESTAMP
+fn is_valid_timestamp(timestamp: &DateTime<Local>) -> bool {
+    timestamp.timestamp() >= MIN_VALID_TIMESTAMP
+        && *timestamp <= Local::now() + MAX_FUTURE_CLOCK_SKEW
+}
+
+/// Find the timestamp of a FIT message
+fn record_timestamp(record: &FitDataRecord) -> Option<&DateTime<Local>> {
+    record
+        .fields()
+        .iter()
+        .find(|field| field.name() == "timestamp")
+        .and_then(|field| value_to_timestamp(field.value()))
+}
+
+/// Check that a Value is not a NaN or infinite float
+fn is_finite_value(value: &Value) -> bool {
+    match value {
+        Value::Float32(float) => float.is_finite(),
+        Value::Float64(float) => float.is_finite(),
+        _ => true,
+    }
+}
+
 /// Convert a Value to a timestamp
-fn value_to_timestamp(value: &Value) -> Option<&DateTime<Local>> {
+pub(crate) fn value_to_timestamp(value: &Value) -> Option<&DateTime<Local>> {
     match value {
         Value::Timestamp(timestamp) => Some(timestamp),
         _ => None,
     }
 }
 
-/// Find the duration of an activity based on multiple fallback values
-fn find_duration(records: &[FitDataRecord]) -> Option<Duration> {
-    let total_moving_time = find_one_value(records, &MesgNum::Session, "total_moving_time");
-    let total_elapsed_time = find_one_value(records, &MesgNum::Session, "total_elapsed_time");
-    let total_timer_time = find_one_value(records, &MesgNum::Session, "total_timer_time");
-
-    let duration: f64 = total_moving_time
-        .or(total_elapsed_time)
-        .or(total_timer_time)?
+/// Find a time field of the session message
+fn find_session_time(records: &[FitDataRecord], field_name: &str) -> Option<Duration> {
+    let seconds: f64 = find_one_value(records, &MesgNum::Session, field_name)?
         .clone()
         .try_into()
         .ok()?;
 
-    Some(Duration::seconds(duration as i64))
+    Some(Duration::seconds(seconds as i64))
+}
+
+/// Linearly interpolate timestamped samples at each point of a grid
+/// Grid points before the first sample, after the last one or within a gap longer than
+/// MOVING_TIME_GAP_THRESHOLD have no value.
+fn interpolate(samples: &[(DateTime<Local>, f64)], grid: &[DateTime<Local>]) -> Vec<Option<f64>> {
+    let mut index = 0;
+    grid.iter()
+        .map(|timestamp| {
+            while index + 1 < samples.len() && samples[index + 1].0 <= *timestamp {
+                index += 1;
+            }
+            let (before_time, before_value) = samples.get(index)?;
+            if before_time == timestamp {
+                return Some(*before_value);
+            }
+            let (after_time, after_value) = samples.get(index + 1)?;
+            let interval = *after_time - *before_time;
+            if before_time > timestamp || interval > MOVING_TIME_GAP_THRESHOLD {
+                return None;
+            }
+            let ratio = (*timestamp - *before_time).num_milliseconds() as f64
+                / interval.num_milliseconds() as f64;
+            Some(before_value + (after_value - before_value) * ratio)
+        })
+        .collect()
+}
+
+/// Merge the activity messages of concatenated parts into one, None if none of them has one
+/// The total timer time is summed up, the timestamps are taken from the last part and the
+/// merged activity has a single session.
+fn merge_activity_messages(parts: &[Activity]) -> Option<FitDataRecord> {
+    let messages = parts
+        .iter()
+        .filter_map(|part| {
+            part.records
+                .iter()
+                .find(|record| record.kind() == MesgNum::Activity)
+        })
+        .collect::<Vec<_>>();
+    let last_value = |field_name: &str| {
+        messages.iter().rev().find_map(|record| {
+            find_one_value(
+                std::slice::from_ref(*record),
+                &MesgNum::Activity,
+                field_name,
+            )
+        })
+    };
+
+    let mut merged = FitDataRecord::new(MesgNum::Activity);
+    merged.extend(
+        messages
+            .first()?
+            .fields()
+            .iter()
+            .map(|field| {
+                let value = match field.name() {
+                    "total_timer_time" => Value::Float64(
+                        messages
+                            .iter()
+                            .filter_map(|record| {
+                                let value: f64 = find_one_value(
+                                    std::slice::from_ref(*record),
+                                    &MesgNum::Activity,
+                                    "total_timer_time",
+                                )?
+                                .clone()
+                                .try_into()
+                                .ok()?;
+                                Some(value)
+                            })
+                            .sum(),
+                    ),
+                    "num_sessions" => Value::UInt16(1),
+                    "timestamp" | "local_timestamp" => {
+                        last_value(field.name()).unwrap_or(field.value()).clone()
+                    }
+                    _ => field.value().clone(),
+                };
+                FitDataField::new(
+                    field.name().to_string(),
+                    field.number(),
+                    value,
+                    field.units().to_string(),
+                )
+            })
+            .collect(),
+    );
+    Some(merged)
+}
+
+/// Session fields kept when slicing an activity, the totals of the whole session are dropped
+const SLICED_SESSION_FIELDS: [&str; 2] = ["sport", "sub_sport"];
+
+/// Clip a session message to the bounds of a slice, None if it's outside of them
+/// The elapsed time spans the clipped session, the timer and moving times are computed from the
+/// records within it.
+fn slice_session(
+    session: &FitDataRecord,
+    records: &[FitDataRecord],
+    from: DateTime<Local>,
+    to: DateTime<Local>,
+) -> Option<FitDataRecord> {
+    let Session {
+        start_time,
+        elapsed_time,
+        ..
+    } = Session::from_record(session);
+    let start = start_time.map_or(from, |start_time| start_time.max(from));
+    let end = start_time
+        .zip(elapsed_time)
+        .map_or(to, |(start_time, elapsed_time)| {
+            (start_time + elapsed_time).min(to)
+        });
+    if end <= start {
+        return None;
+    }
+    let session_records = records
+        .iter()
+        .filter(|record| {
+            record.kind() == MesgNum::Record
+                && record_timestamp(record)
+                    .is_some_and(|timestamp| start <= *timestamp && *timestamp < end)
+        })
+        .cloned()
+        .collect::<Vec<_>>();
+    let moving_time = computed_moving_time(&session_records, MOVING_TIME_GAP_THRESHOLD)
+        .unwrap_or_else(Duration::zero);
+    let seconds = |duration: Duration| Value::Float64(duration.num_milliseconds() as f64 / 1000.0);
+
+    let mut sliced = FitDataRecord::new(MesgNum::Session);
+    sliced.extend(
+        session
+            .fields()
+            .iter()
+            .filter(|field| SLICED_SESSION_FIELDS.contains(&field.name()))
+            .cloned()
+            .collect(),
+    );
+    // Field numbers of the FIT profile
+    sliced.extend(vec![
+        FitDataField::new(
+            "timestamp".to_string(),
+            253,
+            Value::Timestamp(end),
+            "s".to_string(),
+        ),
+        FitDataField::new(
+            "start_time".to_string(),
+            2,
+            Value::Timestamp(start),
+            String::new(),
+        ),
+        FitDataField::new(
+            "total_elapsed_time".to_string(),
+            7,
+            seconds(end - start),
+            "s".to_string(),
+        ),
+        FitDataField::new(
+            "total_timer_time".to_string(),
+            8,
+            seconds(moving_time),
+            "s".to_string(),
+        ),
+        FitDataField::new(
+            "total_moving_time".to_string(),
+            59,
+            seconds(moving_time),
+            "s".to_string(),
+        ),
+    ]);
+    Some(sliced)
+}
+
+/// Sum the intervals between records shorter than a gap threshold
+fn computed_moving_time(records: &[FitDataRecord], gap_threshold: Duration) -> Option<Duration> {
+    let timestamps = records
+        .iter()
+        .filter(|record| record.kind() == MesgNum::Record)
+        .filter_map(record_timestamp)
+        .collect::<Vec<_>>();
+    if timestamps.len() < 2 {
+        return None;
+    }
+    Some(
+        timestamps
+            .windows(2)
+            .map(|pair| *pair[1] - *pair[0])
+            .filter(|interval| *interval > Duration::zero() && *interval <= gap_threshold)
+            .sum(),
+    )
+}
+
+/// Find the duration of an activity based on multiple fallback values
+fn find_duration(records: &[FitDataRecord]) -> Option<Duration> {
+    find_session_time(records, "total_moving_time")
+        .or_else(|| find_session_time(records, "total_elapsed_time"))
+        .or_else(|| find_session_time(records, "total_timer_time"))
+}
+
+#[cfg(test)]
+mod activity_tests {
+    use super::*;
+    use crate::measurements::{Average, Power, Speed};
+    use assertables::{assert_in_delta, assert_in_delta_as_result};
+    use chrono::FixedOffset;
+    use std::fs::File;
+
+    #[test]
+    fn activity_file_session_times() {
+        let mut fp = File::open("./tests/fixtures/Activity.fit").unwrap();
+        let activity = Activity::from_reader(&mut fp).unwrap();
+
+        assert_eq!(activity.moving_time(), None);
+        assert_eq!(activity.elapsed_time(), Some(Duration::seconds(3601)));
+        assert_eq!(activity.timer_time(), Some(Duration::seconds(3601)));
+        assert_eq!(activity.duration, activity.elapsed_time());
+        assert_eq!(activity.total_calories(), None);
+
+        let mut fp = File::open("./tests/fixtures/Calories.fit").unwrap();
+        let activity = Activity::from_reader(&mut fp).unwrap();
+        assert_eq!(activity.total_calories(), Some(420));
+    }
+
+    #[test]
+    fn activity_from_path() {
+        let activity = Activity::from_path("./tests/fixtures/PedalingDynamics.fit.gz").unwrap();
+        assert_eq!(
+            activity.source_path,
+            Some(PathBuf::from("./tests/fixtures/PedalingDynamics.fit.gz"))
+        );
+        assert!(!activity.records.is_empty());
+
+        assert!(matches!(
+            Activity::from_path("./tests/fixtures/Route.gpx"),
+            Err(AnalyserError::InvalidValue(_))
+        ));
+        assert!(matches!(
+            Activity::from_path("./tests/fixtures/Missing.fit"),
+            Err(AnalyserError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn activity_file_type() {
+        let mut fp = File::open("./tests/fixtures/Activity.fit").unwrap();
+        let activity = Activity::from_reader(&mut fp).unwrap();
+
+        assert_eq!(activity.file_type().map(String::as_str), Some("activity"));
+    }
+
+    #[test]
+    fn multisport_sessions() {
+        let mut fp = File::open("./tests/fixtures/Multisport.fit").unwrap();
+        let activity = Activity::from_reader(&mut fp).unwrap();
+
+        let sessions = activity.sessions();
+        let sports = sessions
+            .iter()
+            .map(|session| session.sport.as_deref())
+            .collect::<Vec<_>>();
+        assert_eq!(sports, vec![Some("running"), Some("cycling")]);
+        assert_eq!(activity.sport().map(String::as_str), Some("running"));
+        assert_eq!(sessions[1].duration, Some(Duration::seconds(600)));
+
+        let session_activities = activity.split_sessions();
+        let record_counts = session_activities
+            .iter()
+            .map(|activity| activity.get_data::<Speed>("enhanced_speed").len())
+            .collect::<Vec<_>>();
+        assert_eq!(record_counts, vec![300, 600]);
+        assert_eq!(session_activities[1].start_time, sessions[1].start_time);
+        assert_eq!(session_activities[1].duration, Some(Duration::seconds(600)));
+    }
+
+    #[test]
+    fn field_coverage() {
+        let activity = Activity::from_path("./tests/fixtures/Multisport.fit").unwrap();
+        assert_eq!(activity.field_coverage("power"), (600, 900));
+        assert_eq!(activity.field_coverage("heart_rate"), (900, 900));
+        assert_eq!(activity.field_coverage("cadence"), (0, 900));
+    }
+
+    #[test]
+    fn semicircle_conversion() {
+        assert_eq!(semicircles_to_degrees(0), 0.0);
+        assert_eq!(semicircles_to_degrees(1 << 30), 90.0);
+        assert_eq!(semicircles_to_degrees(-(1 << 31)), -180.0);
+        assert_in_delta!(semicircles_to_degrees(566_672_020), 47.4979, 1e-6);
+    }
+
+    #[test]
+    fn distance_from_gps() {
+        let activity = Activity::from_path("./tests/fixtures/GpsOnly.fit").unwrap();
+        assert_eq!(activity.field_coverage("position_lat"), (99, 100));
+        assert!(activity.get_data::<Distance>("distance").is_empty());
+
+        // 0.099 degrees along a meridian, bridging the record without a fix
+        let Distance(distance) = activity.gps_distance().unwrap();
+        assert_in_delta!(distance, 11_008.3, 0.1);
+        assert_eq!(activity.distance(), activity.gps_distance());
+
+        let activity = Activity::from_path("./tests/fixtures/Indoor.fit").unwrap();
+        assert_eq!(activity.gps_distance(), None);
+    }
+
+    #[test]
+    fn concat_split_recording() {
+        let part_1 = Activity::from_path("./tests/fixtures/SplitPart1.fit").unwrap();
+        let part_2 = Activity::from_path("./tests/fixtures/SplitPart2.fit").unwrap();
+        let start_time = part_1.start_time;
+
+        let activity = Activity::concat(vec![part_2, part_1]).unwrap();
+        assert_eq!(activity.start_time, start_time);
+        assert_eq!(activity.field_coverage("power"), (1800, 1800));
+        assert_eq!(activity.get_data::<Power>("power")[1200], Power(250));
+        assert_eq!(activity.sessions().len(), 1);
+        // The 5 minute gap between the parts is elapsed, but not timer time
+        assert_eq!(activity.elapsed_time(), Some(Duration::seconds(2100)));
+        assert_eq!(activity.timer_time(), Some(Duration::seconds(1800)));
+        assert_eq!(activity.duration, Some(Duration::seconds(2100)));
+        assert_eq!(activity.distance(), Some(Distance(14_400.0)));
+        assert_eq!(activity.total_calories(), Some(450));
+        assert_eq!(activity.sport().map(String::as_str), Some("cycling"));
+    }
+
+    #[test]
+    fn concat_keeps_laps_and_events_of_all_parts() {
+        let message = |kind: MesgNum, fields: Vec<(&str, u8, Value)>| {
+            let mut record = FitDataRecord::new(kind);
+            record.extend(
+                fields
+                    .into_iter()
+                    .map(|(name, number, value)| {
+                        FitDataField::new(name.to_string(), number, value, String::new())
+                    })
+                    .collect(),
+            );
+            record
+        };
+        let part = |path: &str| {
+            let mut part = Activity::from_path(path).unwrap();
+            let start_time = part.start_time.unwrap();
+            let elapsed_time = part.elapsed_time().unwrap();
+            let end = start_time + elapsed_time;
+            let seconds = Value::Float64(elapsed_time.num_seconds() as f64);
+            part.records.extend([
+                message(
+                    MesgNum::Lap,
+                    vec![
+                        ("start_time", 2, Value::Timestamp(start_time)),
+                        ("total_elapsed_time", 7, seconds.clone()),
+                    ],
+                ),
+                message(
+                    MesgNum::Event,
+                    vec![
+                        ("timestamp", 253, Value::Timestamp(start_time)),
+                        ("event_type", 1, Value::String("start".to_string())),
+                    ],
+                ),
+                message(
+                    MesgNum::Activity,
+                    vec![
+                        ("timestamp", 253, Value::Timestamp(end)),
+                        ("total_timer_time", 0, seconds),
+                        ("num_sessions", 1, Value::UInt16(1)),
+                    ],
+                ),
+                message(MesgNum::Record, vec![("power", 7, Value::UInt16(100))]),
+            ]);
+            part
+        };
+        let part_1 = part("./tests/fixtures/SplitPart1.fit");
+        let part_2 = part("./tests/fixtures/SplitPart2.fit");
+        let starts = [part_1.start_time, part_2.start_time];
+        let end = part_2.start_time.unwrap() + part_2.elapsed_time().unwrap();
+
+        let activity = Activity::concat(vec![part_2, part_1]).unwrap();
+        let messages = |kind: MesgNum| {
+            activity
+                .records
+                .iter()
+                .filter(|record| record.kind() == kind)
+                .collect::<Vec<_>>()
+        };
+        let lap_starts = messages(MesgNum::Lap)
+            .iter()
+            .map(|lap| {
+                find_one_value(std::slice::from_ref(*lap), &MesgNum::Lap, "start_time")
+                    .and_then(value_to_timestamp)
+                    .copied()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(lap_starts, starts);
+        assert_eq!(messages(MesgNum::Event).len(), 2);
+
+        let activity_messages = messages(MesgNum::Activity);
+        assert_eq!(activity_messages.len(), 1);
+        let activity_message = std::slice::from_ref(activity_messages[0]);
+        let total_timer_time: f64 =
+            find_one_value(activity_message, &MesgNum::Activity, "total_timer_time")
+                .unwrap()
+                .clone()
+                .try_into()
+                .unwrap();
+        assert_eq!(total_timer_time, 1800.0);
+        assert_eq!(
+            find_one_value(activity_message, &MesgNum::Activity, "timestamp")
+                .and_then(value_to_timestamp),
+            Some(&end)
+        );
+
+        // Records without a timestamp are kept from both parts
+        assert_eq!(
+            messages(MesgNum::Record)
+                .iter()
+                .filter(|record| record_timestamp(record).is_none())
+                .count(),
+            2
+        );
+    }
+
+    #[test]
+    fn concat_rejects_overlaps() {
+        let part = || Activity::from_path("./tests/fixtures/SplitPart1.fit").unwrap();
+        let error = Activity::concat(vec![part(), part()]).unwrap_err();
+        assert!(matches!(error, AnalyserError::InvalidValue(_)));
+
+        let error = Activity::concat(Vec::new()).unwrap_err();
+        assert!(matches!(error, AnalyserError::EmptyActivity(_)));
+    }
+
+    #[test]
+    fn session_positions() {
+        let activity = Activity::from_path("./tests/fixtures/Route.fit").unwrap();
+        let (start_lat, start_long) = activity.start_position().unwrap();
+        assert_in_delta!(start_lat, 47.4979, 1e-6);
+        assert_in_delta!(start_long, 19.0402, 1e-6);
+        let (end_lat, end_long) = activity.end_position().unwrap();
+        assert_in_delta!(end_lat, 47.52, 1e-6);
+        assert_in_delta!(end_long, 19.05, 1e-6);
+
+        let activity = Activity::from_path("./tests/fixtures/Indoor.fit").unwrap();
+        assert_eq!(activity.start_position(), None);
+        assert_eq!(activity.end_position(), None);
+    }
+
+    #[test]
+    fn activity_slice() {
+        let mut fp = File::open("./tests/fixtures/Activity.fit").unwrap();
+        let activity = Activity::from_reader(&mut fp).unwrap();
+        let from = activity.start_time.unwrap() + Duration::minutes(10);
+        let to = activity.start_time.unwrap() + Duration::minutes(40);
+
+        let slice = activity.slice(from, to);
+
+        assert_eq!(slice.start_time, Some(from));
+        assert_eq!(slice.duration, Some(Duration::minutes(30)));
+        assert_eq!(slice.get_data::<Power>("power").len(), 1800);
+        assert_eq!(slice.file_type(), activity.file_type());
+        assert_eq!(slice.elapsed_time(), Some(Duration::minutes(30)));
+        // Moving time sums the intervals between the 1800 records
+        assert_eq!(slice.moving_time(), Some(Duration::seconds(1799)));
+        assert_eq!(slice.sport(), activity.sport());
+        let sessions = slice.sessions();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].start_time, Some(from));
+        assert!(slice
+            .records
+            .iter()
+            .all(|record| record.kind() != MesgNum::Lap));
+        assert!(activity.slice(to, from).sessions().is_empty());
+    }
+
+    #[test]
+    fn activity_start_date_in_timezone() {
+        let mut fp = File::open("./tests/fixtures/Activity.fit").unwrap();
+        let activity = Activity::from_reader(&mut fp).unwrap();
+
+        let east = FixedOffset::east_opt(5 * 3600).unwrap();
+        let west = FixedOffset::west_opt(5 * 3600).unwrap();
+        assert_eq!(
+            activity.start_date(&east),
+            NaiveDate::from_ymd_opt(2021, 7, 21)
+        );
+        assert_eq!(
+            activity.start_date(&west),
+            NaiveDate::from_ymd_opt(2021, 7, 20)
+        );
+    }
+
+    #[test]
+    fn workout_file_is_rejected() {
+        let mut fp = File::open("./tests/fixtures/Workout.fit").unwrap();
+        let error = Activity::from_reader(&mut fp).unwrap_err();
+
+        assert!(error.to_string().contains("file type: workout"));
+    }
+
+    #[test]
+    fn non_finite_values() {
+        assert!(is_finite_value(&Value::Float64(5.5)));
+        assert!(is_finite_value(&Value::UInt16(5)));
+        assert!(!is_finite_value(&Value::Float64(f64::NAN)));
+        assert!(!is_finite_value(&Value::Float32(f32::INFINITY)));
+    }
+
+    #[test]
+    fn gzipped_activity_file() {
+        let mut fp = File::open("./tests/fixtures/PedalingDynamics.fit").unwrap();
+        let activity = Activity::from_reader(&mut fp).unwrap();
+        let mut fp = File::open("./tests/fixtures/PedalingDynamics.fit.gz").unwrap();
+        let gzipped_activity = Activity::from_reader(&mut fp).unwrap();
+
+        assert_eq!(gzipped_activity.bytes, activity.bytes);
+        assert_eq!(gzipped_activity.start_time, activity.start_time);
+        assert_eq!(gzipped_activity.records.len(), activity.records.len());
+    }
+
+    #[test]
+    fn aligned_record_series() {
+        let mut fp = File::open("./tests/fixtures/Activity.fit").unwrap();
+        let activity = Activity::from_reader(&mut fp).unwrap();
+        let series = activity.record_series();
+
+        let power = series
+            .iter()
+            .filter_map(|point| point.power)
+            .collect::<Vec<_>>();
+        assert_eq!(power, activity.get_data::<Power>("power"));
+        let heart_rate = series
+            .iter()
+            .filter_map(|point| point.heart_rate)
+            .collect::<Vec<_>>();
+        assert_eq!(heart_rate, activity.get_data::<HeartRate>("heart_rate"));
+        assert_eq!(
+            series.first().map(|point| point.timestamp),
+            activity
+                .get_data_with_timestamps::<Power>("power")
+                .first()
+                .map(|(_, timestamp)| **timestamp)
+        );
+    }
+
+    #[test]
+    fn conversion_failures_are_reported() {
+        let mut fp = File::open("./tests/fixtures/PedalingDynamics.fit").unwrap();
+        let mut activity = Activity::from_reader(&mut fp).unwrap();
+        let mut record = FitDataRecord::new(MesgNum::Record);
+        record.push(FitDataField::new(
+            "power".to_string(),
+            7,
+            Value::String("high".to_string()),
+            "watts".to_string(),
+        ));
+
+        assert_eq!(
+            activity.try_get_data::<Power>("power").unwrap(),
+            activity.get_data::<Power>("power")
+        );
+        assert!(matches!(
+            activity.try_get_data::<Power>("unknown_field"),
+            Err(AnalyserError::MissingField(_))
+        ));
+
+        activity.records.push(record);
+        assert!(matches!(
+            activity.try_get_data::<Power>("power"),
+            Err(AnalyserError::InvalidValue(_))
+        ));
+        assert!(!activity.get_data::<Power>("power").is_empty());
+    }
+
+    #[test]
+    fn moving_time_from_records() {
+        let start = Local.timestamp_opt(1_700_000_000, 0).unwrap();
+        let records = [0, 1, 2, 3, 63, 64, 65]
+            .iter()
+            .map(|seconds| {
+                let mut record = FitDataRecord::new(MesgNum::Record);
+                record.push(FitDataField::new(
+                    "timestamp".to_string(),
+                    253,
+                    Value::Timestamp(start + Duration::seconds(*seconds)),
+                    "s".to_string(),
+                ));
+                record
+            })
+            .collect::<Vec<_>>();
+        let activity = Activity {
+            workout_name: None,
+            start_time: Some(start),
+            duration: None,
+            records,
+            bytes: Vec::new(),
+            source_path: None,
+        };
+
+        assert_eq!(
+            activity.computed_moving_time(MOVING_TIME_GAP_THRESHOLD),
+            Some(Duration::seconds(5))
+        );
+        assert_eq!(
+            activity.computed_moving_time(Duration::minutes(2)),
+            Some(Duration::seconds(65))
+        );
+        assert_eq!(
+            activity
+                .slice(start, start)
+                .computed_moving_time(MOVING_TIME_GAP_THRESHOLD),
+            None
+        );
+    }
+
+    #[test]
+    fn resample_to_uniform_grid() {
+        let start = Local.timestamp_opt(1_700_000_000, 0).unwrap();
+        let records = [(0, 100), (4, 200), (5, 200), (30, 100), (31, 120)]
+            .iter()
+            .map(|(seconds, power)| {
+                let mut record = FitDataRecord::new(MesgNum::Record);
+                record.push(FitDataField::new(
+                    "timestamp".to_string(),
+                    253,
+                    Value::Timestamp(start + Duration::seconds(*seconds)),
+                    "s".to_string(),
+                ));
+                record.push(FitDataField::new(
+                    "power".to_string(),
+                    7,
+                    Value::UInt16(*power),
+                    "watts".to_string(),
+                ));
+                record
+            })
+            .collect::<Vec<_>>();
+        let activity = Activity {
+            workout_name: None,
+            start_time: Some(start),
+            duration: None,
+            records,
+            bytes: Vec::new(),
+            source_path: None,
+        };
+
+        let resampled = activity.resample(Duration::seconds(2));
+        let power = resampled
+            .iter()
+            .map(|point| point.power.map(|Power(power)| power))
+            .collect::<Vec<_>>();
+
+        assert_eq!(resampled.len(), 16);
+        assert_eq!(resampled[15].timestamp, start + Duration::seconds(30));
+        assert_eq!(&power[..4], &[Some(100), Some(150), Some(200), None]);
+        assert_eq!(power[15], Some(100));
+        assert_eq!(resampled[1].heart_rate, None);
+        assert!(activity.resample(Duration::zero()).is_empty());
+        assert!(activity.resample(Duration::microseconds(500)).is_empty());
+    }
+
+    #[test]
+    fn calibration_events() {
+        let start = Local.timestamp_opt(1_700_000_000, 0).unwrap();
+        let event = |seconds: i64, event: Value, data: u32| {
+            let mut record = FitDataRecord::new(MesgNum::Event);
+            record.extend(vec![
+                FitDataField::new(
+                    "timestamp".to_string(),
+                    253,
+                    Value::Timestamp(start + Duration::seconds(seconds)),
+                    "s".to_string(),
+                ),
+                FitDataField::new("event".to_string(), 0, event, String::new()),
+                FitDataField::new(
+                    "event_type".to_string(),
+                    1,
+                    Value::String("marker".to_string()),
+                    String::new(),
+                ),
+                FitDataField::new("data".to_string(), 3, Value::UInt32(data), String::new()),
+            ]);
+            record
+        };
+        let activity = Activity {
+            workout_name: None,
+            start_time: Some(start),
+            duration: None,
+            records: vec![
+                event(0, Value::String("timer".to_string()), 0),
+                event(60, Value::String("calibration".to_string()), 512),
+                event(120, Value::Enum(36), 498),
+            ],
+            bytes: Vec::new(),
+            source_path: None,
+        };
+
+        let events = activity.calibration_events();
+        assert_eq!(events.len(), 2);
+        assert_eq!(
+            events[0],
+            CalibrationEvent {
+                timestamp: Some(start + Duration::seconds(60)),
+                event_type: Some("marker".to_string()),
+                data: Some(512),
+            }
+        );
+        assert_eq!(events[1].data, Some(498));
+
+        let mut fp = File::open("./tests/fixtures/Activity.fit").unwrap();
+        let activity = Activity::from_reader(&mut fp).unwrap();
+        assert!(activity.calibration_events().is_empty());
+    }
+
+    #[test]
+    fn fractional_cadence() {
+        let mut fp = File::open("./tests/fixtures/FractionalCadence.fit").unwrap();
+        let activity = Activity::from_reader(&mut fp).unwrap();
+        let cadence_data = activity.get_fractional_cadence_data();
+
+        assert_eq!(cadence_data.len(), 600);
+        assert_eq!(
+            cadence_data[..2],
+            [FractionalCadence(90.5), FractionalCadence(91.5)]
+        );
+        assert_eq!(
+            Average::average(&cadence_data),
+            Some(FractionalCadence(91.0))
+        );
+        assert_eq!(
+            Average::average(activity.get_data::<Cadence>("cadence")),
+            Some(Cadence(90))
+        );
+    }
+
+    #[test]
+    fn indoor_activities() {
+        let indoor = |path: &str| {
+            let mut fp = File::open(path).unwrap();
+            Activity::from_reader(&mut fp).unwrap().is_indoor()
+        };
+
+        assert!(indoor("./tests/fixtures/Indoor.fit"));
+        // No GPS and no speed
+        assert!(indoor("./tests/fixtures/FractionalCadence.fit"));
+        // No GPS, but moving
+        assert!(!indoor("./tests/fixtures/PedalingDynamics.fit"));
+        assert!(!indoor("./tests/fixtures/Activity.fit"));
+    }
+
+    #[test]
+    fn recording_device() {
+        let mut fp = File::open("./tests/fixtures/PedalingDynamics.fit").unwrap();
+        let activity = Activity::from_reader(&mut fp).unwrap();
+        let device_info = activity.device_info().unwrap();
+
+        assert_eq!(device_info.manufacturer.as_deref(), Some("garmin"));
+        assert_eq!(device_info.product.as_deref(), Some("edge_530"));
+        assert_eq!(device_info.to_string(), "Garmin Edge 530");
+
+        let mut fp = File::open("./tests/fixtures/Activity.fit").unwrap();
+        let activity = Activity::from_reader(&mut fp).unwrap();
+        let device_info = activity.device_info().unwrap();
+
+        assert_eq!(device_info.serial_number, Some(1457061125));
+        assert_eq!(device_info.software_version, Some(1.0));
+        assert_eq!(device_info.to_string(), "Development FIT Cookbook");
+    }
+
+    #[test]
+    fn recording_device_ignores_sensors() {
+        let mut fp = File::open("./tests/fixtures/Activity.fit").unwrap();
+        let mut activity = Activity::from_reader(&mut fp).unwrap();
+        // A heart rate strap listed before the recording device
+        let mut sensor = FitDataRecord::new(MesgNum::DeviceInfo);
+        sensor.push(FitDataField::new(
+            "device_index".to_string(),
+            0,
+            Value::UInt8(1),
+            String::new(),
+        ));
+        sensor.push(FitDataField::new(
+            "software_version".to_string(),
+            5,
+            Value::Float64(4.2),
+            String::new(),
+        ));
+        sensor.push(FitDataField::new(
+            "product_name".to_string(),
+            27,
+            Value::String("HRM".to_string()),
+            String::new(),
+        ));
+        activity.records.insert(1, sensor);
+
+        let device_info = activity.device_info().unwrap();
+        assert_eq!(device_info.software_version, Some(1.0));
+        assert_eq!(device_info.to_string(), "Development FIT Cookbook");
+    }
+
+    #[test]
+    fn bogus_timestamps_are_dropped() {
+        let mut fp = File::open("./tests/fixtures/BogusTimestamps.fit").unwrap();
+        let activity = Activity::from_reader(&mut fp).unwrap();
+        let series = activity.record_series();
+
+        assert_eq!(
+            activity.start_time,
+            Some(Local.timestamp_opt(1_700_600_000, 0).unwrap())
+        );
+        assert_eq!(series.len(), 600);
+        assert!(series.iter().all(|point| point.power == Some(Power(200))));
+        assert_eq!(activity.sessions()[0].start_time, None);
+    }
 }
@@ -1,72 +1,542 @@
-use crate::activity::Activity;
-use crate::measurements::{AltitudeDiff, Average, HeartRate, Power, Speed, Work};
-use crate::metrics::{calc_altitude_changes, calc_normalized_power, calc_total_work, IF, TSS, VI};
+use crate::activity::{Activity, RecordPoint, MOVING_TIME_GAP_THRESHOLD};
+use crate::error::AnalyserError;
+use crate::measurements::{
+    AltitudeDiff, Average, Cadence, Distance, FractionalCadence, HeartRate, LeftRightBalance, Pace,
+    PedalSmoothness, Power, Speed, TorqueEffectiveness, Weight, Work,
+};
+use crate::metrics::{
+    altitude_from_grade, calc_altitude_changes_with_threshold, calc_coasting_percentage,
+    calc_force_velocity, calc_ngp, calc_normalized_power_with_window, calc_pacing,
+    calc_power_heart_rate_relation, calc_quadrant_analysis, calc_total_work, calc_xpower,
+    detect_intervals, estimate_ftp, estimate_ftp_from_peak, estimate_vo2max_from_peak,
+    heart_rate_zone_distribution, moving_average_speed, power_zone_distribution, HrTssModel,
+    Interval, Pacing, PowerHeartRateRelation, QuadrantAnalysis, TssUnavailableReason,
+    ALTITUDE_CHANGE_THRESHOLD, FTP_ESTIMATE_DURATION, HR_ZONE_COUNT, IF, MOVING_SPEED_THRESHOLD,
+    NP_WINDOW, POWER_ZONE_COUNT, RI, TSS, VI, VO2MAX_ESTIMATE_DURATION,
+};
 use crate::peak::Peak;
-use chrono::{DateTime, Duration, Local};
+use crate::zones::ZoneModel;
+use chrono::{DateTime, Days, Duration, Local, NaiveDate};
 use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
 
 /// Results of a full activity analysis
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ActivityAnalysis {
-    pub total_work: Work,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, with = "crate::serialization::option_duration_seconds")
+    )]
+    pub duration: Option<Duration>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub distance: Option<Distance>,
+    /// None if the activity has no power data at all
+    pub total_work: Option<Work>,
     pub normalized_power: Option<Power>,
     pub intensity_factor: Option<IF>,
+    pub xpower: Option<Power>,
+    pub relative_intensity: Option<RI>,
     pub variability_index: Option<VI>,
-    pub tss: Option<TSS>,
+    /// TSS, or the reason why it couldn't be calculated, see tss and try_tss
+    tss: Result<TSS, TssUnavailableReason>,
+    /// FTP the intensity metrics are based on, either measured or estimated
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub ftp: Option<Power>,
+    /// Intensity metrics, TSS and power zones are based on an FTP estimated from the activity,
+    /// as no FTP was measured
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub ftp_estimated: bool,
     pub hr_tss: Option<TSS>,
+    /// Fraction of the recorded time covered by power samples, None without power data
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub power_coverage: Option<f64>,
     pub average_power: Option<Power>,
     pub maximum_power: Option<Power>,
+    /// Percentage of power samples spent coasting (not pedaling)
+    pub coasting_percentage: Option<f64>,
+    /// Average (left %, right %) power balance
+    pub average_balance: Option<(u8, u8)>,
+    pub average_left_torque_effectiveness: Option<TorqueEffectiveness>,
+    pub average_right_torque_effectiveness: Option<TorqueEffectiveness>,
+    pub average_left_pedal_smoothness: Option<PedalSmoothness>,
+    pub average_right_pedal_smoothness: Option<PedalSmoothness>,
     pub average_heart_rate: Option<HeartRate>,
     pub maximum_heart_rate: Option<HeartRate>,
+    /// Correlation and linear fit of power to heart rate, for tracking aerobic efficiency over
+    /// time
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub power_heart_rate_relation: Option<PowerHeartRateRelation>,
     pub average_speed: Option<Speed>,
+    /// Average speed excluding the samples below MOVING_SPEED_THRESHOLD
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub moving_average_speed: Option<Speed>,
     pub maximum_speed: Option<Speed>,
+    /// Pace normalized for gradient and variability, only available for files with grade data
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub normalized_graded_pace: Option<Pace>,
+    /// Average cadence including the fractional part recorded by the device, zeros included
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub average_cadence: Option<FractionalCadence>,
+    /// Pacing by average power, or by average speed for activities without power
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub pacing: Option<Pacing>,
     pub elevation_gain: Option<AltitudeDiff>,
     pub elevation_loss: Option<AltitudeDiff>,
+    /// Elevation gain and loss are estimated from distance and grade, as the activity has no
+    /// altitude data
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub elevation_estimated: bool,
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "crate::serialization::option_duration_seconds_array")
+    )]
+    pub heart_rate_zones: Option<[Duration; HR_ZONE_COUNT]>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "crate::serialization::option_duration_seconds_array")
+    )]
+    pub power_zones: Option<[Duration; POWER_ZONE_COUNT]>,
     pub peak_performances: PeakPerformances,
+    /// Power samples with their timestamps, kept for detecting intervals
+    /// Not serialized, so empty in cached analyses.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub power_series: Vec<(Power, DateTime<Local>)>,
 }
 
 impl ActivityAnalysis {
-    /// Analyse an activity and create an ActivityAnalysis
+    /// Analyse an activity and create an ActivityAnalysis with all metrics computed
     pub fn from_activity(
         ftp: &Option<Power>,
         fthr: &Option<HeartRate>,
         activity: &Activity,
         peak_durations: &HashSet<Duration>,
     ) -> Self {
-        let power_data_with_timestamps = activity.get_data_with_timestamps("power");
+        ActivityAnalysisBuilder::new()
+            .ftp(*ftp)
+            .fthr(*fthr)
+            .peak_durations(peak_durations.clone())
+            .build(activity)
+    }
+
+    /// TSS of the activity, None if it couldn't be calculated
+    pub fn tss(&self) -> Option<TSS> {
+        self.tss.ok()
+    }
+
+    /// TSS of the activity, or the reason why it couldn't be calculated
+    pub fn try_tss(&self) -> Result<TSS, TssUnavailableReason> {
+        self.tss
+    }
+
+    /// Segment the power series into efforts at or above a threshold power and recovery between
+    /// them, see metrics::detect_intervals
+    pub fn detect_intervals(&self, threshold: Power, min_duration: Duration) -> Vec<Interval> {
+        let power_data = self
+            .power_series
+            .iter()
+            .map(|(power, timestamp)| (*power, timestamp))
+            .collect::<Vec<_>>();
+        detect_intervals(&power_data, threshold, min_duration)
+    }
+
+    /// Quadrant analysis of the power samples and the cadence samples with matching timestamps
+    /// The quadrants are split at the FTP of the analysis and the threshold cadence, the crank
+    /// length is in meters. None without FTP, or without samples both pedaling and with power.
+    pub fn quadrant_analysis(
+        &self,
+        cadence_data: &[(Cadence, &DateTime<Local>)],
+        threshold_cadence: Cadence,
+        crank_length: f64,
+    ) -> Option<QuadrantAnalysis> {
+        let power_data = self
+            .power_series
+            .iter()
+            .map(|(power, timestamp)| (*power, timestamp))
+            .collect::<Vec<_>>();
+        let force_velocity = calc_force_velocity(&power_data, cadence_data, crank_length);
+        calc_quadrant_analysis(
+            &force_velocity,
+            &self.ftp?,
+            &threshold_cadence,
+            crank_length,
+        )
+    }
+
+    /// Estimate VO2max in ml/kg/min from the 5 minute peak power and the weight of the athlete
+    /// The estimate is only as good as the effort: see estimate_vo2max_from_peak for the model
+    /// and its limitations. None if the 5 minute peak power wasn't computed.
+    pub fn estimate_vo2max(&self, weight: Weight) -> Option<f64> {
+        let peak = self
+            .peak_performances
+            .power
+            .get(&VO2MAX_ESTIMATE_DURATION)?;
+        estimate_vo2max_from_peak(peak.value, weight)
+    }
+
+    /// TSS blended from power TSS and hrTSS, for rides where the power meter dropped out partway
+    /// Both scores are assumed to be spread evenly over the activity: power TSS is weighted by
+    /// the fraction of time covered by power samples, hrTSS by the uncovered fraction. With full
+    /// power coverage, or without hrTSS, this is the power TSS, without power it is the hrTSS.
+    pub fn combined_tss(&self) -> Option<TSS> {
+        match (self.tss(), self.hr_tss, self.power_coverage) {
+            (Some(TSS(tss)), Some(TSS(hr_tss)), Some(coverage)) => {
+                let coverage = coverage.clamp(0.0, 1.0);
+                Some(TSS(
+                    (tss as f64 * coverage + hr_tss as f64 * (1.0 - coverage)).round() as i64,
+                ))
+            }
+            (tss, hr_tss, _) => tss.or(hr_tss),
+        }
+    }
+
+    /// Training stress per hour, so activities of different lengths can be compared
+    /// Based on the combined TSS, None if it or the duration is unknown.
+    pub fn intensity_density(&self) -> Option<f64> {
+        let TSS(tss) = self.combined_tss()?;
+        let hours = self.duration?.num_seconds() as f64 / 3600.0;
+        (hours > 0.0).then(|| tss as f64 / hours)
+    }
+
+    /// Training stress per kilometer, see intensity_density
+    pub fn intensity_density_per_km(&self) -> Option<f64> {
+        let TSS(tss) = self.combined_tss()?;
+        let Distance(distance) = self.distance?;
+        (distance > 0.0).then(|| tss as f64 / (distance / 1000.0))
+    }
+
+    /// Classify the ride by its Variability Index, coasting and climbing
+    /// Climbing is checked first, as long climbs and descents dominate the other metrics. Rides
+    /// without power data are Unknown, unless they qualify as a climb.
+    pub fn ride_type(&self) -> RideType {
+        let climbing_rate = match (self.elevation_gain, self.distance) {
+            (Some(AltitudeDiff(gain)), Some(Distance(distance))) if distance > 0.0 => {
+                Some(gain / (distance / 1000.0))
+            }
+            _ => None,
+        };
+        if climbing_rate.is_some_and(|rate| rate >= CLIMB_ELEVATION_GAIN_PER_KM) {
+            return RideType::Climb;
+        }
+
+        match self.variability_index {
+            Some(VI(vi)) if vi < INTERVALS_VI => RideType::Steady,
+            Some(_)
+                if self
+                    .coasting_percentage
+                    .is_some_and(|coasting| coasting >= STOCHASTIC_COASTING_PERCENTAGE) =>
+            {
+                RideType::Stochastic
+            }
+            Some(_) => RideType::Intervals,
+            None => RideType::Unknown,
+        }
+    }
+}
+
+/// Elevation gain in meters per km from which a ride is classified as a climb
+pub const CLIMB_ELEVATION_GAIN_PER_KM: f64 = 15.0;
+
+/// Variability Index from which a ride is not steady anymore
+pub const INTERVALS_VI: f64 = 1.1;
+
+/// Coasting percentage from which a variable ride is classified as stochastic (e.g. a group
+/// ride) instead of intervals, where the recoveries are usually pedaled through
+pub const STOCHASTIC_COASTING_PERCENTAGE: f64 = 15.0;
+
+/// Type of a ride, see ActivityAnalysis::ride_type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RideType {
+    Steady,
+    Intervals,
+    /// Frequent surges and coasting, e.g. a group ride or a race
+    Stochastic,
+    Climb,
+    /// Not enough data to classify the ride
+    Unknown,
+}
+
+impl Display for RideType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        let ride_type = match self {
+            RideType::Steady => "Steady",
+            RideType::Intervals => "Intervals",
+            RideType::Stochastic => "Group/Stochastic",
+            RideType::Climb => "Climb",
+            RideType::Unknown => "Unknown",
+        };
+        write!(f, "{}", ride_type)
+    }
+}
+
+/// Parse and analyse an activity file from memory
+/// This is the entry point for environments without filesystem or thread support, such as
+/// wasm32 targets: nothing on this path touches the filesystem or spawns threads. Gzip compressed
+/// files are accepted as well.
+pub fn analyze_bytes(
+    bytes: &[u8],
+    ftp: Option<Power>,
+    fthr: Option<HeartRate>,
+    peak_durations: &[Duration],
+) -> Result<ActivityAnalysis, AnalyserError> {
+    let activity = Activity::from_reader(&mut &bytes[..])?;
+    Ok(ActivityAnalysisBuilder::new()
+        .ftp(ftp)
+        .fthr(fthr)
+        .peak_durations(peak_durations.iter().copied().collect())
+        .build(&activity))
+}
+
+/// Parse an activity file from memory and calculate its TSS with a known FTP
+/// A minimal alternative to analyze_bytes, computing only Normalized Power and TSS. Ok(None) if
+/// the TSS can't be calculated, e.g. the activity has no power data.
+pub fn quick_tss(bytes: &[u8], ftp: Power) -> Result<Option<TSS>, AnalyserError> {
+    let activity = Activity::from_reader(&mut &bytes[..])?;
+    let power_data_with_timestamps = activity.get_data_with_timestamps::<Power>("power");
+    let normalized_power =
+        calc_normalized_power_with_window(&power_data_with_timestamps, NP_WINDOW);
+    Ok(TSS::try_calculate(
+        Some(&ftp),
+        activity.duration.as_ref(),
+        normalized_power.as_ref(),
+    )
+    .ok())
+}
+
+/// Builder of an ActivityAnalysis, computing only the selected metrics
+/// Everything is computed by default, metrics that are turned off are left empty.
+/// Power metrics (NP, IF, TSS, etc.) are always computed.
+#[derive(Debug, Clone)]
+pub struct ActivityAnalysisBuilder {
+    ftp: Option<Power>,
+    fthr: Option<HeartRate>,
+    peak_durations: HashSet<Duration>,
+    hr_tss_model: HrTssModel,
+    power_zone_model: ZoneModel,
+    peaks: bool,
+    zones: bool,
+    heart_rate: bool,
+    speed: bool,
+    cadence: bool,
+    pedaling_dynamics: bool,
+    elevation: bool,
+}
+
+impl Default for ActivityAnalysisBuilder {
+    fn default() -> Self {
+        Self {
+            ftp: None,
+            fthr: None,
+            peak_durations: HashSet::new(),
+            hr_tss_model: HrTssModel::default(),
+            power_zone_model: ZoneModel::coggan_power(),
+            peaks: true,
+            zones: true,
+            heart_rate: true,
+            speed: true,
+            cadence: true,
+            pedaling_dynamics: true,
+            elevation: true,
+        }
+    }
+}
+
+impl ActivityAnalysisBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Functional Threshold Power of the athlete at the time of the activity
+    pub fn ftp(mut self, ftp: Option<Power>) -> Self {
+        self.ftp = ftp;
+        self
+    }
+
+    /// Functional Threshold Heart Rate of the athlete at the time of the activity
+    pub fn fthr(mut self, fthr: Option<HeartRate>) -> Self {
+        self.fthr = fthr;
+        self
+    }
+
+    /// Durations to calculate peak performances for
+    pub fn peak_durations(mut self, peak_durations: HashSet<Duration>) -> Self {
+        self.peak_durations = peak_durations;
+        self
+    }
+
+    /// Heart rate zones and weights of hrTSS and the heart rate zone distribution
+    pub fn hr_tss_model(mut self, hr_tss_model: HrTssModel) -> Self {
+        self.hr_tss_model = hr_tss_model;
+        self
+    }
+
+    /// Power zones of the power zone distribution, Coggan zones by default
+    /// Zones beyond POWER_ZONE_COUNT are ignored.
+    pub fn power_zone_model(mut self, power_zone_model: ZoneModel) -> Self {
+        self.power_zone_model = power_zone_model;
+        self
+    }
+
+    /// Toggle peak performances of all measurement series
+    pub fn peaks(mut self, enabled: bool) -> Self {
+        self.peaks = enabled;
+        self
+    }
+
+    /// Toggle power and heart rate zone distributions
+    pub fn zones(mut self, enabled: bool) -> Self {
+        self.zones = enabled;
+        self
+    }
+
+    /// Toggle heart rate metrics (averages, hrTSS, zones and peaks)
+    pub fn heart_rate(mut self, enabled: bool) -> Self {
+        self.heart_rate = enabled;
+        self
+    }
+
+    /// Toggle speed metrics (averages and peaks)
+    pub fn speed(mut self, enabled: bool) -> Self {
+        self.speed = enabled;
+        self
+    }
+
+    /// Toggle cadence peaks
+    pub fn cadence(mut self, enabled: bool) -> Self {
+        self.cadence = enabled;
+        self
+    }
+
+    /// Toggle power balance, torque effectiveness and pedal smoothness
+    pub fn pedaling_dynamics(mut self, enabled: bool) -> Self {
+        self.pedaling_dynamics = enabled;
+        self
+    }
+
+    /// Toggle elevation gain and loss
+    pub fn elevation(mut self, enabled: bool) -> Self {
+        self.elevation = enabled;
+        self
+    }
+
+    /// Analyse an activity, computing the selected metrics
+    pub fn build(&self, activity: &Activity) -> ActivityAnalysis {
+        let fthr = &self.fthr;
+        let empty_peak_durations = HashSet::new();
+        let peak_durations = if self.peaks {
+            &self.peak_durations
+        } else {
+            &empty_peak_durations
+        };
+
+        // Common measurements are read in a single pass over the records
+        let series = activity.record_series();
+
+        let power_data_with_timestamps = series_if(true, &series, |point| point.power);
         let power_data = power_data_with_timestamps
             .iter()
             .map(|t| t.0)
             .collect::<Vec<_>>();
 
-        let heart_rate_data_with_timestamps = activity.get_data_with_timestamps("heart_rate");
+        let heart_rate_data_with_timestamps =
+            series_if(self.heart_rate, &series, |point| point.heart_rate);
         let heart_rate_data = heart_rate_data_with_timestamps
             .iter()
             .map(|t| t.0)
             .collect::<Vec<_>>();
 
-        let speed_data_with_timestamps = activity.get_data_with_timestamps("enhanced_speed");
+        let speed_data_with_timestamps = series_if(self.speed, &series, |point| point.speed);
         let speed_data = speed_data_with_timestamps
             .iter()
             .map(|t| t.0)
             .collect::<Vec<_>>();
 
-        let altitude_data = activity.get_data("altitude");
+        let cadence_data_with_timestamps = series_if(self.cadence, &series, |point| point.cadence);
+
+        let altitude_data = series_if(self.elevation, &series, |point| point.altitude)
+            .into_iter()
+            .map(|t| t.0)
+            .collect::<Vec<_>>();
+        // Files without altitude may still have grade, the altitude is reconstructed from that
+        let (altitude_data, elevation_estimated) = if altitude_data.is_empty() {
+            let distance_grade_data = series_if(self.elevation, &series, |point| {
+                Some((point.distance?, point.grade?))
+            })
+            .into_iter()
+            .map(|t| t.0)
+            .collect::<Vec<_>>();
+            let altitude_data = altitude_from_grade(&distance_grade_data);
+            let elevation_estimated = !altitude_data.is_empty();
+            (altitude_data, elevation_estimated)
+        } else {
+            (altitude_data, false)
+        };
 
+        let power_coverage = power_coverage(&series, &power_data_with_timestamps);
         let average_power = Average::average(&power_data);
         let maximum_power = power_data.iter().max().copied();
+        let coasting_percentage = calc_coasting_percentage(&power_data);
+        let average_balance = average_if(self.pedaling_dynamics, &series, |point| {
+            point.left_right_balance
+        })
+        .map(|balance: LeftRightBalance| (balance.left(), balance.right()));
+
+        let average_left_torque_effectiveness =
+            average_if(self.pedaling_dynamics, &series, |point| {
+                point.left_torque_effectiveness
+            });
+        let average_right_torque_effectiveness =
+            average_if(self.pedaling_dynamics, &series, |point| {
+                point.right_torque_effectiveness
+            });
+        let average_left_pedal_smoothness = average_if(self.pedaling_dynamics, &series, |point| {
+            point.left_pedal_smoothness
+        });
+        let average_right_pedal_smoothness = average_if(self.pedaling_dynamics, &series, |point| {
+            point.right_pedal_smoothness
+        });
 
         let average_heart_rate = Average::average(&heart_rate_data);
         let maximum_heart_rate = heart_rate_data.iter().max().copied();
+        let power_heart_rate_relation = calc_power_heart_rate_relation(
+            &power_data_with_timestamps,
+            &heart_rate_data_with_timestamps,
+        );
 
         let average_speed = Average::average(&speed_data);
+        let moving_average_speed = moving_average_speed(&speed_data, MOVING_SPEED_THRESHOLD);
         let maximum_speed = speed_data
             .iter()
             .max_by(|Speed(x), Speed(y)| x.total_cmp(y))
             .copied();
+        let grade_data_with_timestamps = series_if(self.speed, &series, |point| point.grade);
+        let normalized_graded_pace =
+            calc_ngp(&speed_data_with_timestamps, &grade_data_with_timestamps);
 
-        let total_work = calc_total_work(&power_data);
-        let normalized_power = calc_normalized_power(&power_data);
+        let average_cadence = average_if(self.cadence, &series, |point| point.fractional_cadence);
+
+        let pacing = if power_data_with_timestamps.is_empty() {
+            calc_pacing(&speed_data_with_timestamps)
+        } else {
+            calc_pacing(&power_data_with_timestamps)
+        };
+
+        let estimated_ftp = match self.ftp {
+            Some(_) => None,
+            None => estimate_ftp(&power_data_with_timestamps),
+        };
+        let ftp_estimated = estimated_ftp.is_some();
+        let ftp = &self.ftp.or(estimated_ftp);
+
+        let total_work = (!power_data.is_empty()).then(|| calc_total_work(&power_data));
+        let normalized_power =
+            calc_normalized_power_with_window(&power_data_with_timestamps, NP_WINDOW);
+        let xpower = calc_xpower(&power_data_with_timestamps);
+        let relative_intensity = match (ftp, xpower) {
+            (Some(ftp), Some(xpower)) => Some(RI::calculate(ftp, &xpower)),
+            _ => None,
+        };
         let intensity_factor = match (ftp, normalized_power) {
             (Some(ftp), Some(normalized_power)) => Some(IF::calculate(ftp, &normalized_power)),
             _ => None,
@@ -77,48 +547,157 @@ impl ActivityAnalysis {
             }
             _ => None,
         };
-        let tss = match (ftp, &activity.duration, &normalized_power) {
-            (Some(ftp), Some(duration), Some(normalized_power)) => {
-                Some(TSS::calculate(ftp, duration, normalized_power))
-            }
-            _ => None,
-        };
-        let hr_tss = fthr.map(|fthr| TSS::calculate_hr_tss(&fthr, &heart_rate_data));
-        let (elevation_gain, elevation_loss) = calc_altitude_changes(&altitude_data);
+        let tss = TSS::try_calculate(
+            ftp.as_ref(),
+            activity.duration.as_ref(),
+            normalized_power.as_ref(),
+        );
+        let hr_tss_model = &self.hr_tss_model;
+        let hr_tss = fthr
+            .filter(|_| self.heart_rate)
+            .map(|fthr| TSS::calculate_hr_tss(&fthr, &heart_rate_data, hr_tss_model));
+        let heart_rate_zones = fthr
+            .filter(|_| self.heart_rate && self.zones)
+            .map(|fthr| heart_rate_zone_distribution(&fthr, &heart_rate_data, hr_tss_model));
+        let power_zones = ftp
+            .filter(|_| self.zones)
+            .map(|ftp| power_zone_distribution(&ftp, &power_data, &self.power_zone_model));
+        let (elevation_gain, elevation_loss) =
+            calc_altitude_changes_with_threshold(&altitude_data, ALTITUDE_CHANGE_THRESHOLD);
 
         let peak_performances = PeakPerformances::from_data(
             &power_data_with_timestamps,
             &heart_rate_data_with_timestamps,
             &speed_data_with_timestamps,
+            &cadence_data_with_timestamps,
             peak_durations,
         );
 
-        Self {
+        ActivityAnalysis {
+            duration: activity.duration,
+            distance: activity.distance(),
             total_work,
             normalized_power,
             intensity_factor,
+            xpower,
+            relative_intensity,
             variability_index,
             tss,
+            ftp: *ftp,
+            ftp_estimated,
             hr_tss,
+            power_coverage,
             average_power,
             maximum_power,
+            coasting_percentage,
+            average_balance,
+            average_left_torque_effectiveness,
+            average_right_torque_effectiveness,
+            average_left_pedal_smoothness,
+            average_right_pedal_smoothness,
             average_heart_rate,
             maximum_heart_rate,
+            power_heart_rate_relation,
             average_speed,
+            moving_average_speed,
             maximum_speed,
+            normalized_graded_pace,
+            average_cadence,
+            pacing,
             elevation_gain,
             elevation_loss,
+            elevation_estimated,
+            heart_rate_zones,
+            power_zones,
             peak_performances,
+            power_series: power_data_with_timestamps
+                .iter()
+                .map(|(power, timestamp)| (*power, **timestamp))
+                .collect(),
         }
     }
 }
 
+/// Get a measurement of the record series with timestamps, or no data if the metric is disabled
+fn series_if<T>(
+    enabled: bool,
+    series: &[RecordPoint],
+    measurement: impl Fn(&RecordPoint) -> Option<T>,
+) -> Vec<(T, &DateTime<Local>)> {
+    if enabled {
+        series
+            .iter()
+            .filter_map(|point| Some((measurement(point)?, &point.timestamp)))
+            .collect()
+    } else {
+        Vec::new()
+    }
+}
+
+/// Average a measurement of the record series, or None if the metric is disabled
+fn average_if<T>(
+    enabled: bool,
+    series: &[RecordPoint],
+    measurement: impl Fn(&RecordPoint) -> Option<T>,
+) -> Option<T>
+where
+    T: Average,
+{
+    Average::average(
+        series_if(enabled, series, measurement)
+            .into_iter()
+            .map(|t| t.0)
+            .collect::<Vec<_>>(),
+    )
+}
+
+/// Fraction of the recorded time covered by power samples
+/// Gaps between samples longer than MOVING_TIME_GAP_THRESHOLD (e.g. pauses or power meter
+/// dropouts) are not covered.
+fn power_coverage(series: &[RecordPoint], power_data: &[(Power, &DateTime<Local>)]) -> Option<f64> {
+    if power_data.is_empty() {
+        return None;
+    }
+    let recorded_time = covered_time(series.iter().map(|point| &point.timestamp));
+    let power_time = covered_time(power_data.iter().map(|(_, timestamp)| *timestamp));
+    (recorded_time > Duration::zero())
+        .then(|| power_time.num_milliseconds() as f64 / recorded_time.num_milliseconds() as f64)
+}
+
+/// Sum of the intervals between consecutive timestamps up to MOVING_TIME_GAP_THRESHOLD
+fn covered_time<'a>(timestamps: impl Iterator<Item = &'a DateTime<Local>>) -> Duration {
+    let timestamps = timestamps.collect::<Vec<_>>();
+    timestamps
+        .windows(2)
+        .map(|pair| *pair[1] - *pair[0])
+        .filter(|interval| *interval > Duration::zero() && *interval <= MOVING_TIME_GAP_THRESHOLD)
+        .sum()
+}
+
 /// Highest performance values achieved for certain time durations
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PeakPerformances {
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "crate::serialization::duration_seconds_map")
+    )]
     pub power: HashMap<Duration, Peak<Power>>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "crate::serialization::duration_seconds_map")
+    )]
     pub heart_rate: HashMap<Duration, Peak<HeartRate>>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "crate::serialization::duration_seconds_map")
+    )]
     pub speed: HashMap<Duration, Peak<Speed>>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "crate::serialization::duration_seconds_map", default)
+    )]
+    pub cadence: HashMap<Duration, Peak<Cadence>>,
 }
 
 impl PeakPerformances {
@@ -127,31 +706,555 @@ impl PeakPerformances {
         power_data: &[(Power, &DateTime<Local>)],
         heart_rate_data: &[(HeartRate, &DateTime<Local>)],
         speed_data: &[(Speed, &DateTime<Local>)],
+        cadence_data: &[(Cadence, &DateTime<Local>)],
         peak_durations: &HashSet<Duration>,
     ) -> Self {
         Self {
-            power: Self::get_one(power_data, peak_durations),
-            heart_rate: Self::get_one(heart_rate_data, peak_durations),
-            speed: Self::get_one(speed_data, peak_durations),
+            power: peaks_for(power_data, peak_durations),
+            heart_rate: peaks_for(heart_rate_data, peak_durations),
+            speed: peaks_for(speed_data, peak_durations),
+            cadence: peaks_for(cadence_data, peak_durations),
         }
     }
+}
 
-    /// Calculate performances for a specific measurment type
-    fn get_one<T>(
-        data_with_timestamps: &[(T, &DateTime<Local>)],
-        peak_durations: &HashSet<Duration>,
-    ) -> HashMap<Duration, Peak<T>>
-    where
-        T: Ord + Average + Copy,
-    {
-        peak_durations
+/// Calculate peaks of any measurement type for each of the given durations
+/// Durations longer than the data series are omitted.
+pub fn peaks_for<T>(
+    data_with_timestamps: &[(T, &DateTime<Local>)],
+    peak_durations: &HashSet<Duration>,
+) -> HashMap<Duration, Peak<T>>
+where
+    T: Ord + Average + Copy,
+{
+    peak_durations
+        .iter()
+        .filter_map(|duration| {
+            Some((
+                *duration,
+                Peak::from_measurement_records(data_with_timestamps, *duration)?,
+            ))
+        })
+        .collect()
+}
+
+/// Calculate the best power of each duration across multiple activities
+/// Durations without a peak in any of the activities are omitted.
+pub fn season_power_curve<'a, I>(analyses: I, durations: &[Duration]) -> Vec<(Duration, Power)>
+where
+    I: IntoIterator<Item = &'a ActivityAnalysis>,
+{
+    let best_powers = analyses.into_iter().fold(
+        HashMap::new(),
+        |mut acc: HashMap<Duration, Power>, analysis| {
+            analysis
+                .peak_performances
+                .power
+                .iter()
+                .for_each(|(duration, peak)| {
+                    acc.entry(*duration)
+                        .and_modify(|power| {
+                            if *power < peak.value {
+                                *power = peak.value
+                            }
+                        })
+                        .or_insert(peak.value);
+                });
+            acc
+        },
+    );
+
+    durations
+        .iter()
+        .filter_map(|duration| Some((*duration, *best_powers.get(duration)?)))
+        .collect()
+}
+
+/// Track estimated FTP (eFTP) over a season, without formal FTP tests
+/// For each date with an activity, eFTP is estimated from the best 20 minute peak power of the
+/// activities within the trailing window of `window_days` days, ending on that date. Activities
+/// without a 20 minute peak (e.g. when it wasn't computed) are ignored. Dates are in ascending order.
+pub fn eftp_history<'a, I>(analyses: I, window_days: u64) -> Vec<(NaiveDate, Power)>
+where
+    I: IntoIterator<Item = (NaiveDate, &'a ActivityAnalysis)>,
+{
+    let mut peaks = analyses
+        .into_iter()
+        .filter_map(|(date, analysis)| {
+            let peak = analysis
+                .peak_performances
+                .power
+                .get(&FTP_ESTIMATE_DURATION)?;
+            Some((date, peak.value))
+        })
+        .collect::<Vec<_>>();
+    peaks.sort_by_key(|(date, _)| *date);
+
+    let mut dates = peaks.iter().map(|(date, _)| *date).collect::<Vec<_>>();
+    dates.dedup();
+    dates
+        .into_iter()
+        .filter_map(|date| {
+            let window_start = date - Days::new(window_days);
+            let best_peak = peaks
+                .iter()
+                .filter(|(peak_date, _)| window_start < *peak_date && *peak_date <= date)
+                .map(|(_, power)| *power)
+                .max()?;
+            Some((date, estimate_ftp_from_peak(best_peak)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod activity_analysis_tests {
+    use super::*;
+    use crate::activity::SampleBounds;
+    use crate::athlete::MeasurementRecords;
+    use crate::metrics::IntervalKind;
+    use assertables::{assert_in_delta, assert_in_delta_as_result};
+    use chrono::Utc;
+    use std::fs::File;
+
+    fn analyse_fixture(path: &str, peak_durations: &HashSet<Duration>) -> ActivityAnalysis {
+        let mut fp = File::open(path).unwrap();
+        let activity = Activity::from_reader(&mut fp).unwrap();
+        ActivityAnalysis::from_activity(&None, &None, &activity, peak_durations)
+    }
+
+    #[test]
+    fn pedaling_dynamics() {
+        let analysis = analyse_fixture("./tests/fixtures/PedalingDynamics.fit", &HashSet::new());
+
+        assert_eq!(analysis.average_balance, Some((48, 52)));
+        assert_eq!(
+            analysis.average_left_torque_effectiveness,
+            Some(TorqueEffectiveness(70.0))
+        );
+        assert_eq!(
+            analysis.average_right_torque_effectiveness,
+            Some(TorqueEffectiveness(72.0))
+        );
+        assert_eq!(
+            analysis.average_left_pedal_smoothness,
+            Some(PedalSmoothness(20.0))
+        );
+        assert_eq!(
+            analysis.average_right_pedal_smoothness,
+            Some(PedalSmoothness(22.5))
+        );
+    }
+
+    #[test]
+    fn missing_pedaling_dynamics() {
+        let analysis = analyse_fixture("./tests/fixtures/Activity.fit", &HashSet::new());
+
+        assert_eq!(analysis.average_balance, None);
+        assert_eq!(analysis.average_left_torque_effectiveness, None);
+        assert_eq!(analysis.average_right_pedal_smoothness, None);
+    }
+
+    #[test]
+    fn cadence_peaks() {
+        let peak_durations = HashSet::from([Duration::minutes(1), Duration::hours(2)]);
+        let analysis = analyse_fixture("./tests/fixtures/Activity.fit", &peak_durations);
+
+        let cadence_peaks = &analysis.peak_performances.cadence;
+        assert_eq!(cadence_peaks.len(), 1);
+        assert_eq!(cadence_peaks[&Duration::minutes(1)].value, Cadence(224));
+    }
+
+    #[test]
+    fn spikes_are_cleaned() {
+        let mut fp = File::open("./tests/fixtures/Spikes.fit").unwrap();
+        let mut activity = Activity::from_reader(&mut fp).unwrap();
+        let peak_durations = HashSet::from([Duration::seconds(5)]);
+
+        let analysis = ActivityAnalysis::from_activity(&None, &None, &activity, &peak_durations);
+        assert_eq!(analysis.maximum_power, Some(Power(4000)));
+        assert_eq!(analysis.maximum_heart_rate, Some(HeartRate(250)));
+
+        activity.clean(&SampleBounds::default());
+        let analysis = ActivityAnalysis::from_activity(&None, &None, &activity, &peak_durations);
+        assert_eq!(analysis.maximum_power, Some(Power(200)));
+        assert_eq!(analysis.maximum_heart_rate, Some(HeartRate(140)));
+        assert_eq!(analysis.maximum_speed, Some(Speed(8.0)));
+        assert!(analysis.moving_average_speed >= analysis.average_speed);
+        assert_eq!(
+            analysis.peak_performances.power[&Duration::seconds(5)].value,
+            Power(200)
+        );
+    }
+
+    #[test]
+    fn builder_skips_disabled_metrics() {
+        let mut fp = File::open("./tests/fixtures/Activity.fit").unwrap();
+        let activity = Activity::from_reader(&mut fp).unwrap();
+
+        let analysis = ActivityAnalysisBuilder::new()
+            .ftp(Some(Power(260)))
+            .fthr(Some(HeartRate(178)))
+            .peak_durations(HashSet::from([Duration::minutes(1)]))
+            .peaks(false)
+            .heart_rate(false)
+            .build(&activity);
+
+        assert!(analysis.tss().is_some());
+        assert!(analysis.power_zones.is_some());
+        assert!(analysis.average_speed.is_some());
+        assert_eq!(analysis.hr_tss, None);
+        assert_eq!(analysis.average_heart_rate, None);
+        assert!(analysis.heart_rate_zones.is_none());
+        assert!(analysis.peak_performances.power.is_empty());
+    }
+
+    #[test]
+    fn builder_uses_hr_tss_model() {
+        let mut fp = File::open("./tests/fixtures/Activity.fit").unwrap();
+        let activity = Activity::from_reader(&mut fp).unwrap();
+        let builder = ActivityAnalysisBuilder::new().fthr(Some(HeartRate(178)));
+
+        let default_hr_tss = builder.clone().build(&activity).hr_tss.unwrap();
+        let hr_tss_model = HrTssModel {
+            zone_weights: HrTssModel::default()
+                .zone_weights
+                .map(|weight| weight * 2.0),
+            ..HrTssModel::default()
+        };
+        let hr_tss = builder
+            .hr_tss_model(hr_tss_model)
+            .build(&activity)
+            .hr_tss
+            .unwrap();
+        assert_in_delta!(hr_tss.0, default_hr_tss.0 * 2, 1);
+    }
+
+    #[test]
+    fn builder_uses_power_zone_model() {
+        let mut fp = File::open("./tests/fixtures/Activity.fit").unwrap();
+        let activity = Activity::from_reader(&mut fp).unwrap();
+        // Everything below FTP in the first zone, everything above in the second one
+        let power_zones = ActivityAnalysisBuilder::new()
+            .ftp(Some(Power(260)))
+            .power_zone_model(ZoneModel::contiguous([("Below", 0.0), ("Above", 1.0)]))
+            .build(&activity)
+            .power_zones
+            .unwrap();
+
+        let power_data = activity.get_data::<Power>("power");
+        let above = power_data
             .iter()
-            .filter_map(|duration| {
-                Some((
-                    *duration,
-                    Peak::from_measurement_records(data_with_timestamps, *duration)?,
-                ))
-            })
-            .collect()
+            .filter(|power| **power >= Power(260))
+            .count();
+        assert_eq!(
+            power_zones[0],
+            Duration::seconds((power_data.len() - above) as i64)
+        );
+        assert_eq!(power_zones[1], Duration::seconds(above as i64));
+        assert!(power_zones[2..]
+            .iter()
+            .all(|duration| *duration == Duration::zero()));
+    }
+
+    /// Benchmark of a full analysis, run with `cargo test --release -- --ignored --nocapture`
+    /// Activity.fit is scaled up to ~10 hours by repeating its records.
+    #[test]
+    #[ignore]
+    fn build_benchmark() {
+        let mut fp = File::open("./tests/fixtures/Activity.fit").unwrap();
+        let mut activity = Activity::from_reader(&mut fp).unwrap();
+        let records = activity.records.clone();
+        let scale = (Duration::hours(10).num_seconds() as usize).div_ceil(records.len());
+        activity.records = (0..scale).flat_map(|_| records.clone()).collect();
+
+        let builder = ActivityAnalysisBuilder::new()
+            .ftp(Some(Power(260)))
+            .fthr(Some(HeartRate(178)))
+            .peaks(false);
+        let start = std::time::Instant::now();
+        let analysis = builder.build(&activity);
+        println!(
+            "Analysed {} records in {:?}",
+            activity.records.len(),
+            start.elapsed()
+        );
+        assert!(analysis.average_power.is_some());
+    }
+
+    #[test]
+    fn estimated_ftp_without_measurement() {
+        let analysis = analyse_fixture("./tests/fixtures/Activity.fit", &HashSet::new());
+        assert!(analysis.ftp_estimated);
+        assert!(analysis.intensity_factor.is_some());
+        assert!(analysis.tss().is_some());
+
+        let analysis = analyse_fixture("./tests/fixtures/Spikes.fit", &HashSet::new());
+        assert!(!analysis.ftp_estimated);
+        assert_eq!(analysis.tss(), None);
+    }
+
+    #[test]
+    fn analyze_from_bytes() {
+        let bytes = include_bytes!("../tests/fixtures/PedalingDynamics.fit.gz");
+        let analysis =
+            analyze_bytes(bytes, Some(Power(250)), None, &[Duration::seconds(5)]).unwrap();
+
+        assert_eq!(analysis.average_balance, Some((48, 52)));
+        assert!(!analysis.ftp_estimated);
+        assert_eq!(analysis.peak_performances.power.len(), 1);
+
+        assert!(analyze_bytes(b"not a fit file", None, None, &[]).is_err());
+    }
+
+    #[test]
+    fn running_power_uses_running_ftp() {
+        let mut fp = File::open("./tests/fixtures/RunningPower.fit").unwrap();
+        let activity = Activity::from_reader(&mut fp).unwrap();
+        let measurements = MeasurementRecords::from_csv_reader(
+            "2023-01-01,ftp,300\n2023-01-01,running_ftp,250\n".as_bytes(),
+        )
+        .unwrap();
+        let date = activity.start_date(&Utc).unwrap();
+        let ftp =
+            measurements.get_actual_ftp_for_sport(&date, activity.sport().map(String::as_str));
+        let analysis = ActivityAnalysisBuilder::new().ftp(ftp).build(&activity);
+
+        assert_eq!(ftp, Some(Power(250)));
+        assert_eq!(analysis.intensity_factor, Some(IF(1.0)));
+        assert_eq!(analysis.tss(), Some(TSS(50)));
+    }
+
+    #[test]
+    fn quick_tss_from_bytes() {
+        let bytes = include_bytes!("../tests/fixtures/Activity.fit");
+        assert_eq!(quick_tss(bytes, Power(260)).unwrap(), Some(TSS(67)));
+
+        let bytes = include_bytes!("../tests/fixtures/Swim.fit");
+        assert_eq!(quick_tss(bytes, Power(260)).unwrap(), None);
+
+        assert!(quick_tss(b"not a fit file", Power(260)).is_err());
+    }
+
+    #[test]
+    fn activity_without_power() {
+        let analysis = analyse_fixture("./tests/fixtures/Swim.fit", &HashSet::new());
+
+        assert_eq!(analysis.total_work, None);
+        assert_eq!(analysis.normalized_power, None);
+        assert_eq!(analysis.average_power, None);
+        assert!(analysis.average_heart_rate.is_some());
+        assert_eq!(analysis.power_heart_rate_relation, None);
+
+        let analysis = analyse_fixture("./tests/fixtures/Activity.fit", &HashSet::new());
+        assert!(analysis.total_work.is_some());
+        assert!(analysis.power_heart_rate_relation.is_some());
+    }
+
+    #[test]
+    fn tss_unavailable_reasons() {
+        let analysis = analyse_fixture("./tests/fixtures/Activity.fit", &HashSet::new());
+        assert_eq!(analysis.try_tss(), Ok(analysis.tss().unwrap()));
+
+        let analysis = analyse_fixture("./tests/fixtures/Swim.fit", &HashSet::new());
+        assert_eq!(analysis.try_tss(), Err(TssUnavailableReason::MissingPower));
+
+        let analysis = analyse_fixture("./tests/fixtures/Spikes.fit", &HashSet::new());
+        assert_eq!(analysis.try_tss(), Err(TssUnavailableReason::MissingFtp));
+    }
+
+    #[test]
+    fn intensity_densities() {
+        let mut analysis = analyse_fixture("./tests/fixtures/Activity.fit", &HashSet::new());
+        analysis.tss = Ok(TSS(75));
+        analysis.hr_tss = None;
+        analysis.duration = Some(Duration::minutes(90));
+        analysis.distance = Some(Distance(50000.0));
+
+        assert_eq!(analysis.intensity_density(), Some(50.0));
+        assert_eq!(analysis.intensity_density_per_km(), Some(1.5));
+
+        analysis.duration = Some(Duration::zero());
+        analysis.distance = None;
+        assert_eq!(analysis.intensity_density(), None);
+        assert_eq!(analysis.intensity_density_per_km(), None);
+    }
+
+    #[test]
+    fn ride_types() {
+        let mut analysis = analyse_fixture("./tests/fixtures/Activity.fit", &HashSet::new());
+        analysis.distance = Some(Distance(20000.0));
+        analysis.elevation_gain = Some(AltitudeDiff(100.0));
+        analysis.coasting_percentage = Some(5.0);
+
+        analysis.variability_index = Some(VI(1.03));
+        assert_eq!(analysis.ride_type(), RideType::Steady);
+
+        analysis.variability_index = Some(VI(1.2));
+        assert_eq!(analysis.ride_type(), RideType::Intervals);
+
+        analysis.coasting_percentage = Some(20.0);
+        assert_eq!(analysis.ride_type(), RideType::Stochastic);
+
+        analysis.elevation_gain = Some(AltitudeDiff(400.0));
+        assert_eq!(analysis.ride_type(), RideType::Climb);
+
+        let analysis = analyse_fixture("./tests/fixtures/Swim.fit", &HashSet::new());
+        assert_eq!(analysis.ride_type(), RideType::Unknown);
+    }
+
+    #[test]
+    fn blended_tss() {
+        let mut analysis = analyse_fixture("./tests/fixtures/Activity.fit", &HashSet::new());
+        assert_in_delta!(analysis.power_coverage.unwrap(), 1.0, 0.01);
+
+        analysis.tss = Ok(TSS(100));
+        analysis.hr_tss = Some(TSS(60));
+        analysis.power_coverage = Some(0.75);
+        assert_eq!(analysis.combined_tss(), Some(TSS(90)));
+
+        analysis.power_coverage = Some(1.0);
+        assert_eq!(analysis.combined_tss(), Some(TSS(100)));
+
+        analysis.tss = Err(TssUnavailableReason::MissingPower);
+        assert_eq!(analysis.combined_tss(), Some(TSS(60)));
+
+        let analysis = analyse_fixture("./tests/fixtures/Swim.fit", &HashSet::new());
+        assert_eq!(analysis.power_coverage, None);
+    }
+
+    #[test]
+    fn vo2max_from_five_minute_peak() {
+        let analysis = analyse_fixture(
+            "./tests/fixtures/Activity.fit",
+            &HashSet::from([VO2MAX_ESTIMATE_DURATION]),
+        );
+        let Power(peak) = analysis.peak_performances.power[&VO2MAX_ESTIMATE_DURATION].value;
+
+        assert_in_delta!(
+            analysis.estimate_vo2max(Weight(70.0)).unwrap(),
+            16.6 + 8.87 * peak as f64 / 70.0,
+            0.001
+        );
+        assert_eq!(analysis.estimate_vo2max(Weight(0.0)), None);
+
+        let analysis = analyse_fixture("./tests/fixtures/Activity.fit", &HashSet::new());
+        assert_eq!(analysis.estimate_vo2max(Weight(70.0)), None);
+    }
+
+    #[test]
+    fn elevation_from_grade() {
+        let analysis = analyse_fixture("./tests/fixtures/GradeOnly.fit", &HashSet::new());
+
+        assert!(analysis.elevation_estimated);
+        // 24.75 m up at 5% and 14.85 m down at 3%, changes are only counted once they reach the
+        // altitude change threshold
+        assert_in_delta!(analysis.elevation_gain.unwrap().0, 24.0, 0.01);
+        assert_in_delta!(analysis.elevation_loss.unwrap().0, 12.6, 0.01);
+
+        let analysis = analyse_fixture("./tests/fixtures/Activity.fit", &HashSet::new());
+        assert!(!analysis.elevation_estimated);
+    }
+
+    #[test]
+    fn detect_intervals_of_analysis() {
+        let analysis = analyse_fixture("./tests/fixtures/Activity.fit", &HashSet::new());
+
+        // Every sample is above a zero threshold, giving a single effort over the whole ride
+        let intervals = analysis.detect_intervals(Power(0), Duration::zero());
+        assert_eq!(intervals.len(), 1);
+        assert_eq!(intervals[0].kind, IntervalKind::On);
+        assert_eq!(intervals[0].start, analysis.power_series[0].1);
+        assert_eq!(intervals[0].average_power, analysis.average_power.unwrap());
+    }
+
+    #[test]
+    fn quadrant_analysis_of_analysis() {
+        let mut fp = File::open("./tests/fixtures/Activity.fit").unwrap();
+        let activity = Activity::from_reader(&mut fp).unwrap();
+        let cadence_data = activity.get_data_with_timestamps::<Cadence>("cadence");
+
+        let analysis = ActivityAnalysis::from_activity(&None, &None, &activity, &HashSet::new());
+        let quadrants = analysis
+            .quadrant_analysis(&cadence_data, Cadence(90), 0.1725)
+            .unwrap();
+        assert_in_delta!(
+            quadrants.q1 + quadrants.q2 + quadrants.q3 + quadrants.q4,
+            1.0,
+            1e-9
+        );
+
+        let analysis = ActivityAnalysis {
+            ftp: None,
+            ..analysis
+        };
+        assert_eq!(
+            analysis.quadrant_analysis(&cadence_data, Cadence(90), 0.1725),
+            None
+        );
+    }
+
+    #[test]
+    fn normalized_graded_pace_needs_grade() {
+        let analysis = analyse_fixture("./tests/fixtures/GradeOnly.fit", &HashSet::new());
+        // Climbing at 5% costs more than descending at 3% saves, so the graded pace is faster
+        let Pace(ngp) = analysis.normalized_graded_pace.unwrap();
+        let Pace(average_pace) = Pace::from_speed(analysis.average_speed.unwrap()).unwrap();
+        assert!(ngp < average_pace);
+
+        let analysis = analyse_fixture("./tests/fixtures/Activity.fit", &HashSet::new());
+        assert_eq!(analysis.normalized_graded_pace, None);
+    }
+
+    #[test]
+    fn season_power_curve_takes_best_peaks() {
+        let durations = [
+            Duration::seconds(5),
+            Duration::minutes(20),
+            Duration::hours(2),
+        ];
+        let peak_durations = HashSet::from(durations);
+        let analyses = [
+            analyse_fixture("./tests/fixtures/Activity.fit", &peak_durations),
+            analyse_fixture("./tests/fixtures/WithGearChangeData.fit", &peak_durations),
+        ];
+
+        let curve = season_power_curve(&analyses, &durations);
+
+        assert_eq!(
+            curve,
+            vec![
+                (Duration::seconds(5), Power(327)),
+                (Duration::minutes(20), Power(203)),
+            ]
+        );
+    }
+
+    #[test]
+    fn eftp_over_a_season() {
+        let peak_durations = HashSet::from([FTP_ESTIMATE_DURATION]);
+        let activity = analyse_fixture("./tests/fixtures/Activity.fit", &peak_durations);
+        let gear_change =
+            analyse_fixture("./tests/fixtures/WithGearChangeData.fit", &peak_durations);
+        let short = analyse_fixture("./tests/fixtures/Spikes.fit", &peak_durations);
+        let day = |days| NaiveDate::from_ymd_opt(2023, 1, 1).unwrap() + Days::new(days);
+
+        let history = eftp_history(
+            [
+                (day(50), &gear_change),
+                (day(0), &activity),
+                (day(10), &gear_change),
+                (day(20), &short),
+            ],
+            42,
+        );
+
+        assert_eq!(
+            history,
+            vec![
+                (day(0), Power(192)),
+                (day(10), Power(192)),
+                (day(50), Power(133))
+            ]
+        );
     }
 }
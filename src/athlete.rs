@@ -1,7 +1,13 @@
+use crate::error::AnalyserError;
 use crate::measurements::{HeartRate, Power, Weight};
 use chrono::NaiveDate;
+use std::io::Read;
+
+/// FTHr as a fraction of the maximum heart rate, used when only the latter is known
+pub const FTHR_FRACTION_OF_MAX_HR: f64 = 0.9;
 
 /// A sorted vector including all previous measurement data of an athlete
+#[derive(Default)]
 pub struct MeasurementRecords(Vec<(NaiveDate, MeasurementRecord)>);
 
 impl MeasurementRecords {
@@ -11,20 +17,127 @@ impl MeasurementRecords {
         T: AsMut<[(NaiveDate, MeasurementRecord)]>,
     {
         let measurements = measurements.as_mut();
-        measurements.sort_by(|(a, _), (b, _)| a.cmp(b));
+        measurements.sort_by_key(|(date, _)| *date);
         Self(measurements.to_vec())
     }
 
+    /// Create an empty list of measurements, to be filled with `push`
+    pub fn empty() -> Self {
+        Self(Vec::new())
+    }
+
+    /// Add a measurement, keeping the list sorted
+    /// Measurements of the same date keep their insertion order.
+    pub fn push(&mut self, date: NaiveDate, measurement: MeasurementRecord) {
+        let MeasurementRecords(measurements) = self;
+        let index = measurements.partition_point(|(d, _)| *d <= date);
+        measurements.insert(index, (date, measurement));
+    }
+
+    /// Read measurements from CSV rows of `date,type,value`, where type is one of ftp,
+    /// running_ftp, fthr, max_hr or weight
+    /// Dates are formatted as YYYY-MM-DD. An optional header row, empty lines and lines starting
+    /// with # are skipped.
+    pub fn from_csv_reader<R: Read>(mut reader: R) -> Result<Self, AnalyserError> {
+        let mut contents = String::new();
+        reader.read_to_string(&mut contents)?;
+
+        let mut measurements = contents
+            .lines()
+            .enumerate()
+            .map(|(index, line)| (index + 1, line.trim()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+            .filter(|(line_number, line)| *line_number != 1 || !line.starts_with("date"))
+            .map(|(line_number, line)| {
+                parse_csv_row(line).ok_or_else(|| {
+                    AnalyserError::InvalidValue(format!(
+                        "invalid measurement on line {}: {}",
+                        line_number, line
+                    ))
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self::new(&mut measurements))
+    }
+
     /// Get the FTP of the athlete for a given date
     pub fn get_actual_ftp(&self, date: &NaiveDate) -> Option<Power> {
         self.get_actual(date)
     }
 
+    /// Get the running FTP (critical power) of the athlete for a given date
+    pub fn get_actual_running_ftp(&self, date: &NaiveDate) -> Option<Power> {
+        self.get_actual(date).map(|RunningFtp(power)| power)
+    }
+
+    /// Get the FTP matching the sport of an activity for a given date
+    /// Running power (e.g. from Stryd) is on a different scale than cycling power, so runs only
+    /// use the running FTP, everything else uses the cycling FTP.
+    pub fn get_actual_ftp_for_sport(&self, date: &NaiveDate, sport: Option<&str>) -> Option<Power> {
+        match sport {
+            Some("running") => self.get_actual_running_ftp(date),
+            _ => self.get_actual_ftp(date),
+        }
+    }
+
     /// Get the FTHr of the athlete for a given date
+    /// Without any FTHr measurement until that date, it is derived from the maximum heart rate
+    /// (see FTHR_FRACTION_OF_MAX_HR).
     pub fn get_actual_fthr(&self, date: &NaiveDate) -> Option<HeartRate> {
+        self.get_actual(date).or_else(|| {
+            let HeartRate(max_hr) = self.get_actual_max_hr(date)?;
+            Some(HeartRate(
+                (max_hr as f64 * FTHR_FRACTION_OF_MAX_HR).round() as i64
+            ))
+        })
+    }
+
+    /// Get the maximum heart rate of the athlete for a given date
+    pub fn get_actual_max_hr(&self, date: &NaiveDate) -> Option<HeartRate> {
+        self.get_actual(date).map(|MaxHr(heart_rate)| heart_rate)
+    }
+
+    /// Get the weight of the athlete for a given date
+    pub fn get_actual_weight(&self, date: &NaiveDate) -> Option<Weight> {
         self.get_actual(date)
     }
 
+    /// Dates when the FTP of the athlete changed, with the new value
+    /// Repeated measurements of the same value (e.g. a retest with the same result) are not
+    /// changes, the first measurement always is.
+    pub fn ftp_history(&self) -> Vec<(NaiveDate, Power)> {
+        self.history()
+    }
+
+    /// Number of days between the last FTP change on or before a given date and that date
+    /// None if there was no FTP measurement until that date.
+    pub fn days_since_ftp_change(&self, date: &NaiveDate) -> Option<i64> {
+        let (last_change, _) = self
+            .ftp_history()
+            .into_iter()
+            .take_while(|(d, _)| d <= date)
+            .last()?;
+        Some((*date - last_change).num_days())
+    }
+
+    /// Get the change points of some measurement of the athlete
+    fn history<T>(&self) -> Vec<(NaiveDate, T)>
+    where
+        T: TryFrom<MeasurementRecord> + PartialEq,
+    {
+        let MeasurementRecords(measurements) = self;
+        measurements
+            .iter()
+            .filter_map(|(d, m)| Some((*d, T::try_from(m.clone()).ok()?)))
+            .fold(Vec::new(), |mut acc, (d, m)| {
+                if acc.last().is_none_or(|(_, last)| *last != m) {
+                    acc.push((d, m));
+                }
+                acc
+            })
+    }
+
     /// Get some measurement of the athlete for a given date with a getter
     fn get_actual<T>(&self, date: &NaiveDate) -> Option<T>
     where
@@ -40,11 +153,41 @@ impl MeasurementRecords {
     }
 }
 
+impl FromIterator<(NaiveDate, MeasurementRecord)> for MeasurementRecords {
+    fn from_iter<I: IntoIterator<Item = (NaiveDate, MeasurementRecord)>>(iter: I) -> Self {
+        let mut measurements = iter.into_iter().collect::<Vec<_>>();
+        Self::new(&mut measurements)
+    }
+}
+
+/// Parse a single `date,type,value` CSV row
+fn parse_csv_row(line: &str) -> Option<(NaiveDate, MeasurementRecord)> {
+    let [date, measurement_type, value] = line
+        .split(',')
+        .map(str::trim)
+        .collect::<Vec<_>>()
+        .try_into()
+        .ok()?;
+    let date = date.parse::<NaiveDate>().ok()?;
+    let measurement = match measurement_type.to_lowercase().as_str() {
+        "ftp" => MeasurementRecord::FTP(Power(value.parse().ok()?)),
+        "running_ftp" => MeasurementRecord::RunningFTP(Power(value.parse().ok()?)),
+        "fthr" => MeasurementRecord::FTHr(HeartRate(value.parse().ok()?)),
+        "max_hr" => MeasurementRecord::MaxHr(HeartRate(value.parse().ok()?)),
+        "weight" => MeasurementRecord::Weight(Weight(value.parse().ok()?)),
+        _ => return None,
+    };
+    Some((date, measurement))
+}
+
 /// An athlete measurement type
 #[derive(Clone)]
 pub enum MeasurementRecord {
     FTP(Power),
+    /// FTP of running power, also known as critical power
+    RunningFTP(Power),
     FTHr(HeartRate),
+    MaxHr(HeartRate),
     Weight(Weight),
 }
 
@@ -58,6 +201,32 @@ impl TryFrom<MeasurementRecord> for Power {
     }
 }
 
+/// Running FTP, to tell it apart from the cycling FTP when looking up measurements
+struct RunningFtp(Power);
+
+impl TryFrom<MeasurementRecord> for RunningFtp {
+    type Error = ();
+    fn try_from(value: MeasurementRecord) -> Result<Self, ()> {
+        match value {
+            MeasurementRecord::RunningFTP(power) => Ok(RunningFtp(power)),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Maximum heart rate, to tell it apart from the FTHr when looking up measurements
+struct MaxHr(HeartRate);
+
+impl TryFrom<MeasurementRecord> for MaxHr {
+    type Error = ();
+    fn try_from(value: MeasurementRecord) -> Result<Self, ()> {
+        match value {
+            MeasurementRecord::MaxHr(heart_rate) => Ok(MaxHr(heart_rate)),
+            _ => Err(()),
+        }
+    }
+}
+
 impl TryFrom<MeasurementRecord> for HeartRate {
     type Error = ();
     fn try_from(value: MeasurementRecord) -> Result<Self, ()> {
@@ -68,6 +237,16 @@ impl TryFrom<MeasurementRecord> for HeartRate {
     }
 }
 
+impl TryFrom<MeasurementRecord> for Weight {
+    type Error = ();
+    fn try_from(value: MeasurementRecord) -> Result<Self, ()> {
+        match value {
+            MeasurementRecord::Weight(weight) => Ok(weight),
+            _ => Err(()),
+        }
+    }
+}
+
 #[cfg(test)]
 mod athlete_tests {
     use super::*;
@@ -93,4 +272,156 @@ mod athlete_tests {
             Some(Power(210))
         );
     }
+
+    #[test]
+    fn incrementally_built_measurements() {
+        let date = NaiveDate::from_ymd_opt(2022, 9, 1).unwrap();
+        let mut measurements = MeasurementRecords::empty();
+        assert_eq!(measurements.get_actual_ftp(&date), None);
+
+        measurements.push(
+            NaiveDate::from_ymd_opt(2022, 8, 8).unwrap(),
+            MeasurementRecord::FTP(Power(210)),
+        );
+        measurements.push(
+            NaiveDate::from_ymd_opt(2022, 7, 8).unwrap(),
+            MeasurementRecord::FTP(Power(200)),
+        );
+        assert_eq!(measurements.get_actual_ftp(&date), Some(Power(210)));
+
+        let measurements = [(2022, 9, 8, 220), (2022, 7, 8, 200), (2022, 8, 8, 210)]
+            .into_iter()
+            .map(|(year, month, day, ftp)| {
+                (
+                    NaiveDate::from_ymd_opt(year, month, day).unwrap(),
+                    MeasurementRecord::FTP(Power(ftp)),
+                )
+            })
+            .collect::<MeasurementRecords>();
+        assert_eq!(measurements.get_actual_ftp(&date), Some(Power(210)));
+    }
+
+    #[test]
+    fn ftp_change_points() {
+        let measurements = [
+            (2022, 7, 8, MeasurementRecord::FTP(Power(200))),
+            (2022, 7, 20, MeasurementRecord::Weight(Weight(70.0))),
+            (2022, 8, 8, MeasurementRecord::FTP(Power(200))),
+            (2022, 9, 8, MeasurementRecord::FTP(Power(220))),
+        ]
+        .into_iter()
+        .map(|(year, month, day, measurement)| {
+            (
+                NaiveDate::from_ymd_opt(year, month, day).unwrap(),
+                measurement,
+            )
+        })
+        .collect::<MeasurementRecords>();
+
+        assert_eq!(
+            measurements.ftp_history(),
+            vec![
+                (NaiveDate::from_ymd_opt(2022, 7, 8).unwrap(), Power(200)),
+                (NaiveDate::from_ymd_opt(2022, 9, 8).unwrap(), Power(220)),
+            ]
+        );
+        assert_eq!(
+            measurements.days_since_ftp_change(&NaiveDate::from_ymd_opt(2022, 9, 1).unwrap()),
+            Some(55)
+        );
+        assert_eq!(
+            measurements.days_since_ftp_change(&NaiveDate::from_ymd_opt(2022, 9, 8).unwrap()),
+            Some(0)
+        );
+        assert_eq!(
+            measurements.days_since_ftp_change(&NaiveDate::from_ymd_opt(2022, 1, 1).unwrap()),
+            None
+        );
+    }
+
+    #[test]
+    fn ftp_by_sport() {
+        let measurements = MeasurementRecords::from_csv_reader(
+            "2022-07-08,ftp,260\n2022-07-08,running_ftp,250\n2022-08-08,running_ftp,255\n"
+                .as_bytes(),
+        )
+        .unwrap();
+        let date = NaiveDate::from_ymd_opt(2022, 9, 1).unwrap();
+
+        assert_eq!(
+            measurements.get_actual_ftp_for_sport(&date, Some("running")),
+            Some(Power(255))
+        );
+        assert_eq!(
+            measurements.get_actual_ftp_for_sport(&date, Some("cycling")),
+            Some(Power(260))
+        );
+        assert_eq!(
+            measurements.get_actual_ftp_for_sport(&date, None),
+            Some(Power(260))
+        );
+        assert_eq!(
+            MeasurementRecords::new([(date, MeasurementRecord::FTP(Power(260)))])
+                .get_actual_ftp_for_sport(&date, Some("running")),
+            None
+        );
+    }
+
+    #[test]
+    fn fthr_from_max_hr() {
+        let measurements = MeasurementRecords::from_csv_reader(
+            "2022-07-08,max_hr,190\n2022-08-08,max_hr,188\n2022-09-08,fthr,172\n".as_bytes(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            measurements.get_actual_fthr(&NaiveDate::from_ymd_opt(2022, 7, 20).unwrap()),
+            Some(HeartRate(171))
+        );
+        assert_eq!(
+            measurements.get_actual_fthr(&NaiveDate::from_ymd_opt(2022, 9, 1).unwrap()),
+            Some(HeartRate(169))
+        );
+        assert_eq!(
+            measurements.get_actual_fthr(&NaiveDate::from_ymd_opt(2022, 9, 8).unwrap()),
+            Some(HeartRate(172))
+        );
+        assert_eq!(
+            measurements.get_actual_max_hr(&NaiveDate::from_ymd_opt(2022, 9, 8).unwrap()),
+            Some(HeartRate(188))
+        );
+        assert_eq!(
+            measurements.get_actual_fthr(&NaiveDate::from_ymd_opt(2022, 1, 1).unwrap()),
+            None
+        );
+    }
+
+    #[test]
+    fn measurements_from_csv() {
+        let csv = "date,type,value
+2022-09-08,ftp,220
+2022-07-08,fthr,170
+2022-07-08,ftp,200
+# retest after the break
+2022-08-08,weight,70.5
+2022-08-08,FTP,210
+2022-09-20,fthr,175
+";
+        let measurements = MeasurementRecords::from_csv_reader(csv.as_bytes()).unwrap();
+        let date = NaiveDate::from_ymd_opt(2022, 9, 1).unwrap();
+
+        assert_eq!(measurements.get_actual_ftp(&date), Some(Power(210)));
+        assert_eq!(measurements.get_actual_fthr(&date), Some(HeartRate(170)));
+        assert_eq!(measurements.get_actual_weight(&date), Some(Weight(70.5)));
+    }
+
+    #[test]
+    fn invalid_csv_row() {
+        let csv = "2022-09-08,ftp,220\n2022-09-09,vo2max,60\n";
+        let error = MeasurementRecords::from_csv_reader(csv.as_bytes())
+            .err()
+            .unwrap();
+
+        assert!(error.to_string().contains("line 2"));
+    }
 }
@@ -0,0 +1,235 @@
+use crate::activity::Activity;
+use crate::activity_analysis::ActivityAnalysis;
+use crate::error::AnalyserError;
+use crate::measurements::{HeartRate, Power};
+use chrono::{DateTime, Duration, Local};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Analysis results of an activity, as stored in the cache
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CacheEntry {
+    pub start_time: Option<DateTime<Local>>,
+    #[serde(with = "crate::serialization::option_duration_seconds")]
+    pub duration: Option<Duration>,
+    pub analysis: ActivityAnalysis,
+}
+
+/// Basic fields of an activity, as stored in the cache
+/// Lets activities be filtered and their inputs be looked up without parsing the file again.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ActivityHeader {
+    pub start_time: Option<DateTime<Local>>,
+    #[serde(with = "crate::serialization::option_duration_seconds")]
+    pub duration: Option<Duration>,
+    pub sport: Option<String>,
+}
+
+impl From<&Activity> for ActivityHeader {
+    fn from(activity: &Activity) -> Self {
+        Self {
+            start_time: activity.start_time,
+            duration: activity.duration,
+            sport: activity.sport().cloned(),
+        }
+    }
+}
+
+/// Inputs of an analysis besides the activity itself
+#[derive(Debug, Clone)]
+pub struct AnalysisInputs<'a> {
+    pub ftp: Option<Power>,
+    pub fthr: Option<HeartRate>,
+    pub peak_durations: &'a HashSet<Duration>,
+}
+
+/// On-disk cache of activity analyses keyed by a hash of the FIT file contents
+/// Keys include the crate version, so entries written by other versions are never reused.
+/// Analyses are also keyed by their inputs, so e.g. an FTP update doesn't return stale results.
+#[derive(Debug, Clone)]
+pub struct AnalysisCache {
+    dir: PathBuf,
+}
+
+impl AnalysisCache {
+    /// Open a cache directory, creating it if it does not exist
+    pub fn new(dir: &Path) -> Result<Self, AnalyserError> {
+        fs::create_dir_all(dir)?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+        })
+    }
+
+    /// Calculate the cache key of the header of a file's raw bytes
+    pub fn header_key(bytes: &[u8]) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(env!("CARGO_PKG_VERSION"));
+        hasher.update("header");
+        hasher.update(bytes);
+        format!("{:x}", hasher.finalize())
+    }
+
+    /// Calculate the cache key of the analysis of a file's raw bytes with the given inputs
+    pub fn key(bytes: &[u8], inputs: &AnalysisInputs) -> String {
+        let mut peak_durations = inputs
+            .peak_durations
+            .iter()
+            .map(Duration::num_milliseconds)
+            .collect::<Vec<_>>();
+        peak_durations.sort_unstable();
+
+        let mut hasher = Sha256::new();
+        hasher.update(env!("CARGO_PKG_VERSION"));
+        hasher.update(bytes);
+        hasher.update(format!(
+            "{:?} {:?} {:?}",
+            inputs.ftp, inputs.fthr, peak_durations
+        ));
+        format!("{:x}", hasher.finalize())
+    }
+
+    /// Look up a cache entry, either a `CacheEntry` or an `ActivityHeader`
+    /// Missing or unreadable entries are treated as cache misses.
+    pub fn get<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        let contents = fs::read(self.entry_path(key)).ok()?;
+        serde_json::from_slice(&contents).ok()
+    }
+
+    /// Store a cache entry
+    pub fn insert<T: Serialize>(&self, key: &str, entry: &T) -> Result<(), AnalyserError> {
+        let contents = serde_json::to_vec(entry).map_err(io::Error::from)?;
+        fs::write(self.entry_path(key), contents)?;
+        Ok(())
+    }
+
+    fn entry_path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", key))
+    }
+}
+
+#[cfg(test)]
+mod cache_tests {
+    use super::*;
+
+    #[test]
+    fn key_depends_on_contents() {
+        let peak_durations = HashSet::new();
+        let inputs = AnalysisInputs {
+            ftp: None,
+            fthr: None,
+            peak_durations: &peak_durations,
+        };
+        assert_eq!(
+            AnalysisCache::key(b"activity", &inputs),
+            AnalysisCache::key(b"activity", &inputs)
+        );
+        assert_ne!(
+            AnalysisCache::key(b"activity", &inputs),
+            AnalysisCache::key(b"workout", &inputs)
+        );
+        assert_ne!(
+            AnalysisCache::key(b"activity", &inputs),
+            AnalysisCache::header_key(b"activity")
+        );
+    }
+
+    #[test]
+    fn key_depends_on_inputs() {
+        let peak_durations = HashSet::from([Duration::minutes(1), Duration::minutes(5)]);
+        let inputs = AnalysisInputs {
+            ftp: Some(Power(250)),
+            fthr: Some(HeartRate(170)),
+            peak_durations: &peak_durations,
+        };
+        let key = AnalysisCache::key(b"activity", &inputs);
+
+        let reordered_peak_durations = HashSet::from([Duration::minutes(5), Duration::minutes(1)]);
+        let reordered = AnalysisInputs {
+            peak_durations: &reordered_peak_durations,
+            ..inputs.clone()
+        };
+        assert_eq!(AnalysisCache::key(b"activity", &reordered), key);
+
+        let updated_ftp = AnalysisInputs {
+            ftp: Some(Power(260)),
+            ..inputs.clone()
+        };
+        assert_ne!(AnalysisCache::key(b"activity", &updated_ftp), key);
+        let updated_fthr = AnalysisInputs {
+            fthr: None,
+            ..inputs.clone()
+        };
+        assert_ne!(AnalysisCache::key(b"activity", &updated_fthr), key);
+        let other_peak_durations = HashSet::from([Duration::minutes(1)]);
+        let other_peaks = AnalysisInputs {
+            peak_durations: &other_peak_durations,
+            ..inputs
+        };
+        assert_ne!(AnalysisCache::key(b"activity", &other_peaks), key);
+    }
+
+    #[test]
+    fn cached_analysis_round_trip() {
+        let dir =
+            std::env::temp_dir().join(format!("activity-analyser-cache-{}", std::process::id()));
+        let cache = AnalysisCache::new(&dir).unwrap();
+
+        let bytes = fs::read("./tests/fixtures/PedalingDynamics.fit").unwrap();
+        let peak_durations = HashSet::from([Duration::minutes(1)]);
+        let inputs = AnalysisInputs {
+            ftp: Some(Power(250)),
+            fthr: None,
+            peak_durations: &peak_durations,
+        };
+        let key = AnalysisCache::key(&bytes, &inputs);
+        assert!(cache.get::<CacheEntry>(&key).is_none());
+
+        let activity = Activity::from_bytes(&bytes).unwrap();
+        let header_key = AnalysisCache::header_key(&bytes);
+        cache
+            .insert(&header_key, &ActivityHeader::from(&activity))
+            .unwrap();
+        let entry = CacheEntry {
+            start_time: activity.start_time,
+            duration: activity.duration,
+            analysis: ActivityAnalysis::from_activity(
+                &inputs.ftp,
+                &inputs.fthr,
+                &activity,
+                &peak_durations,
+            ),
+        };
+        cache.insert(&key, &entry).unwrap();
+
+        let cached: CacheEntry = cache.get(&key).unwrap();
+        let cached_header: ActivityHeader = cache.get(&header_key).unwrap();
+        // An FTP update misses the cache
+        let updated_ftp = AnalysisInputs {
+            ftp: Some(Power(260)),
+            ..inputs
+        };
+        let stale = cache.get::<CacheEntry>(&AnalysisCache::key(&bytes, &updated_ftp));
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(stale.is_none());
+        assert_eq!(cached_header.start_time, activity.start_time);
+        assert_eq!(cached_header.sport, activity.sport().cloned());
+
+        assert_eq!(cached.start_time, entry.start_time);
+        assert_eq!(cached.duration, Some(Duration::seconds(600)));
+        assert_eq!(cached.analysis.total_work, entry.analysis.total_work);
+        assert_eq!(
+            cached.analysis.average_balance,
+            entry.analysis.average_balance
+        );
+        assert_eq!(
+            cached.analysis.peak_performances.power[&Duration::minutes(1)].value,
+            entry.analysis.peak_performances.power[&Duration::minutes(1)].value
+        );
+    }
+}
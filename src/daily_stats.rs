@@ -1,6 +1,62 @@
 use crate::metrics::{DailyTSS, ATL, CTL, TSB, TSS};
 use chrono::{Days, NaiveDate};
 use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+
+/// TSB above which the athlete is losing fitness
+pub const FORM_TRANSITION_THRESHOLD: f64 = 25.0;
+/// Lowest TSB of a fresh athlete
+pub const FORM_FRESH_THRESHOLD: f64 = 5.0;
+/// Lowest TSB of a neutral athlete
+pub const FORM_NEUTRAL_THRESHOLD: f64 = -10.0;
+/// Lowest TSB of a fatigued athlete, below this the athlete is very fatigued
+pub const FORM_FATIGUED_THRESHOLD: f64 = -30.0;
+
+/// Default maximum number of days (about ten years) of daily TSS kept, counting back from the
+/// latest one. Older records are dropped, so a bogus date (e.g. 1989 from a corrupt file) or a
+/// decade long gap can't fill thousands of 0 TSS days.
+pub const MAX_DAILY_TSS_DAYS: u64 = 3653;
+
+/// Category of the athlete's form based on the Training Stress Balance
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FormZone {
+    Transition,
+    Fresh,
+    Neutral,
+    Fatigued,
+    VeryFatigued,
+}
+
+impl FormZone {
+    /// Classify a Training Stress Balance
+    pub fn from_tsb(TSB(tsb): &TSB) -> Self {
+        if *tsb > FORM_TRANSITION_THRESHOLD {
+            FormZone::Transition
+        } else if *tsb >= FORM_FRESH_THRESHOLD {
+            FormZone::Fresh
+        } else if *tsb >= FORM_NEUTRAL_THRESHOLD {
+            FormZone::Neutral
+        } else if *tsb >= FORM_FATIGUED_THRESHOLD {
+            FormZone::Fatigued
+        } else {
+            FormZone::VeryFatigued
+        }
+    }
+}
+
+impl Display for FormZone {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        let label = match self {
+            FormZone::Transition => "Transition",
+            FormZone::Fresh => "Fresh",
+            FormZone::Neutral => "Neutral",
+            FormZone::Fatigued => "Fatigued",
+            FormZone::VeryFatigued => "Very Fatigued",
+        };
+        write!(f, "{}", label)
+    }
+}
 
 /// Peformance management metrics
 #[derive(Clone, Debug)]
@@ -32,9 +88,77 @@ impl DailyStats {
         }
     }
 
+    /// Estimate the stats of the day before the first daily TSS, to seed `DailyStats::calc_rolling`
+    /// with when no earlier stats are known, so CTL and ATL are meaningful from the first day
+    /// instead of ramping up from zero over weeks.
+    ///
+    /// The training load of a day is `TL = TL_yesterday * e^(-1/k) + TSS * (1 - e^(-1/k))`, so
+    /// training with the same TSS every day converges to `TL = TSS`, regardless of the time
+    /// constant k. Assuming the athlete trained like in the first `days` days before as well,
+    /// both CTL and ATL are seeded with the average daily TSS of those days, making TSB zero.
+    pub fn seed(
+        SortedDailyTSS(sorted_daily_tss): &SortedDailyTSS,
+        days: usize,
+    ) -> Option<DailyStats> {
+        let DailyTSS(first_day, _) = sorted_daily_tss.first()?;
+        let seed_days = &sorted_daily_tss[..days.min(sorted_daily_tss.len())];
+        if seed_days.is_empty() {
+            return None;
+        }
+        let average_tss = seed_days
+            .iter()
+            .map(|DailyTSS(_, TSS(tss))| *tss as f64)
+            .sum::<f64>()
+            / seed_days.len() as f64;
+
+        Some(DailyStats {
+            date: *first_day - Days::new(1),
+            tss: TSS(0),
+            ctl: CTL(average_tss),
+            atl: ATL(average_tss),
+            tsb: TSB(0.0),
+        })
+    }
+
+    /// Project performance management metrics into the future based on planned TSS values
+    /// Days without a planned workout are treated as rest days, planned TSS values on or before
+    /// the date of the seed stats are disregarded.
+    pub fn project(from: &DailyStats, planned_tss: &[DailyTSS]) -> Vec<DailyStats> {
+        let SortedDailyTSS(sorted_planned_tss) =
+            SortedDailyTSS::from_unsorted(planned_tss, Some(from));
+
+        sorted_planned_tss
+            .iter()
+            .scan(from.clone(), |yesterdays_stats, daily_tss| {
+                *yesterdays_stats = DailyStats::calc_next(yesterdays_stats, daily_tss);
+                Some(yesterdays_stats.clone())
+            })
+            .collect()
+    }
+
+    /// Find the stats of a given day in stats sorted by date (e.g. from `DailyStats::calc_rolling`)
+    pub fn at(stats: &[DailyStats], date: NaiveDate) -> Option<&DailyStats> {
+        let index = stats.binary_search_by_key(&date, |stats| stats.date).ok()?;
+        Some(&stats[index])
+    }
+
+    /// Stats of the most recent day in stats sorted by date
+    pub fn latest(stats: &[DailyStats]) -> Option<&DailyStats> {
+        stats.last()
+    }
+
+    /// Form category of the day
+    pub fn form_zone(&self) -> FormZone {
+        FormZone::from_tsb(&self.tsb)
+    }
+
     /// Calculating rolling daily statistics, starting from the last known point.
     /// Any daily TSS before the last known point will be disregarded.
-    /// Daily TSS must be sorted, and there must not be any gaps between the days.
+    /// After the last daily TSS, rest days are added until CTL, ATL and TSB all decay below 0.45,
+    /// but at most MAX_ENDING_DAYS of them.
+    /// Daily TSS must be sorted, and there must not be any gaps between the days. If the range
+    /// was capped, so the last known point is more than a day before the first daily TSS, it is
+    /// carried over the missing days as rest days, without storing them.
     pub fn calc_rolling(
         SortedDailyTSS(sorted_daily_tss): SortedDailyTSS,
         last_known_stats: Option<&DailyStats>,
@@ -46,10 +170,17 @@ impl DailyStats {
         let DailyTSS(first_day, _) = sorted_daily_tss[0];
         let DailyTSS(last_day, _) = sorted_daily_tss[sorted_daily_tss.len() - 1];
 
-        let ending_days = (1..).map(|days| DailyTSS(last_day + Days::new(days), TSS(0)));
+        let ending_days =
+            (1..=MAX_ENDING_DAYS).map(|days| DailyTSS(last_day + Days::new(days), TSS(0)));
 
         let init = match last_known_stats {
-            Some(stats) => stats.clone(),
+            Some(stats) => {
+                let rest_days = (first_day - stats.date).num_days() - 1;
+                (0..rest_days).fold(stats.clone(), |stats, _| {
+                    let date = stats.date + Days::new(1);
+                    DailyStats::calc_next(&stats, &DailyTSS(date, TSS(0)))
+                })
+            }
             None => DailyStats {
                 date: first_day - Days::new(1),
                 tss: TSS(0),
@@ -82,6 +213,72 @@ impl DailyStats {
     }
 }
 
+/// Daily stats saved by a run, for later runs to compare their stats with
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Baseline {
+    /// Date of the run the stats were saved by
+    pub date: NaiveDate,
+    pub daily_stats: Vec<DailyStats>,
+}
+
+/// Change of CTL, ATL and TSB since a baseline
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StatsDelta {
+    pub ctl: f64,
+    pub atl: f64,
+    pub tsb: f64,
+}
+
+impl Baseline {
+    /// Daily stats are sorted by date, so they can be looked up by `DailyStats::at`
+    pub fn new(date: NaiveDate, mut daily_stats: Vec<DailyStats>) -> Self {
+        daily_stats.sort_by_key(|stats| stats.date);
+        Self { date, daily_stats }
+    }
+
+    /// Stats of the day the baseline was saved on
+    /// None if the stats don't cover that day, e.g. all the data ended long before it.
+    pub fn stats(&self) -> Option<&DailyStats> {
+        DailyStats::at(&self.daily_stats, self.date)
+    }
+
+    /// Change of the given stats since the day the baseline was saved on
+    /// None if there are no stats for either of the days.
+    pub fn delta(&self, stats: Option<&DailyStats>) -> Option<StatsDelta> {
+        let (baseline, stats) = (self.stats()?, stats?);
+        Some(StatsDelta {
+            ctl: stats.ctl.0 - baseline.ctl.0,
+            atl: stats.atl.0 - baseline.atl.0,
+            tsb: stats.tsb.0 - baseline.tsb.0,
+        })
+    }
+}
+
+/// Maximum number of rest days added by `DailyStats::calc_rolling` after the last daily TSS
+/// The metrics decay below the threshold within ~330 days even from a CTL of 1000, this only
+/// bounds pathological inputs (e.g. an infinite CTL of the last known stats).
+pub const MAX_ENDING_DAYS: u64 = 400;
+
+/// Sum of the daily TSS over the 7 days ending on (and including) the given date
+pub fn weekly_tss_total(stats: &[DailyStats], date: NaiveDate) -> TSS {
+    trailing_tss_total(stats, date, 7)
+}
+
+/// Sum of the daily TSS over the 28 days ending on (and including) the given date
+pub fn monthly_tss_total(stats: &[DailyStats], date: NaiveDate) -> TSS {
+    trailing_tss_total(stats, date, 28)
+}
+
+/// Sum of the daily TSS over a number of days ending on (and including) the given date
+fn trailing_tss_total(stats: &[DailyStats], date: NaiveDate, days: u64) -> TSS {
+    let first_day = date - Days::new(days - 1);
+    stats
+        .iter()
+        .filter(|stats| first_day <= stats.date && stats.date <= date)
+        .fold(TSS(0), |acc, stats| acc + stats.tss)
+}
+
 #[derive(Clone, Debug)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SortedDailyTSS(Vec<DailyTSS>);
@@ -94,28 +291,44 @@ impl SortedDailyTSS {
     /// - sort
     /// - fill gaps with 0 TSS days
     /// - fill beginning of the list with 0 TSS days, if last known stats exist
+    /// - drop days more than MAX_DAILY_TSS_DAYS before the latest record
     pub fn from_unsorted(
         unsorted: &[DailyTSS],
         last_known_stats: Option<&DailyStats>,
     ) -> SortedDailyTSS {
+        Self::from_unsorted_with_max_days(unsorted, last_known_stats, MAX_DAILY_TSS_DAYS)
+    }
+
+    /// Same as `from_unsorted`, keeping at most `max_days` days (at least one), counting back
+    /// from the latest record. Records and gap filling before that range are dropped.
+    pub fn from_unsorted_with_max_days(
+        unsorted: &[DailyTSS],
+        last_known_stats: Option<&DailyStats>,
+        max_days: u64,
+    ) -> SortedDailyTSS {
+        let Some(latest_date) = unsorted.iter().map(|DailyTSS(date, _)| *date).max() else {
+            return SortedDailyTSS(Vec::new());
+        };
+        let earliest_date = latest_date - Days::new(max_days.max(1) - 1);
+
         // In order to fill gap between the last known stat and the first daily tss record,
-        // we initialise the BTreeMap with 0 TSS values.
+        // we initialise the BTreeMap with 0 TSS values. Filling up to the latest date is
+        // harmless, as the records are accumulated into it.
         let init_map: BTreeMap<NaiveDate, TSS> = match last_known_stats {
             None => BTreeMap::new(),
-            Some(stats) => match unsorted.first() {
-                None => BTreeMap::new(),
-                // As this is still unsorted, this is practically a random date in the date range.
-                Some(DailyTSS(random_date, _)) => {
-                    let days = (*random_date - stats.date).num_days();
-                    (1..days)
-                        .map(|days| (stats.date + Days::new(days as u64), TSS(0)))
-                        .collect()
-                }
-            },
+            Some(stats) => {
+                let first_date = (stats.date + Days::new(1)).max(earliest_date);
+                first_date
+                    .iter_days()
+                    .take_while(|date| *date < latest_date)
+                    .map(|date| (date, TSS(0)))
+                    .collect()
+            }
         };
         // By definitions BTreeMap converts into an iterator sorted by keys
         let acc = unsorted
             .iter()
+            .filter(|DailyTSS(date, _)| earliest_date <= *date)
             // Accumulation step
             .fold(init_map, |mut acc, DailyTSS(date, tss)| {
                 acc.entry(*date)
@@ -158,7 +371,10 @@ impl SortedDailyTSS {
 
 #[cfg(test)]
 mod daily_stats_tests {
-    use crate::daily_stats::{DailyStats, DailyTSS, SortedDailyTSS, ATL, CTL, TSB, TSS};
+    use crate::daily_stats::{
+        monthly_tss_total, weekly_tss_total, Baseline, DailyStats, DailyTSS, FormZone,
+        SortedDailyTSS, ATL, CTL, MAX_DAILY_TSS_DAYS, MAX_ENDING_DAYS, TSB, TSS,
+    };
     use assertables::*;
     use chrono::{Days, NaiveDate};
     use proptest::collection::vec;
@@ -252,4 +468,229 @@ mod daily_stats_tests {
 
         }
     }
+
+    #[test]
+    fn form_zone_thresholds() {
+        assert_eq!(FormZone::from_tsb(&TSB(30.0)), FormZone::Transition);
+        assert_eq!(FormZone::from_tsb(&TSB(25.0)), FormZone::Fresh);
+        assert_eq!(FormZone::from_tsb(&TSB(5.0)), FormZone::Fresh);
+        assert_eq!(FormZone::from_tsb(&TSB(0.0)), FormZone::Neutral);
+        assert_eq!(FormZone::from_tsb(&TSB(-10.0)), FormZone::Neutral);
+        assert_eq!(FormZone::from_tsb(&TSB(-20.0)), FormZone::Fatigued);
+        assert_eq!(FormZone::from_tsb(&TSB(-30.5)), FormZone::VeryFatigued);
+    }
+
+    #[test]
+    fn trailing_tss_totals() {
+        let first_day = NaiveDate::from_ymd_opt(2023, 10, 7).unwrap();
+        let daily_tss = (0..40)
+            .map(|days| DailyTSS(first_day + Days::new(days), TSS(10)))
+            .collect::<Vec<_>>();
+        let sorted = SortedDailyTSS::from_unsorted(&daily_tss, None);
+        let daily_stats = DailyStats::calc_rolling(sorted, None);
+
+        let last_day = first_day + Days::new(39);
+        assert_eq!(weekly_tss_total(&daily_stats, last_day), TSS(70));
+        assert_eq!(monthly_tss_total(&daily_stats, last_day), TSS(280));
+        assert_eq!(weekly_tss_total(&daily_stats, first_day), TSS(10));
+        assert_eq!(
+            weekly_tss_total(&daily_stats, last_day + Days::new(3)),
+            TSS(40)
+        );
+    }
+
+    #[test]
+    fn ending_days_are_capped() {
+        let first_day = NaiveDate::from_ymd_opt(2023, 10, 7).unwrap();
+        let daily_tss = [DailyTSS(first_day, TSS(1_000_000))];
+        let sorted = SortedDailyTSS::from_unsorted(&daily_tss, None);
+        let daily_stats = DailyStats::calc_rolling(sorted, None);
+        assert_le!(daily_stats.len(), 1 + MAX_ENDING_DAYS as usize);
+
+        let last_known_stats = DailyStats {
+            date: first_day - Days::new(1),
+            tss: TSS(0),
+            ctl: CTL(f64::INFINITY),
+            atl: ATL(0.0),
+            tsb: TSB(f64::INFINITY),
+        };
+        let sorted = SortedDailyTSS::from_unsorted(&daily_tss, Some(&last_known_stats));
+        let daily_stats = DailyStats::calc_rolling(sorted, Some(&last_known_stats));
+        assert_eq!(daily_stats.len(), 1 + MAX_ENDING_DAYS as usize);
+    }
+
+    #[test]
+    fn baseline_delta() {
+        let first_day = NaiveDate::from_ymd_opt(2023, 10, 7).unwrap();
+        let daily_tss = (0..14)
+            .map(|days| DailyTSS(first_day + Days::new(days), TSS(100)))
+            .collect::<Vec<_>>();
+        let daily_stats =
+            DailyStats::calc_rolling(SortedDailyTSS::from_unsorted(&daily_tss[..7], None), None);
+        let baseline_date = first_day + Days::new(6);
+        let baseline = Baseline::new(baseline_date, daily_stats.into_iter().rev().collect());
+
+        let new_stats =
+            DailyStats::calc_rolling(SortedDailyTSS::from_unsorted(&daily_tss, None), None);
+        let today = first_day + Days::new(13);
+        let todays_stats = DailyStats::at(&new_stats, today);
+        let delta = baseline.delta(todays_stats).unwrap();
+        let baseline_stats = baseline.stats().unwrap();
+        assert_eq!(baseline_stats.date, baseline_date);
+        assert_eq!(
+            delta.ctl,
+            todays_stats.unwrap().ctl.0 - baseline_stats.ctl.0
+        );
+        assert_gt!(delta.ctl, 0.0);
+        assert_gt!(delta.atl, 0.0);
+
+        // No stats for today in the new run
+        assert_eq!(baseline.delta(None), None);
+
+        // The baseline date isn't covered by its stats
+        let baseline = Baseline::new(first_day - Days::new(1), baseline.daily_stats);
+        assert!(baseline.stats().is_none());
+        assert_eq!(baseline.delta(todays_stats), None);
+    }
+
+    #[test]
+    fn stats_at_date() {
+        let first_day = NaiveDate::from_ymd_opt(2023, 10, 7).unwrap();
+        let daily_tss = (0..40)
+            .map(|days| DailyTSS(first_day + Days::new(days), TSS(10 + days as i64)))
+            .collect::<Vec<_>>();
+        let sorted = SortedDailyTSS::from_unsorted(&daily_tss, None);
+        let daily_stats = DailyStats::calc_rolling(sorted, None);
+
+        let date = first_day + Days::new(12);
+        let stats = DailyStats::at(&daily_stats, date).unwrap();
+        assert_eq!(stats.date, date);
+        assert_eq!(stats.tss, TSS(22));
+        assert!(DailyStats::at(&daily_stats, first_day - Days::new(1)).is_none());
+
+        let latest = DailyStats::latest(&daily_stats).unwrap();
+        assert_eq!(latest.date, daily_stats[daily_stats.len() - 1].date);
+        assert!(DailyStats::latest(&[]).is_none());
+    }
+
+    #[test]
+    fn projection_fills_rest_days() {
+        let today = NaiveDate::from_ymd_opt(2023, 10, 7).unwrap();
+        let todays_stats = DailyStats {
+            date: today,
+            tss: TSS(50),
+            ctl: CTL(50.0),
+            atl: ATL(60.0),
+            tsb: TSB(-10.0),
+        };
+        let planned_tss = [
+            DailyTSS(today + Days::new(4), TSS(100)),
+            DailyTSS(today + Days::new(1), TSS(80)),
+            DailyTSS(today, TSS(200)),
+        ];
+
+        let projection = DailyStats::project(&todays_stats, &planned_tss);
+
+        let daily_tss = projection
+            .iter()
+            .map(|stats| (stats.date, stats.tss))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            daily_tss,
+            vec![
+                (today + Days::new(1), TSS(80)),
+                (today + Days::new(2), TSS(0)),
+                (today + Days::new(3), TSS(0)),
+                (today + Days::new(4), TSS(100)),
+            ]
+        );
+        assert_lt!(projection[2].atl, projection[1].atl);
+    }
+
+    #[test]
+    fn seeded_training_load() {
+        let first_day = NaiveDate::from_ymd_opt(2023, 10, 7).unwrap();
+        let daily_tss = [40, 60, 80, 100]
+            .iter()
+            .enumerate()
+            .map(|(days, tss)| DailyTSS(first_day + Days::new(days as u64), TSS(*tss)))
+            .collect::<Vec<_>>();
+        let sorted = SortedDailyTSS::from_unsorted(&daily_tss, None);
+
+        let seed = DailyStats::seed(&sorted, 3).unwrap();
+        assert_eq!(seed.date, first_day - Days::new(1));
+        assert_eq!(
+            (seed.ctl, seed.atl, seed.tsb),
+            (CTL(60.0), ATL(60.0), TSB(0.0))
+        );
+
+        let daily_stats = DailyStats::calc_rolling(sorted, Some(&seed));
+        assert_eq!(daily_stats[0].date, first_day);
+        assert_gt!(daily_stats[0].ctl, CTL(55.0));
+
+        let empty = SortedDailyTSS::from_unsorted(&[], None);
+        assert!(DailyStats::seed(&empty, 3).is_none());
+        assert!(DailyStats::seed(&SortedDailyTSS::from_unsorted(&daily_tss, None), 0).is_none());
+    }
+
+    #[test]
+    fn bogus_dates_are_not_filled() {
+        let latest_date = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        let daily_tss = [
+            DailyTSS(NaiveDate::from_ymd_opt(1989, 12, 31).unwrap(), TSS(500)),
+            DailyTSS(latest_date - Days::new(2), TSS(50)),
+            DailyTSS(latest_date, TSS(80)),
+        ];
+
+        let SortedDailyTSS(sorted) = SortedDailyTSS::from_unsorted(&daily_tss, None);
+        assert_eq!(sorted.len(), 3);
+        assert_eq!(sorted[2].0, latest_date);
+
+        let last_known_stats = DailyStats {
+            date: NaiveDate::from_ymd_opt(1989, 12, 31).unwrap(),
+            tss: TSS(0),
+            ctl: CTL(0.0),
+            atl: ATL(0.0),
+            tsb: TSB(0.0),
+        };
+        let SortedDailyTSS(sorted) =
+            SortedDailyTSS::from_unsorted(&daily_tss, Some(&last_known_stats));
+        assert_eq!(sorted.len(), MAX_DAILY_TSS_DAYS as usize);
+        assert_eq!(sorted.last().unwrap().0, latest_date);
+    }
+
+    #[test]
+    fn capped_range_rests_from_last_known_stats() {
+        let latest_date = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        let daily_tss = [
+            DailyTSS(latest_date - Days::new(60), TSS(100)),
+            DailyTSS(latest_date - Days::new(5), TSS(50)),
+            DailyTSS(latest_date, TSS(80)),
+        ];
+        let last_known_stats = DailyStats {
+            date: latest_date - Days::new(100),
+            tss: TSS(0),
+            ctl: CTL(50.0),
+            atl: ATL(60.0),
+            tsb: TSB(-10.0),
+        };
+
+        let full = SortedDailyTSS::from_unsorted(&daily_tss, Some(&last_known_stats));
+        let capped =
+            SortedDailyTSS::from_unsorted_with_max_days(&daily_tss, Some(&last_known_stats), 10);
+        assert_eq!(capped.0.len(), 10);
+        assert_eq!(capped.0[0].0, latest_date - Days::new(9));
+
+        let full = DailyStats::calc_rolling(full, Some(&last_known_stats));
+        let capped = DailyStats::calc_rolling(capped, Some(&last_known_stats));
+        let full_last_day = full.iter().find(|stats| stats.date == latest_date).unwrap();
+        let capped_last_day = capped
+            .iter()
+            .find(|stats| stats.date == latest_date)
+            .unwrap();
+        // Only the TSS of the dropped record (60 days earlier) is missing
+        assert_lt!(capped_last_day.ctl, full_last_day.ctl);
+        assert_gt!(capped_last_day.ctl, CTL(full_last_day.ctl.0 - 100.0 / 42.0));
+        assert_in_delta!(capped_last_day.atl.0, full_last_day.atl.0, 0.01);
+    }
 }
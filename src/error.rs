@@ -0,0 +1,97 @@
+use fitparser::ErrorKind;
+use std::fmt::{Display, Formatter};
+use std::io;
+
+/// Errors of reading and analysing activities
+#[derive(Debug)]
+pub enum AnalyserError {
+    /// Reading or writing a file failed
+    Io(io::Error),
+    /// The FIT file is malformed
+    FitParse(FitParseError),
+    /// A required field is missing from the file
+    MissingField(String),
+    /// A value could not be converted or is out of its valid range
+    InvalidValue(String),
+    /// The file has no record data, e.g. it is a workout or a course
+    EmptyActivity(String),
+}
+
+impl Display for AnalyserError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self {
+            AnalyserError::Io(error) => write!(f, "io error: {}", error),
+            AnalyserError::FitParse(error) => write!(f, "{}", error),
+            AnalyserError::MissingField(field_name) => write!(f, "missing field: {}", field_name),
+            AnalyserError::InvalidValue(message) => write!(f, "value error: {}", message),
+            AnalyserError::EmptyActivity(message) => write!(f, "empty activity: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for AnalyserError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            AnalyserError::Io(error) => Some(error),
+            AnalyserError::FitParse(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for AnalyserError {
+    fn from(error: io::Error) -> Self {
+        AnalyserError::Io(error)
+    }
+}
+
+impl From<fitparser::Error> for AnalyserError {
+    /// IO and value conversion errors of fitparser are mapped to their own variants
+    fn from(error: fitparser::Error) -> Self {
+        match *error {
+            ErrorKind::Io(error) => AnalyserError::Io(error),
+            ErrorKind::ValueError(message) => AnalyserError::InvalidValue(message),
+            kind => AnalyserError::FitParse(FitParseError(Box::new(kind))),
+        }
+    }
+}
+
+/// Error of the FIT parser, kept opaque so the parser isn't part of the public API
+#[derive(Debug)]
+pub struct FitParseError(fitparser::Error);
+
+impl Display for FitParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for FitParseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.0.source()
+    }
+}
+
+#[cfg(test)]
+mod error_tests {
+    use super::*;
+
+    #[test]
+    fn fitparser_errors_are_classified() {
+        let error = AnalyserError::from(fitparser::Error::from(ErrorKind::ValueError(
+            "not a number".to_string(),
+        )));
+        assert!(
+            matches!(error, AnalyserError::InvalidValue(ref message) if message == "not a number")
+        );
+
+        let error = AnalyserError::from(fitparser::Error::from(io::Error::from(
+            io::ErrorKind::NotFound,
+        )));
+        assert!(matches!(error, AnalyserError::Io(_)));
+
+        let error = AnalyserError::from(fitparser::Error::from(ErrorKind::TrailingBytes(3)));
+        assert!(matches!(error, AnalyserError::FitParse(_)));
+        assert_eq!(error.to_string(), ErrorKind::TrailingBytes(3).to_string());
+    }
+}
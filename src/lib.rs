@@ -1,7 +1,15 @@
 pub mod activity;
 pub mod activity_analysis;
 pub mod athlete;
+#[cfg(feature = "cache")]
+pub mod cache;
 pub mod daily_stats;
+pub mod error;
 pub mod measurements;
 pub mod metrics;
 pub mod peak;
+#[cfg(feature = "serde")]
+pub mod serialization;
+pub mod summary;
+pub mod swim;
+pub mod zones;
@@ -1,48 +1,275 @@
 #[macro_use]
 extern crate prettytable;
 use activity_analyser::activity::Activity;
-use activity_analyser::activity_analysis::ActivityAnalysis;
+use activity_analyser::activity_analysis::{
+    eftp_history, season_power_curve, ActivityAnalysis, ActivityAnalysisBuilder, PeakPerformances,
+};
 use activity_analyser::athlete::{MeasurementRecord, MeasurementRecords};
-use activity_analyser::daily_stats::{DailyStats, SortedDailyTSS};
-use activity_analyser::measurements::{HeartRate, Power, Speed, Weight};
-use activity_analyser::metrics::DailyTSS;
-use chrono::{Duration, Local, NaiveDate};
-use clap::Parser;
-use fitparser::{self, Error};
+use activity_analyser::cache::{ActivityHeader, AnalysisCache, AnalysisInputs, CacheEntry};
+use activity_analyser::daily_stats::{
+    monthly_tss_total, weekly_tss_total, Baseline, DailyStats, SortedDailyTSS,
+};
+use activity_analyser::error::AnalyserError;
+use activity_analyser::measurements::{
+    Altitude, Cadence, HeartRate, LeftRightBalance, Power, Speed, Weight,
+};
+use activity_analyser::metrics::{
+    estimate_calories, polarization_index, DailyTSS, POWER_ZONE_COUNT,
+};
+use activity_analyser::peak::Peak;
+use activity_analyser::summary::{SeasonSummary, Summary};
+use chrono::{DateTime, Duration, FixedOffset, Local, NaiveDate};
+use clap::{Parser, Subcommand, ValueEnum};
+use fitparser::profile::MesgNum;
+use log::{info, log_enabled, warn, Level, LevelFilter};
 use prettytable::{format, Table};
 use rayon::prelude::*;
 use std::collections::{HashMap, HashSet};
 use std::fmt::{Display, Formatter};
 use std::fs;
-use std::path::PathBuf;
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
+struct Cli {
+    /// Only print results, without progress messages
+    /// Progress messages are printed to stderr, their level can also be set with RUST_LOG.
+    #[arg(short, long, global = true)]
+    quiet: bool,
+    /// Style of the printed tables
+    #[arg(long, value_enum, global = true, default_value_t = TableStyle::Default)]
+    table_style: TableStyle,
+    #[command(subcommand)]
+    command: Args,
+}
+
+#[derive(Subcommand)]
 enum Args {
     SingleActivity {
         /// FIT file path
         #[arg(short, long)]
         path: PathBuf,
+        /// Power normalization method
+        #[arg(long, value_enum, default_value_t = Normalization::Np)]
+        normalization: Normalization,
+        /// Timezone offset used for calendar dates (e.g. +02:00), defaults to the host's timezone
+        #[arg(long, allow_hyphen_values = true)]
+        tz: Option<FixedOffset>,
+        /// List the best N non-overlapping power efforts
+        #[arg(long)]
+        top_n: Option<usize>,
+        /// Duration of the efforts listed by --top-n in seconds
+        #[arg(long, default_value_t = 300)]
+        top_n_duration: u32,
         /// Print verbose logs
         #[arg(short, long)]
         verbose: bool,
     },
-    MultiActivity {
+    MultiActivity(MultiActivityArgs),
+    /// Compare the metrics of two activities side by side
+    Compare {
+        /// FIT file path of the first activity
+        #[arg(long)]
+        path_a: PathBuf,
+        /// FIT file path of the second activity
+        #[arg(long)]
+        path_b: PathBuf,
+        /// Timezone offset used for calendar dates (e.g. +02:00), defaults to the host's timezone
+        #[arg(long, allow_hyphen_values = true)]
+        tz: Option<FixedOffset>,
+    },
+    /// Print raw record fields as CSV, one row per record
+    DumpCsv {
+        /// FIT file path
+        #[arg(short, long)]
+        path: PathBuf,
+        /// Comma separated list of record fields
+        #[arg(
+            short,
+            long,
+            value_delimiter = ',',
+            default_value = "timestamp,power,heart_rate,cadence,altitude,distance"
+        )]
+        fields: Vec<String>,
+    },
+    /// List which measurements FIT files in a directory have, and the files that can't be read
+    Validate {
         /// Path to the directory containing FIT files
         #[arg(short, long)]
         path: PathBuf,
-        /// Print verbose logs
+        /// Search for FIT files in subdirectories as well
         #[arg(short, long)]
-        verbose: bool,
+        recursive: bool,
     },
 }
 
-fn main() -> Result<(), Error> {
-    let cli = Args::parse();
+#[derive(clap::Args)]
+struct MultiActivityArgs {
+    /// Path to the directory containing FIT files
+    #[arg(short, long)]
+    path: PathBuf,
+    /// Search for FIT files in subdirectories as well
+    #[arg(short, long)]
+    recursive: bool,
+    /// Only analyse activities started on or after this date (YYYY-MM-DD)
+    #[arg(long)]
+    from: Option<NaiveDate>,
+    /// Only analyse activities started on or before this date (YYYY-MM-DD)
+    #[arg(long)]
+    to: Option<NaiveDate>,
+    /// Timezone offset used for calendar dates (e.g. +02:00), defaults to the host's timezone
+    /// Fixed offsets do not follow daylight saving time changes.
+    #[arg(long, allow_hyphen_values = true)]
+    tz: Option<FixedOffset>,
+    /// Seed CTL and ATL with the average TSS of this many days from the start of the data
+    #[arg(long)]
+    seed_days: Option<usize>,
+    /// Exclude activities shorter than this many seconds (or without a known duration)
+    #[arg(long)]
+    min_duration: Option<u32>,
+    /// Print sparklines of the daily CTL, ATL and TSB over the analysed date range
+    #[arg(long)]
+    sparkline: bool,
+    /// Save today's date and the daily CTL, ATL and TSB as JSON, to be used as a baseline by later
+    /// runs
+    #[arg(long)]
+    save_stats: Option<PathBuf>,
+    /// Show the change of today's CTL, ATL and TSB since a baseline saved with --save-stats
+    /// Only available with the table output format.
+    #[arg(long)]
+    compare_to_baseline: Option<PathBuf>,
+    /// Output format
+    #[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+    format: OutputFormat,
+    /// Directory to cache analysis results in, skipping unchanged files on later runs
+    #[arg(long)]
+    cache: Option<PathBuf>,
+    /// Directory to save a JSON report of each analysed activity in, named by its start time
+    #[arg(long)]
+    reports: Option<PathBuf>,
+    /// Number of files analysed in parallel, defaults to the number of CPUs
+    #[arg(short, long, value_parser = clap::value_parser!(u16).range(1..))]
+    jobs: Option<u16>,
+    /// Print verbose logs
+    #[arg(short, long)]
+    verbose: bool,
+}
+
+fn main() -> Result<(), AnalyserError> {
+    let cli = Cli::parse();
+    init_logger(cli.quiet);
 
-    match cli {
-        Args::SingleActivity { path, verbose } => single_activity(path, verbose),
-        Args::MultiActivity { path, verbose } => multi_activity(path, verbose),
+    match cli.command {
+        Args::SingleActivity {
+            path,
+            normalization,
+            tz,
+            top_n,
+            top_n_duration,
+            verbose,
+        } => single_activity(
+            path,
+            normalization,
+            tz,
+            top_n.map(|n| (n, Duration::seconds(top_n_duration.into()))),
+            verbose,
+            cli.table_style,
+        ),
+        Args::MultiActivity(args) => multi_activity(args, cli.table_style),
+        Args::Compare { path_a, path_b, tz } => compare(path_a, path_b, tz, cli.table_style),
+        Args::DumpCsv { path, fields } => dump_csv(path, &fields),
+        Args::Validate { path, recursive } => validate(&path, recursive, cli.table_style),
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Normalization {
+    /// Normalized Power with Intensity Factor
+    Np,
+    /// xPower with Relative Intensity
+    Xpower,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    /// Human readable tables
+    Table,
+    /// One JSON object per activity
+    Jsonl,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum TableStyle {
+    /// Tables with borders
+    Default,
+    /// Tables without borders
+    Borderless,
+    /// Markdown tables, for pasting into notes or issues
+    Markdown,
+    /// Comma separated values, one table after another
+    Csv,
+}
+
+/// Per activity line of the JSON lines output
+/// The summary only has the standard peak durations, all the computed peaks are kept in `peaks`.
+#[derive(serde::Serialize)]
+struct ActivityRecord<'a> {
+    path: &'a Path,
+    start_time: Option<DateTime<Local>>,
+    #[serde(flatten)]
+    summary: Summary,
+    peaks: &'a PeakPerformances,
+}
+
+/// Log progress messages and warnings to stderr, keeping stdout for the results only
+/// In quiet mode only warnings are logged. RUST_LOG takes precedence over both.
+fn init_logger(quiet: bool) {
+    env_logger::Builder::new()
+        .filter_level(if quiet {
+            LevelFilter::Warn
+        } else {
+            LevelFilter::Info
+        })
+        .parse_default_env()
+        .format_timestamp(None)
+        .format_target(false)
+        .init();
+}
+
+/// Number of processed files out of the total, reported on stderr while files are analysed in
+/// parallel
+/// Only shown on an interactive terminal with progress messages enabled, so logs and pipelines
+/// are not flooded.
+struct ProgressCounter {
+    done: AtomicUsize,
+    total: usize,
+    enabled: bool,
+}
+
+impl ProgressCounter {
+    fn new(total: usize) -> Self {
+        Self {
+            done: AtomicUsize::new(0),
+            total,
+            enabled: total > 0 && log_enabled!(Level::Info) && io::stderr().is_terminal(),
+        }
+    }
+
+    /// Count a processed file, callable from any thread
+    fn increment(&self) {
+        let done = self.done.fetch_add(1, Ordering::Relaxed) + 1;
+        if self.enabled {
+            eprint!("\rAnalysed {}/{} files", done, self.total);
+        }
+    }
+
+    /// End the progress line
+    fn finish(&self) {
+        if self.enabled {
+            eprintln!();
+        }
     }
 }
 
@@ -77,15 +304,21 @@ fn def_measurements() -> MeasurementRecords {
     ])
 }
 
-fn single_activity(path: PathBuf, verbose: bool) -> Result<(), Error> {
+fn single_activity(
+    path: PathBuf,
+    normalization: Normalization,
+    tz: Option<FixedOffset>,
+    top_n: Option<(usize, Duration)>,
+    verbose: bool,
+    table_style: TableStyle,
+) -> Result<(), AnalyserError> {
     let measurements = def_measurements();
 
-    println!(
+    info!(
         "Parsing FIT files using Profile version: {}",
         fitparser::profile::VERSION
     );
-    let mut fp = fs::File::open(path)?;
-    let activity = Activity::from_reader(&mut fp)?;
+    let activity = Activity::from_path(path)?;
     let peak_durations = HashSet::from([
         Duration::seconds(5),
         Duration::minutes(1),
@@ -93,47 +326,190 @@ fn single_activity(path: PathBuf, verbose: bool) -> Result<(), Error> {
         Duration::minutes(20),
     ]);
 
-    let date: Option<NaiveDate> = activity.start_time.map(|t| t.naive_utc().into());
-    let ftp = date.and_then(|d| measurements.get_actual_ftp(&d));
+    let date = activity
+        .start_time
+        .as_ref()
+        .map(|start_time| date_in(start_time, tz));
+    let ftp = date.and_then(|d| {
+        measurements.get_actual_ftp_for_sport(&d, activity.sport().map(String::as_str))
+    });
     let fthr = date.and_then(|d| measurements.get_actual_fthr(&d));
-    let activity_analysis =
-        ActivityAnalysis::from_activity(&ftp, &fthr, &activity, &peak_durations);
+    let weight = date.and_then(|d| measurements.get_actual_weight(&d));
+    // Speed and elevation of trainer rides are zero or made up, so they are left out
+    let indoor = activity.is_indoor();
+    let activity_analysis = ActivityAnalysisBuilder::new()
+        .ftp(ftp)
+        .fthr(fthr)
+        .peak_durations(peak_durations)
+        .speed(!indoor)
+        .elevation(!indoor)
+        .build(&activity);
+
+    let ((power_label, power_value), (intensity_label, intensity_value)) = match normalization {
+        Normalization::Np => (
+            (
+                "Normalized power",
+                DisplayableOption(activity_analysis.normalized_power).to_string(),
+            ),
+            (
+                "Intensity Factor",
+                DisplayableOption(activity_analysis.intensity_factor).to_string(),
+            ),
+        ),
+        Normalization::Xpower => (
+            (
+                "xPower",
+                DisplayableOption(activity_analysis.xpower).to_string(),
+            ),
+            (
+                "Relative Intensity",
+                DisplayableOption(activity_analysis.relative_intensity).to_string(),
+            ),
+        ),
+    };
+
+    let (intensity_label, tss_label) = if activity_analysis.ftp_estimated {
+        (
+            format!("{} (estimated FTP)", intensity_label),
+            "TSS (estimated FTP)",
+        )
+    } else {
+        (intensity_label.to_string(), "TSS")
+    };
+
+    let elevation_suffix = if activity_analysis.elevation_estimated {
+        " (estimated from grade)"
+    } else {
+        ""
+    };
 
     let mut data_table = table![
-        ["Workout name", DisplayableOption(activity.workout_name)],
+        [
+            "Workout name",
+            DisplayableOption(activity.workout_name.as_ref())
+        ],
         ["Start time", DisplayableOption(activity.start_time)],
+        ["Recorded on", DisplayableOption(activity.device_info())],
         ["Duration", DisplayableOption(activity.duration)],
+        ["Moving time", DisplayableOption(activity.moving_time())],
+        ["Elapsed time", DisplayableOption(activity.elapsed_time())],
+        ["Timer time", DisplayableOption(activity.timer_time())],
         [
             "Average power",
             DisplayableOption(activity_analysis.average_power)
         ],
         [
-            "Normalized power",
-            DisplayableOption(activity_analysis.normalized_power)
+            "Average cadence",
+            DisplayableOption(activity_analysis.average_cadence)
+        ],
+        [
+            "Coasting",
+            DisplayableOption(
+                activity_analysis
+                    .coasting_percentage
+                    .map(|percentage| format!("{:.1}%", percentage))
+            )
+        ],
+        [
+            "L/R balance",
+            DisplayableOption(
+                activity_analysis
+                    .average_balance
+                    .map(|(_, right)| LeftRightBalance(right))
+            )
+        ],
+        [
+            "Torque effectiveness (L)",
+            DisplayableOption(activity_analysis.average_left_torque_effectiveness)
+        ],
+        [
+            "Torque effectiveness (R)",
+            DisplayableOption(activity_analysis.average_right_torque_effectiveness)
+        ],
+        [
+            "Pedal smoothness (L)",
+            DisplayableOption(activity_analysis.average_left_pedal_smoothness)
         ],
+        [
+            "Pedal smoothness (R)",
+            DisplayableOption(activity_analysis.average_right_pedal_smoothness)
+        ],
+        [power_label, power_value],
+        ["Ride type", activity_analysis.ride_type()],
+        ["Pacing", DisplayableOption(activity_analysis.pacing)],
         [
             "Variability Index",
             DisplayableOption(activity_analysis.variability_index)
         ],
+        [intensity_label, intensity_value],
         [
-            "Intensity Factor",
-            DisplayableOption(activity_analysis.intensity_factor)
+            "Total Work",
+            DisplayableOption(activity_analysis.total_work)
+        ],
+        [
+            "Calories",
+            DisplayableOption(
+                activity
+                    .total_calories()
+                    .map(|calories| format!("{} kcal", calories))
+                    .or_else(|| activity_analysis
+                        .total_work
+                        .map(|work| format!("{} kcal (estimated)", estimate_calories(work))))
+            )
+        ],
+        [
+            "Polarization Index",
+            DisplayableOption(
+                activity_analysis
+                    .power_zones
+                    .as_ref()
+                    .and_then(polarization_index)
+                    .map(|index| format!("{:.2}", index))
+            )
         ],
-        ["Total Work", activity_analysis.total_work],
-        ["TSS", DisplayableOption(activity_analysis.tss)],
+        [tss_label, DisplayableOption(activity_analysis.tss())],
         ["hrTSS", DisplayableOption(activity_analysis.hr_tss)],
         [
-            "Elevation gain",
-            DisplayableOption(activity_analysis.elevation_gain)
+            "TSS per hour",
+            DisplayableOption(
+                activity_analysis
+                    .intensity_density()
+                    .map(|density| format!("{:.1}", density))
+            )
         ],
         [
-            "Elevation loss",
-            DisplayableOption(activity_analysis.elevation_loss)
+            "VO2max (estimated)",
+            DisplayableOption(
+                weight
+                    .and_then(|weight| activity_analysis.estimate_vo2max(weight))
+                    .map(|vo2max| format!("{:.1} ml/kg/min", vo2max))
+            )
         ]
     ];
+    if !indoor {
+        data_table.add_row(row![
+            "Average speed",
+            DisplayableOption(activity_analysis.average_speed)
+        ]);
+        data_table.add_row(row![
+            "Average moving speed",
+            DisplayableOption(activity_analysis.moving_average_speed)
+        ]);
+        data_table.add_row(row![
+            "Normalized graded pace",
+            DisplayableOption(activity_analysis.normalized_graded_pace)
+        ]);
+        data_table.add_row(row![
+            format!("Elevation gain{}", elevation_suffix),
+            DisplayableOption(activity_analysis.elevation_gain)
+        ]);
+        data_table.add_row(row![
+            format!("Elevation loss{}", elevation_suffix),
+            DisplayableOption(activity_analysis.elevation_loss)
+        ]);
+    }
 
-    data_table.set_format(*format::consts::FORMAT_NO_LINESEP_WITH_TITLE);
-    data_table.printstd();
+    print_table(&mut data_table, table_style);
 
     let power_peaks = activity_analysis
         .peak_performances
@@ -154,174 +530,976 @@ fn single_activity(path: PathBuf, verbose: bool) -> Result<(), Error> {
         .map(|(k, v)| (k, v.value))
         .collect::<HashMap<_, _>>();
 
-    peaks_table(&power_peaks, &speed_peaks, &heart_rate_peaks).printstd();
+    print_table(
+        &mut peaks_table(&power_peaks, &speed_peaks, &heart_rate_peaks),
+        table_style,
+    );
+
+    let sessions = activity.sessions();
+    if sessions.len() > 1 {
+        let mut sessions_table = table![["Sport", "Start time", "Duration"]];
+        sessions.iter().for_each(|session| {
+            sessions_table.add_row(row![
+                DisplayableOption(session.sport.as_ref()),
+                DisplayableOption(session.start_time),
+                DisplayableOption(session.duration)
+            ]);
+        });
+        print_table(&mut sessions_table, table_style);
+    }
+
+    if let Some((n, duration)) = top_n {
+        let power_data = activity.get_data_with_timestamps::<Power>("power");
+        let mut top_n_table = table![["Effort", "Power", "Start time"]];
+        Peak::top_n_non_overlapping(&power_data, duration, n)
+            .iter()
+            .enumerate()
+            .for_each(|(index, peak)| {
+                top_n_table.add_row(row![
+                    format!("#{} ({})", index + 1, format_duration(&duration)),
+                    peak.value,
+                    peak.timestamps.0
+                ]);
+            });
+        print_table(&mut top_n_table, table_style);
+    }
+
+    let calibration_events = activity.calibration_events();
+    if !calibration_events.is_empty() {
+        let mut calibration_table = table![["Calibration", "Data"]];
+        calibration_events.iter().for_each(|event| {
+            calibration_table.add_row(row![
+                DisplayableOption(event.timestamp),
+                DisplayableOption(event.data)
+            ]);
+        });
+        print_table(&mut calibration_table, table_style);
+    }
+
+    if let Some(power_zones) = activity_analysis.power_zones {
+        print_table(&mut zones_table("Power", &power_zones), table_style);
+    }
+
+    if let Some(heart_rate_zones) = activity_analysis.heart_rate_zones {
+        print_table(
+            &mut zones_table("Heart rate", &heart_rate_zones),
+            table_style,
+        );
+    }
 
     if verbose {
+        if let Err(reason) = activity_analysis.try_tss() {
+            warn!("TSS is unavailable: {}", reason);
+        }
+        let conversions = [
+            ("power", activity.try_get_data::<Power>("power").err()),
+            (
+                "heart_rate",
+                activity.try_get_data::<HeartRate>("heart_rate").err(),
+            ),
+            ("cadence", activity.try_get_data::<Cadence>("cadence").err()),
+            (
+                "enhanced_speed",
+                activity.try_get_data::<Speed>("enhanced_speed").err(),
+            ),
+            (
+                "altitude",
+                activity.try_get_data::<Altitude>("altitude").err(),
+            ),
+        ];
+        conversions
+            .into_iter()
+            .for_each(|(field_name, error)| match error {
+                Some(AnalyserError::MissingField(_)) | None => {}
+                Some(error) => warn!("Could not read {}: {}", field_name, error),
+            });
         println!("{:#?}", activity.records);
     };
     Ok(())
 }
 
+fn compare(
+    path_a: PathBuf,
+    path_b: PathBuf,
+    tz: Option<FixedOffset>,
+    table_style: TableStyle,
+) -> Result<(), AnalyserError> {
+    let measurements = def_measurements();
+    let peak_durations = HashSet::from([
+        Duration::seconds(5),
+        Duration::minutes(1),
+        Duration::minutes(5),
+        Duration::minutes(20),
+    ]);
+    let analyse = |path: &Path| -> Result<(Activity, Summary), AnalyserError> {
+        let activity = Activity::from_path(path)?;
+        let date = activity
+            .start_time
+            .as_ref()
+            .map(|start_time| date_in(start_time, tz));
+        let ftp = date.and_then(|d| {
+            measurements.get_actual_ftp_for_sport(&d, activity.sport().map(String::as_str))
+        });
+        let fthr = date.and_then(|d| measurements.get_actual_fthr(&d));
+        let summary =
+            ActivityAnalysis::from_activity(&ftp, &fthr, &activity, &peak_durations).to_summary();
+        Ok((activity, summary))
+    };
+    let (activity_a, a) = analyse(&path_a)?;
+    let (activity_b, b) = analyse(&path_b)?;
+
+    let minutes = |activity: &Activity| {
+        activity
+            .duration
+            .map(|duration| duration.num_seconds() as f64 / 60.0)
+    };
+    let kmh = |speed: Option<f64>| speed.map(|speed| speed * 3.6);
+    let int = |value: Option<i64>| value.map(|value| value as f64);
+
+    let mut compare_table = table![["", path_a.display(), path_b.display(), "Delta"]];
+    [
+        (
+            "Duration (min)",
+            minutes(&activity_a),
+            minutes(&activity_b),
+            1,
+        ),
+        ("Average power", int(a.avg_power), int(b.avg_power), 0),
+        ("Normalized power", int(a.np), int(b.np), 0),
+        (
+            "Intensity Factor",
+            a.intensity_factor,
+            b.intensity_factor,
+            2,
+        ),
+        ("Variability Index", a.vi, b.vi, 2),
+        ("TSS", int(a.tss), int(b.tss), 0),
+        ("hrTSS", int(a.hr_tss), int(b.hr_tss), 0),
+        ("TSS per hour", a.tss_per_hour, b.tss_per_hour, 1),
+        ("Total Work (kJ)", a.work, b.work, 2),
+        (
+            "Average heart rate",
+            int(a.avg_heart_rate),
+            int(b.avg_heart_rate),
+            0,
+        ),
+        (
+            "Maximum heart rate",
+            int(a.max_heart_rate),
+            int(b.max_heart_rate),
+            0,
+        ),
+        (
+            "Average speed (km/h)",
+            kmh(a.avg_speed),
+            kmh(b.avg_speed),
+            2,
+        ),
+        (
+            "Average moving speed (km/h)",
+            kmh(a.avg_moving_speed),
+            kmh(b.avg_moving_speed),
+            2,
+        ),
+        ("Average cadence", a.avg_cadence, b.avg_cadence, 1),
+        ("Elevation gain (m)", a.elevation_gain, b.elevation_gain, 1),
+        ("Power (5s)", int(a.peak_power_5s), int(b.peak_power_5s), 0),
+        (
+            "Power (1m)",
+            int(a.peak_power_1min),
+            int(b.peak_power_1min),
+            0,
+        ),
+        (
+            "Power (5m)",
+            int(a.peak_power_5min),
+            int(b.peak_power_5min),
+            0,
+        ),
+        (
+            "Power (20m)",
+            int(a.peak_power_20min),
+            int(b.peak_power_20min),
+            0,
+        ),
+        (
+            "Heart rate (20m)",
+            int(a.peak_heart_rate_20min),
+            int(b.peak_heart_rate_20min),
+            0,
+        ),
+    ]
+    .into_iter()
+    .for_each(|(label, a, b, precision)| {
+        let format_value = |value: Option<f64>| {
+            value.map_or("-".to_string(), |value| format!("{:.*}", precision, value))
+        };
+        let delta = match (a, b) {
+            (Some(a), Some(b)) => format!("{:+.*}", precision, b - a),
+            _ => "-".to_string(),
+        };
+        compare_table.add_row(row![label, format_value(a), format_value(b), delta]);
+    });
+
+    print_table(&mut compare_table, table_style);
+    Ok(())
+}
+
+fn dump_csv(path: PathBuf, fields: &[String]) -> Result<(), AnalyserError> {
+    let activity = Activity::from_path(path)?;
+    write_csv(&activity, fields, &mut io::stdout().lock())?;
+    Ok(())
+}
+
+/// Write the fields of every record message as CSV, one row per record
+/// Records without a timestamp or with a repeated one are kept, missing fields are left empty.
+fn write_csv(activity: &Activity, fields: &[String], writer: &mut impl Write) -> io::Result<()> {
+    let header = fields
+        .iter()
+        .map(|field| csv_field(field))
+        .collect::<Vec<_>>();
+    writeln!(writer, "{}", header.join(","))?;
+    for record in activity
+        .records
+        .iter()
+        .filter(|record| record.kind() == MesgNum::Record)
+    {
+        let row = fields
+            .iter()
+            .map(|field_name| {
+                record
+                    .fields()
+                    .iter()
+                    .find(|field| field.name() == field_name)
+                    .map_or(String::new(), |field| csv_field(&field.value().to_string()))
+            })
+            .collect::<Vec<_>>();
+        writeln!(writer, "{}", row.join(","))?;
+    }
+    Ok(())
+}
+
+/// Quote a CSV field if it contains a separator, a quote or a line break (RFC 4180)
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
 fn peaks_table(
     power_peaks: &HashMap<&Duration, Power>,
     speed_peaks: &HashMap<&Duration, Speed>,
     heart_rate_peaks: &HashMap<&Duration, HeartRate>,
 ) -> Table {
-    let mut peaks_table = table![
-        [
-            "Power (5s)",
-            DisplayableOption(power_peaks.get(&Duration::seconds(5)))
-        ],
-        [
-            "Power (1m)",
-            DisplayableOption(power_peaks.get(&Duration::minutes(1)))
-        ],
-        [
-            "Power (5m)",
-            DisplayableOption(power_peaks.get(&Duration::minutes(5)))
-        ],
-        [
-            "Power (20m)",
-            DisplayableOption(power_peaks.get(&Duration::minutes(20)))
-        ],
-        [
-            "Speed (5s)",
-            DisplayableOption(speed_peaks.get(&Duration::seconds(5)))
-        ],
-        [
-            "Speed (1m)",
-            DisplayableOption(speed_peaks.get(&Duration::minutes(1)))
-        ],
-        [
-            "Speed (5m)",
-            DisplayableOption(speed_peaks.get(&Duration::minutes(5)))
-        ],
-        [
-            "Speed (20m)",
-            DisplayableOption(speed_peaks.get(&Duration::minutes(20)))
-        ],
-        [
-            "Heart rate (5s)",
-            DisplayableOption(heart_rate_peaks.get(&Duration::seconds(5)))
-        ],
-        [
-            "Heart rate (1m)",
-            DisplayableOption(heart_rate_peaks.get(&Duration::minutes(1)))
-        ],
-        [
-            "Heart rate (5m)",
-            DisplayableOption(heart_rate_peaks.get(&Duration::minutes(5)))
-        ],
-        [
-            "Heart rate (20m)",
-            DisplayableOption(heart_rate_peaks.get(&Duration::minutes(20)))
-        ]
+    let durations = [
+        Duration::seconds(5),
+        Duration::minutes(1),
+        Duration::minutes(5),
+        Duration::minutes(20),
     ];
-    peaks_table.set_format(*format::consts::FORMAT_NO_LINESEP_WITH_TITLE);
+    let mut peaks_table = Table::new();
+    durations.iter().for_each(|duration| {
+        peaks_table.add_row(row![
+            format!("Power ({})", format_duration(duration)),
+            DisplayableOption(power_peaks.get(duration))
+        ]);
+    });
+    // Speed is not analysed for indoor activities, the rows are left out instead of empty
+    if !speed_peaks.is_empty() {
+        durations.iter().for_each(|duration| {
+            peaks_table.add_row(row![
+                format!("Speed ({})", format_duration(duration)),
+                DisplayableOption(speed_peaks.get(duration))
+            ]);
+        });
+    }
+    durations.iter().for_each(|duration| {
+        peaks_table.add_row(row![
+            format!("Heart rate ({})", format_duration(duration)),
+            DisplayableOption(heart_rate_peaks.get(duration))
+        ]);
+    });
     peaks_table
 }
 
-fn multi_activity(path: PathBuf, verbose: bool) -> Result<(), Error> {
-    let measurements = &def_measurements();
+/// Print a table to stdout in the given style
+/// Apart from the default style, tables are followed by an empty line to keep them apart.
+fn print_table(table: &mut Table, style: TableStyle) {
+    match style {
+        TableStyle::Default => {
+            table.set_format(*format::consts::FORMAT_NO_LINESEP_WITH_TITLE);
+            table.printstd();
+            return;
+        }
+        TableStyle::Borderless => {
+            table.set_format(*format::consts::FORMAT_CLEAN);
+            table.printstd();
+        }
+        TableStyle::Markdown => print!("{}", markdown_table(table)),
+        TableStyle::Csv => {
+            if let Err(err) = table.to_csv(io::stdout()) {
+                warn!("Could not write table as CSV: {}", err);
+            }
+        }
+    }
+    println!();
+}
 
-    println!("Reading files...");
-    #[allow(clippy::type_complexity)]
-    let (successes, failures): (Vec<Result<Activity, Error>>, Vec<Result<Activity, Error>>) =
-        fs::read_dir(path)?
-            .collect::<Vec<_>>()
-            .into_par_iter()
-            .map(|entry| {
-                let mut fp = fs::File::open(entry?.path())?;
-                Activity::from_reader(&mut fp)
+/// Render a table in markdown, using its first row as the header
+/// Pipes are escaped and line breaks are replaced with <br>, so each row stays on a single line.
+fn markdown_table(table: &Table) -> String {
+    if table.is_empty() {
+        return String::new();
+    }
+    let column_count = table.row_iter().map(|row| row.len()).max().unwrap_or(0);
+    let mut lines = table.row_iter().map(|row| {
+        let cells = (0..column_count)
+            .map(|i| {
+                row.get_cell(i)
+                    .map(|cell| cell.get_content().replace('|', "\\|").replace('\n', "<br>"))
+                    .unwrap_or_default()
             })
-            .partition(Result::is_ok);
+            .collect::<Vec<_>>();
+        format!("| {} |\n", cells.join(" | "))
+    });
 
-    let successes = successes
+    let mut markdown = lines.next().unwrap_or_default();
+    markdown.push_str(&format!("|{}\n", "---|".repeat(column_count)));
+    markdown.extend(lines);
+    markdown
+}
+
+/// Format a duration in its largest whole unit (e.g. 5s, 20m, 1h)
+fn format_duration(duration: &Duration) -> String {
+    let seconds = duration.num_seconds();
+    if seconds != 0 && seconds % 3600 == 0 {
+        format!("{}h", seconds / 3600)
+    } else if seconds != 0 && seconds % 60 == 0 {
+        format!("{}m", seconds / 60)
+    } else {
+        format!("{}s", seconds)
+    }
+}
+
+/// Trailing window of the best 20 minute power eFTP is estimated from
+const EFTP_WINDOW_DAYS: u64 = 42;
+
+/// Bars of a sparkline, from the lowest to the highest value
+const SPARKLINE_BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// Render a series as a Unicode sparkline, scaled between its own minimum and maximum
+/// A constant series is rendered with the lowest bar.
+fn render_sparkline(values: &[f64]) -> String {
+    let min = values.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    let range = max - min;
+    values
         .iter()
-        .map(|x| x.as_ref().unwrap())
-        .collect::<Vec<_>>();
-    let failures = failures
+        .map(|value| {
+            let level = if range > 0.0 {
+                ((value - min) / range * (SPARKLINE_BARS.len() - 1) as f64).round() as usize
+            } else {
+                0
+            };
+            SPARKLINE_BARS[level]
+        })
+        .collect()
+}
+
+/// Best power of a duration across multiple activities, with the activity it was achieved in
+#[derive(Clone, Copy)]
+struct BestEffort<'a> {
+    power: Power,
+    path: &'a Path,
+    time: DateTime<Local>,
+}
+
+fn power_curve_table(power_curve: &[(Duration, Power)]) -> Table {
+    let mut power_curve_table = Table::new();
+    power_curve.iter().for_each(|(duration, power)| {
+        power_curve_table.add_row(row![
+            format!("Season best power ({})", format_duration(duration)),
+            power
+        ]);
+    });
+    power_curve_table
+}
+
+fn best_efforts_table(
+    durations: &[Duration],
+    best_efforts: &HashMap<Duration, BestEffort>,
+    tz: Option<FixedOffset>,
+) -> Table {
+    let mut best_efforts_table = table![["Best efforts", "Power", "Date", "File"]];
+    durations
         .iter()
-        .map(|x| x.as_ref().unwrap_err())
+        .filter_map(|duration| Some((duration, best_efforts.get(duration)?)))
+        .for_each(|(duration, effort)| {
+            best_efforts_table.add_row(row![
+                format_duration(duration),
+                effort.power,
+                date_in(&effort.time, tz),
+                effort.path.display()
+            ]);
+        });
+    best_efforts_table
+}
+
+fn zones_table(label: &str, zones: &[Duration]) -> Table {
+    let mut zones_table = Table::new();
+    zones.iter().enumerate().for_each(|(i, duration)| {
+        zones_table.add_row(row![format!("{} zone {}", label, i + 1), duration]);
+    });
+    zones_table
+}
+
+/// Check if a path has a FIT file extension, optionally gzip compressed (.fit or .fit.gz)
+fn is_fit_file(path: &Path) -> bool {
+    let has_extension = |path: &Path, expected: &str| {
+        path.extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case(expected))
+    };
+    has_extension(path, "fit")
+        || (has_extension(path, "gz") && has_extension(&path.with_extension(""), "fit"))
+}
+
+/// Collect FIT file paths in a directory, optionally descending into subdirectories
+/// Paths are sorted, as the order of directory entries depends on the file system.
+fn find_fit_files(dir: &Path, recursive: bool) -> Result<Vec<PathBuf>, AnalyserError> {
+    let mut paths = fs::read_dir(dir)?.try_fold(Vec::new(), |mut acc, entry| {
+        let path = entry?.path();
+        if path.is_dir() {
+            if recursive {
+                acc.extend(find_fit_files(&path, recursive)?);
+            }
+        } else if is_fit_file(&path) {
+            acc.push(path);
+        }
+        Ok::<_, AnalyserError>(acc)
+    })?;
+    paths.sort();
+    Ok(paths)
+}
+
+fn validate(path: &Path, recursive: bool, table_style: TableStyle) -> Result<(), AnalyserError> {
+    info!("Reading files...");
+    let paths = find_fit_files(path, recursive)?;
+
+    let rows = paths
+        .par_iter()
+        .map(|path| {
+            let mut row = vec![path.display().to_string()];
+            match Activity::from_path(path) {
+                Ok(activity) => {
+                    let gps = if activity
+                        .find_many_values(&MesgNum::Record, "position_lat")
+                        .is_empty()
+                    {
+                        "-"
+                    } else {
+                        "yes"
+                    };
+                    row.extend(
+                        [
+                            field_status::<Power>(&activity, &["power"]),
+                            field_status::<HeartRate>(&activity, &["heart_rate"]),
+                            field_status::<Cadence>(&activity, &["cadence"]),
+                            gps,
+                            field_status::<Altitude>(&activity, &["enhanced_altitude", "altitude"]),
+                            "",
+                        ]
+                        .map(str::to_string),
+                    );
+                }
+                Err(error) => {
+                    row.extend(std::iter::repeat_n(String::new(), 5));
+                    row.push(error.to_string());
+                }
+            }
+            row
+        })
         .collect::<Vec<_>>();
 
-    println!(
-        "Successfully parsed {} files, failed to read {}.",
-        successes.len(),
-        failures.len()
-    );
-    println!("Analysing files...");
+    let mut validate_table = table![[
+        "File",
+        "Power",
+        "Heart rate",
+        "Cadence",
+        "GPS",
+        "Altitude",
+        "Error"
+    ]];
+    rows.into_iter().for_each(|row| {
+        validate_table.add_row(row.into());
+    });
+    print_table(&mut validate_table, table_style);
+    Ok(())
+}
 
-    let peak_durations = HashSet::from([
+/// Presence of a record field in an activity, checking alternative field names in order
+/// "yes" if it has valid samples, "invalid" if they can't be converted and "-" if it's missing.
+fn field_status<T>(activity: &Activity, field_names: &[&str]) -> &'static str
+where
+    T: TryFrom<fitparser::Value>,
+    AnalyserError: From<T::Error>,
+{
+    field_names
+        .iter()
+        .find_map(|field_name| match activity.try_get_data::<T>(field_name) {
+            Ok(data) if !data.is_empty() => Some("yes"),
+            Ok(_) | Err(AnalyserError::MissingField(_)) => None,
+            Err(_) => Some("invalid"),
+        })
+        .unwrap_or("-")
+}
+
+/// Calendar date of a point in time, in the given timezone or the host's timezone
+fn date_in(time: &DateTime<Local>, tz: Option<FixedOffset>) -> NaiveDate {
+    match tz {
+        Some(tz) => time.with_timezone(&tz).date_naive(),
+        None => time.date_naive(),
+    }
+}
+
+/// Check if an activity started within a date range
+/// Activities without a start time are only accepted if no range is given.
+fn in_date_range(
+    start_time: Option<DateTime<Local>>,
+    tz: Option<FixedOffset>,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+) -> bool {
+    if from.is_none() && to.is_none() {
+        return true;
+    }
+    start_time.is_some_and(|start_time| {
+        let date = date_in(&start_time, tz);
+        from.is_none_or(|from| from <= date) && to.is_none_or(|to| date <= to)
+    })
+}
+
+/// Parse and analyse a FIT file, reusing cached results of unchanged files
+/// Cached analyses are only reused if they were made with the same FTP, FTHr and peak durations.
+/// Activities rejected by `accept` are not analysed, None is returned for them.
+fn analyse_file(
+    path: &Path,
+    cache: Option<&AnalysisCache>,
+    tz: Option<FixedOffset>,
+    measurements: &MeasurementRecords,
+    peak_durations: &HashSet<Duration>,
+    accept: impl Fn(&ActivityHeader) -> bool,
+) -> Result<Option<CacheEntry>, AnalyserError> {
+    let bytes = fs::read(path)?;
+    let header_key = cache.map(|_| AnalysisCache::header_key(&bytes));
+    let cached_header = cache
+        .zip(header_key.as_ref())
+        .and_then(|(cache, key)| cache.get::<ActivityHeader>(key));
+    let (header, activity) = match cached_header {
+        Some(header) => (header, None),
+        None => {
+            let activity = Activity::from_reader(&mut bytes.as_slice())?;
+            let header = ActivityHeader::from(&activity);
+            if let Some((cache, key)) = cache.zip(header_key) {
+                cache.insert(&key, &header)?;
+            }
+            (header, Some(activity))
+        }
+    };
+    if !accept(&header) {
+        return Ok(None);
+    }
+
+    let date = header
+        .start_time
+        .as_ref()
+        .map(|start_time| date_in(start_time, tz));
+    let inputs = AnalysisInputs {
+        ftp: date.and_then(|d| measurements.get_actual_ftp_for_sport(&d, header.sport.as_deref())),
+        fthr: date.and_then(|d| measurements.get_actual_fthr(&d)),
+        peak_durations,
+    };
+    let key = cache.map(|_| AnalysisCache::key(&bytes, &inputs));
+    if let Some(entry) = cache
+        .zip(key.as_ref())
+        .and_then(|(cache, key)| cache.get::<CacheEntry>(key))
+    {
+        return Ok(Some(entry));
+    }
+
+    let activity = match activity {
+        Some(activity) => activity,
+        None => Activity::from_reader(&mut bytes.as_slice())?,
+    };
+    let entry = CacheEntry {
+        start_time: header.start_time,
+        duration: header.duration,
+        analysis: ActivityAnalysis::from_activity(
+            &inputs.ftp,
+            &inputs.fthr,
+            &activity,
+            peak_durations,
+        ),
+    };
+
+    if let Some((cache, key)) = cache.zip(key) {
+        cache.insert(&key, &entry)?;
+    }
+    Ok(Some(entry))
+}
+
+/// Save one JSON report per activity into a directory, in the same shape as the JSON lines output
+/// Files are named by the start time of the activity (or the name of the FIT file if it's
+/// unknown), activities with the same name get a numeric suffix.
+fn write_reports(
+    dir: &Path,
+    activities: &[(PathBuf, Option<DateTime<Local>>, ActivityAnalysis)],
+) -> Result<(), AnalyserError> {
+    fs::create_dir_all(dir)?;
+    let mut used_names = HashSet::new();
+    for (path, start_time, analysis) in activities {
+        let base_name = match start_time {
+            Some(start_time) => start_time.format("%Y-%m-%dT%H-%M-%S").to_string(),
+            None => path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_else(|| "activity".to_string()),
+        };
+        let name = (1..)
+            .map(|index| match index {
+                1 => base_name.clone(),
+                index => format!("{}-{}", base_name, index),
+            })
+            .find(|name| !used_names.contains(name))
+            .unwrap();
+
+        let record = ActivityRecord {
+            path,
+            start_time: *start_time,
+            summary: analysis.to_summary(),
+            peaks: &analysis.peak_performances,
+        };
+        fs::write(
+            dir.join(format!("{}.json", name)),
+            serde_json::to_string_pretty(&record).map_err(io::Error::from)?,
+        )?;
+        used_names.insert(name);
+    }
+    Ok(())
+}
+
+/// Read a baseline saved by --save-stats
+fn read_baseline(path: &Path) -> Result<Baseline, AnalyserError> {
+    let Baseline { date, daily_stats } =
+        serde_json::from_str(&fs::read_to_string(path)?).map_err(io::Error::from)?;
+    Ok(Baseline::new(date, daily_stats))
+}
+
+/// Change of today's CTL, ATL and TSB since the day the baseline was saved on
+/// Values are left empty if there are no stats for either of the days.
+fn baseline_table(baseline: &Baseline, todays_stats: Option<&DailyStats>) -> Table {
+    let baseline_stats = baseline.stats();
+    if baseline_stats.is_none() {
+        warn!("The baseline has no stats for {}", baseline.date);
+    }
+    let delta = baseline.delta(todays_stats);
+
+    let mut baseline_table = table![[
+        "",
+        format!("Baseline ({})", baseline.date),
+        "Today",
+        "Delta"
+    ]];
+    let ctl = |stats: &DailyStats| stats.ctl.0;
+    let atl = |stats: &DailyStats| stats.atl.0;
+    let tsb = |stats: &DailyStats| stats.tsb.0;
+    for (label, baseline_value, todays_value, delta) in [
+        (
+            "CTL",
+            baseline_stats.map(ctl),
+            todays_stats.map(ctl),
+            delta.map(|delta| delta.ctl),
+        ),
+        (
+            "ATL",
+            baseline_stats.map(atl),
+            todays_stats.map(atl),
+            delta.map(|delta| delta.atl),
+        ),
+        (
+            "TSB",
+            baseline_stats.map(tsb),
+            todays_stats.map(tsb),
+            delta.map(|delta| delta.tsb),
+        ),
+    ] {
+        baseline_table.add_row(row![
+            label,
+            DisplayableOption(baseline_value.map(|value| format!("{:.0}", value))),
+            DisplayableOption(todays_value.map(|value| format!("{:.0}", value))),
+            DisplayableOption(delta.map(|delta| format!("{:+.0}", delta)))
+        ]);
+    }
+    baseline_table
+}
+
+/// Check if an activity lasted at least a minimum duration
+/// Activities without a duration are only accepted if no minimum is given.
+fn is_long_enough(duration: Option<Duration>, min_duration: Option<u32>) -> bool {
+    min_duration.is_none_or(|min_duration| {
+        duration.is_some_and(|duration| duration >= Duration::seconds(min_duration.into()))
+    })
+}
+
+fn multi_activity(args: MultiActivityArgs, table_style: TableStyle) -> Result<(), AnalyserError> {
+    let MultiActivityArgs {
+        path,
+        recursive,
+        from,
+        to,
+        tz,
+        seed_days,
+        sparkline,
+        save_stats,
+        compare_to_baseline,
+        min_duration,
+        format,
+        cache,
+        reports,
+        jobs,
+        verbose,
+    } = args;
+    if format == OutputFormat::Jsonl && compare_to_baseline.is_some() {
+        return Err(AnalyserError::InvalidValue(
+            "--compare-to-baseline is only available with the table output format".to_string(),
+        ));
+    }
+    let measurements = &def_measurements();
+    let cache = cache.map(|dir| AnalysisCache::new(&dir)).transpose()?;
+    let baseline = compare_to_baseline
+        .map(|path| read_baseline(&path))
+        .transpose()?;
+
+    info!("Reading files...");
+    let paths = find_fit_files(&path, recursive)?;
+
+    let power_curve_durations = [
         Duration::seconds(5),
+        Duration::seconds(15),
+        Duration::seconds(30),
         Duration::minutes(1),
+        Duration::minutes(2),
         Duration::minutes(5),
+        Duration::minutes(10),
         Duration::minutes(20),
-    ]);
-    let today = Local::now().date_naive();
+        Duration::hours(1),
+    ];
+    let peak_durations = HashSet::from(power_curve_durations);
+    let today = date_in(&Local::now(), tz);
 
-    let activities_with_analyses = successes
-        .par_iter()
-        .map(|activity| {
-            let date: Option<NaiveDate> = activity.start_time.map(|t| t.naive_utc().into());
-            let ftp = date.and_then(|d| measurements.get_actual_ftp(&d));
-            let fthr = date.and_then(|d| measurements.get_actual_fthr(&d));
-            (
-                activity,
-                ActivityAnalysis::from_activity(&ftp, &fthr, activity, &peak_durations),
-            )
-        })
+    info!("Analysing files...");
+    let progress = ProgressCounter::new(paths.len());
+
+    #[allow(clippy::type_complexity)]
+    let (successes, failures): (
+        Vec<(PathBuf, Result<Option<CacheEntry>, AnalyserError>)>,
+        Vec<(PathBuf, Result<Option<CacheEntry>, AnalyserError>)>,
+    ) = rayon::ThreadPoolBuilder::new()
+        // Zero threads means the number of CPUs
+        .num_threads(jobs.map_or(0, usize::from))
+        .build()
+        .map_err(io::Error::other)?
+        .install(|| {
+            // Each thread reads a single file at a time, so open files are bounded by the jobs
+            paths
+                .into_par_iter()
+                .map(|path| {
+                    // Activities outside of the date range or too short are skipped before their
+                    // analysis
+                    let entry = analyse_file(
+                        &path,
+                        cache.as_ref(),
+                        tz,
+                        measurements,
+                        &peak_durations,
+                        |header| {
+                            in_date_range(header.start_time, tz, from, to)
+                                && is_long_enough(header.duration, min_duration)
+                        },
+                    );
+                    progress.increment();
+                    (path, entry)
+                })
+                .partition(|(_, entry)| entry.is_ok())
+        });
+    progress.finish();
+
+    let successes = successes
+        .into_iter()
+        .map(|(path, entry)| (path, entry.unwrap()))
+        .collect::<Vec<_>>();
+    let mut failures = failures
+        .into_iter()
+        .map(|(path, entry)| (path, entry.unwrap_err()))
+        .collect::<Vec<_>>();
+    failures.sort_by(|(path_a, _), (path_b, _)| path_a.cmp(path_b));
+
+    info!(
+        "Successfully parsed {} files, failed to read {}.",
+        successes.len(),
+        failures.len()
+    );
+    if verbose {
+        failures
+            .iter()
+            .for_each(|(path, error)| warn!("{}: {}", path.display(), error));
+    }
+
+    let mut activities_with_analyses = successes
+        .into_iter()
+        .filter_map(|(path, entry)| Some((path, entry?)))
+        .map(|(path, entry)| (path, entry.start_time, entry.analysis))
         .collect::<Vec<_>>();
+    // Listings, reports and ties between best efforts follow this order, so the output is
+    // stable between runs regardless of the file system and thread scheduling
+    activities_with_analyses.sort_by(|(path_a, start_time_a, _), (path_b, start_time_b, _)| {
+        (start_time_a, path_a).cmp(&(start_time_b, path_b))
+    });
+
+    if let Some(reports) = reports {
+        write_reports(&reports, &activities_with_analyses)?;
+    }
 
     let daily_tss_data = activities_with_analyses
         .iter()
-        .filter_map(|(activity, analysis)| {
+        .filter_map(|(_, start_time, analysis)| {
             Some(DailyTSS(
-                activity.start_time?.date_naive(),
-                analysis.tss.or(analysis.hr_tss)?,
+                date_in(start_time.as_ref()?, tz),
+                analysis.combined_tss()?,
             ))
         })
         .collect::<Vec<_>>();
     let sorted_daily_tss = SortedDailyTSS::from_unsorted(&daily_tss_data, None);
-    let daily_stats = DailyStats::calc_rolling(sorted_daily_tss, None);
+    let seed = seed_days.and_then(|days| DailyStats::seed(&sorted_daily_tss, days));
+    let daily_stats = DailyStats::calc_rolling(sorted_daily_tss, seed.as_ref());
+    if let Some(save_stats) = save_stats {
+        let baseline = Baseline::new(today, daily_stats.clone());
+        fs::write(
+            save_stats,
+            serde_json::to_string_pretty(&baseline).map_err(io::Error::from)?,
+        )?;
+    }
 
-    let todays_stats = daily_stats
+    if format == OutputFormat::Jsonl {
+        for (path, start_time, analysis) in &activities_with_analyses {
+            let record = ActivityRecord {
+                path,
+                start_time: *start_time,
+                summary: analysis.to_summary(),
+                peaks: &analysis.peak_performances,
+            };
+            println!(
+                "{}",
+                serde_json::to_string(&record).map_err(io::Error::from)?
+            );
+        }
+        return Ok(());
+    }
+
+    let todays_stats = DailyStats::at(&daily_stats, today);
+
+    let total_power_zones = activities_with_analyses
         .iter()
-        .find(|daily_stats| daily_stats.date == today);
+        .filter_map(|(_, _, analysis)| analysis.power_zones)
+        .fold([Duration::zero(); POWER_ZONE_COUNT], |acc, zones| {
+            std::array::from_fn(|i| acc[i] + zones[i])
+        });
 
     let mut pm_table = table![
         ["CTL", DisplayableOption(todays_stats.map(|x| x.ctl))],
         ["ATL", DisplayableOption(todays_stats.map(|x| x.atl))],
-        ["TSB", DisplayableOption(todays_stats.map(|x| x.tsb))]
+        ["TSB", DisplayableOption(todays_stats.map(|x| x.tsb))],
+        [
+            "Form",
+            DisplayableOption(todays_stats.map(|x| x.form_zone()))
+        ],
+        ["7 day TSS", weekly_tss_total(&daily_stats, today)],
+        ["28 day TSS", monthly_tss_total(&daily_stats, today)],
+        [
+            "Polarization Index",
+            DisplayableOption(
+                polarization_index(&total_power_zones).map(|index| format!("{:.2}", index))
+            )
+        ]
     ];
 
-    pm_table.set_format(*format::consts::FORMAT_NO_LINESEP_WITH_TITLE);
-    pm_table.printstd();
+    print_table(&mut pm_table, table_style);
 
-    let power_peaks =
-        activities_with_analyses
+    if let Some(baseline) = baseline {
+        print_table(&mut baseline_table(&baseline, todays_stats), table_style);
+    }
+
+    if sparkline {
+        let range_stats = daily_stats
             .iter()
-            .fold(HashMap::new(), |mut acc, (_, analysis)| {
-                analysis
-                    .peak_performances
-                    .power
-                    .iter()
-                    .for_each(|(duration, next_val)| {
-                        let next_val = next_val.value;
-                        acc.entry(duration)
-                            .and_modify(|val| {
-                                if *val < next_val {
-                                    *val = next_val
-                                }
-                            })
-                            .or_insert(next_val);
-                    });
-                acc
-            });
+            .filter(|stats| {
+                from.is_none_or(|from| stats.date >= from) && stats.date <= to.unwrap_or(today)
+            })
+            .collect::<Vec<_>>();
+        let series: [(&str, Vec<f64>); 3] = [
+            ("CTL", range_stats.iter().map(|stats| stats.ctl.0).collect()),
+            ("ATL", range_stats.iter().map(|stats| stats.atl.0).collect()),
+            ("TSB", range_stats.iter().map(|stats| stats.tsb.0).collect()),
+        ];
+        for (label, values) in series {
+            if values.is_empty() {
+                continue;
+            }
+            let min = values.iter().copied().fold(f64::INFINITY, f64::min);
+            let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+            println!(
+                "{} {} ({:.1} to {:.1})",
+                label,
+                render_sparkline(&values),
+                min,
+                max
+            );
+        }
+    }
+
+    let best_efforts = activities_with_analyses.iter().fold(
+        HashMap::new(),
+        |mut acc: HashMap<Duration, BestEffort>, (path, _, analysis)| {
+            analysis
+                .peak_performances
+                .power
+                .iter()
+                .for_each(|(duration, peak)| {
+                    let next_effort = BestEffort {
+                        power: peak.value,
+                        path,
+                        time: peak.timestamps.0,
+                    };
+                    acc.entry(*duration)
+                        .and_modify(|effort| {
+                            if effort.power < next_effort.power {
+                                *effort = next_effort
+                            }
+                        })
+                        .or_insert(next_effort);
+                });
+            acc
+        },
+    );
+    let power_peaks = best_efforts
+        .iter()
+        .map(|(duration, effort)| (duration, effort.power))
+        .collect::<HashMap<_, _>>();
     let speed_peaks =
         activities_with_analyses
             .iter()
-            .fold(HashMap::new(), |mut acc, (_, analysis)| {
+            .fold(HashMap::new(), |mut acc, (_, _, analysis)| {
                 analysis
                     .peak_performances
                     .speed
@@ -341,7 +1519,7 @@ fn multi_activity(path: PathBuf, verbose: bool) -> Result<(), Error> {
     let heart_rate_peaks =
         activities_with_analyses
             .iter()
-            .fold(HashMap::new(), |mut acc, (_, analysis)| {
+            .fold(HashMap::new(), |mut acc, (_, _, analysis)| {
                 analysis
                     .peak_performances
                     .heart_rate
@@ -359,10 +1537,134 @@ fn multi_activity(path: PathBuf, verbose: bool) -> Result<(), Error> {
                 acc
             });
 
-    peaks_table(&power_peaks, &speed_peaks, &heart_rate_peaks).printstd();
+    print_table(
+        &mut peaks_table(&power_peaks, &speed_peaks, &heart_rate_peaks),
+        table_style,
+    );
+
+    let power_curve = season_power_curve(
+        activities_with_analyses
+            .iter()
+            .map(|(_, _, analysis)| analysis),
+        &power_curve_durations,
+    );
+    print_table(&mut power_curve_table(&power_curve), table_style);
+
+    print_table(
+        &mut best_efforts_table(&power_curve_durations, &best_efforts, tz),
+        table_style,
+    );
+
+    let season_summary = SeasonSummary::from_analyses(
+        activities_with_analyses
+            .iter()
+            .map(|(_, _, analysis)| analysis),
+    );
+    let mut season_table = table![
+        ["Activities", season_summary.activity_count],
+        ["Total TSS", season_summary.total_tss],
+        ["Total distance", season_summary.total_distance],
+        [
+            "Total elevation gain",
+            format!("{:.0} m", season_summary.total_elevation_gain.0)
+        ],
+        ["Total work", season_summary.total_work],
+        [
+            "Median duration",
+            DisplayableOption(season_summary.median_duration)
+        ]
+    ];
+    print_table(&mut season_table, table_style);
+
+    let eftp_history = eftp_history(
+        activities_with_analyses
+            .iter()
+            .filter_map(|(_, start_time, analysis)| {
+                Some((date_in(start_time.as_ref()?, tz), analysis))
+            }),
+        EFTP_WINDOW_DAYS,
+    );
+    if !eftp_history.is_empty() {
+        let mut eftp_table = table![["Date", "eFTP"]];
+        eftp_history
+            .iter()
+            .enumerate()
+            // Only list the changes of eFTP
+            .filter(|(index, (_, eftp))| *index == 0 || eftp_history[index - 1].1 != *eftp)
+            .for_each(|(_, (date, eftp))| {
+                eftp_table.add_row(row![date, eftp]);
+            });
+        print_table(&mut eftp_table, table_style);
+    }
 
     if verbose {
         println!("{:#?}", daily_stats);
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod main_tests {
+    use super::*;
+
+    #[test]
+    fn csv_fields_are_quoted() {
+        assert_eq!(csv_field("123"), "123");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("line 1\nline 2"), "\"line 1\nline 2\"");
+    }
+
+    #[test]
+    fn csv_has_a_row_per_record() {
+        let activity = Activity::from_path("./tests/fixtures/Activity.fit").unwrap();
+        let mut csv = Vec::new();
+        write_csv(
+            &activity,
+            &["timestamp".to_string(), "power".to_string()],
+            &mut csv,
+        )
+        .unwrap();
+
+        let csv = String::from_utf8(csv).unwrap();
+        let record_count = activity
+            .records
+            .iter()
+            .filter(|record| record.kind() == MesgNum::Record)
+            .count();
+        assert_eq!(csv.lines().next(), Some("timestamp,power"));
+        assert_eq!(csv.lines().count(), record_count + 1);
+    }
+
+    #[test]
+    fn markdown_table_escapes_cells() {
+        let table = table![["Name", "Value"], ["a|b", "line 1\nline 2"], ["c"]];
+        assert_eq!(
+            markdown_table(&table),
+            "| Name | Value |\n|---|---|\n| a\\|b | line 1<br>line 2 |\n| c |  |\n"
+        );
+        assert_eq!(markdown_table(&Table::new()), "");
+    }
+
+    #[test]
+    fn activity_record_keeps_all_peaks() {
+        let path = Path::new("./tests/fixtures/Activity.fit");
+        let activity = Activity::from_path(path).unwrap();
+        let analysis = ActivityAnalysisBuilder::new()
+            .peak_durations(HashSet::from([Duration::seconds(30), Duration::minutes(5)]))
+            .build(&activity);
+        let record = ActivityRecord {
+            path,
+            start_time: activity.start_time,
+            summary: analysis.to_summary(),
+            peaks: &analysis.peak_performances,
+        };
+
+        let json = serde_json::to_value(&record).unwrap();
+        assert!(json["peaks"]["power"]["30"]["value"].is_i64());
+        assert_eq!(
+            json["peaks"]["power"]["300"]["value"],
+            json["peak_power_5min"]
+        );
+    }
+}
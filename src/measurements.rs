@@ -1,5 +1,6 @@
+use crate::error::AnalyserError;
 use derive_more::{Add, Sub, Sum};
-use fitparser::{Error, Value};
+use fitparser::Value;
 use std::cmp::Ordering;
 use std::fmt::{Display, Formatter};
 
@@ -17,8 +18,10 @@ impl Average for i64 {
     {
         let elems = elems.as_ref();
 
+        // Summing as i128, so large values cannot overflow
         if !elems.is_empty() {
-            Some(elems.iter().sum::<i64>() / (elems.len() as i64))
+            let sum = elems.iter().map(|elem| *elem as i128).sum::<i128>();
+            Some((sum / elems.len() as i128) as i64)
         } else {
             None
         }
@@ -37,12 +40,18 @@ impl Display for Power {
 }
 
 impl TryFrom<Value> for Power {
-    type Error = Error;
-    fn try_from(value: Value) -> Result<Self, Error> {
+    type Error = AnalyserError;
+    fn try_from(value: Value) -> Result<Self, AnalyserError> {
         Ok(Self(value.try_into()?))
     }
 }
 
+impl From<Power> for f64 {
+    fn from(Power(power): Power) -> f64 {
+        power as f64
+    }
+}
+
 impl Average for Power {
     fn average<I>(elems: I) -> Option<Self>
     where
@@ -70,12 +79,27 @@ impl Display for Work {
 }
 
 impl TryFrom<Value> for Work {
-    type Error = Error;
-    fn try_from(value: Value) -> Result<Self, Error> {
+    type Error = AnalyserError;
+    fn try_from(value: Value) -> Result<Self, AnalyserError> {
         Ok(Self(value.try_into()?))
     }
 }
 
+impl Average for Work {
+    fn average<I>(elems: I) -> Option<Self>
+    where
+        I: AsRef<[Self]>,
+    {
+        let elems = elems.as_ref();
+        if !elems.is_empty() {
+            let avg = elems.iter().map(|Self(inner)| inner).sum::<f64>() / (elems.len() as f64);
+            Some(Self(avg))
+        } else {
+            None
+        }
+    }
+}
+
 impl From<Power> for Work {
     fn from(value: Power) -> Work {
         Work(value.0 as f64 / 1000.0)
@@ -94,12 +118,18 @@ impl Display for HeartRate {
 }
 
 impl TryFrom<Value> for HeartRate {
-    type Error = Error;
-    fn try_from(value: Value) -> Result<Self, Error> {
+    type Error = AnalyserError;
+    fn try_from(value: Value) -> Result<Self, AnalyserError> {
         Ok(Self(value.try_into()?))
     }
 }
 
+impl From<HeartRate> for f64 {
+    fn from(HeartRate(heart_rate): HeartRate) -> f64 {
+        heart_rate as f64
+    }
+}
+
 impl Average for HeartRate {
     fn average<I>(elems: I) -> Option<Self>
     where
@@ -116,7 +146,7 @@ impl Average for HeartRate {
 }
 
 /// Cadence data in rpm
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Ord, PartialOrd)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Cadence(pub i64);
 
@@ -127,15 +157,63 @@ impl Display for Cadence {
 }
 
 impl TryFrom<Value> for Cadence {
-    type Error = Error;
-    fn try_from(value: Value) -> Result<Self, Error> {
+    type Error = AnalyserError;
+    fn try_from(value: Value) -> Result<Self, AnalyserError> {
         Ok(Self(value.try_into()?))
     }
 }
 
+impl Average for Cadence {
+    fn average<I>(elems: I) -> Option<Self>
+    where
+        I: AsRef<[Self]>,
+    {
+        let elems = elems.as_ref();
+        if !elems.is_empty() {
+            let avg = elems.iter().map(|Self(inner)| inner).sum::<i64>() / (elems.len() as i64);
+            Some(Self(avg))
+        } else {
+            None
+        }
+    }
+}
+
+/// Cadence in rpm with sub-rpm precision, combining the `cadence` and `fractional_cadence`
+/// fields of FIT records
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FractionalCadence(pub f64);
+
+impl Display for FractionalCadence {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "{:.1} RPM", self.0)
+    }
+}
+
+impl From<Cadence> for FractionalCadence {
+    fn from(Cadence(cadence): Cadence) -> Self {
+        Self(cadence as f64)
+    }
+}
+
+impl Average for FractionalCadence {
+    fn average<I>(elems: I) -> Option<Self>
+    where
+        I: AsRef<[Self]>,
+    {
+        let elems = elems.as_ref();
+        if !elems.is_empty() {
+            let avg = elems.iter().map(|Self(inner)| inner).sum::<f64>() / (elems.len() as f64);
+            Some(Self(avg))
+        } else {
+            None
+        }
+    }
+}
+
 /// Speed data in m/s
 /// Default display will convert it to km/h
-#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+#[derive(Debug, Clone, Copy)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Speed(pub f64);
 
@@ -145,19 +223,37 @@ impl Display for Speed {
     }
 }
 
+impl PartialEq for Speed {
+    /// Equality consistent with the total ordering, so NaN equals itself
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
 impl Eq for Speed {}
 
-#[allow(clippy::derive_ord_xor_partial_ord)]
 impl Ord for Speed {
-    /// Boldly we claim that floats are always comparable.
+    /// Total ordering of floats, so NaN values cannot cause a panic
     fn cmp(&self, other: &Self) -> Ordering {
-        self.partial_cmp(other).unwrap()
+        self.0.total_cmp(&other.0)
+    }
+}
+
+impl PartialOrd for Speed {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl From<Speed> for f64 {
+    fn from(Speed(speed): Speed) -> f64 {
+        speed
     }
 }
 
 impl TryFrom<Value> for Speed {
-    type Error = Error;
-    fn try_from(value: Value) -> Result<Self, Error> {
+    type Error = AnalyserError;
+    fn try_from(value: Value) -> Result<Self, AnalyserError> {
         Ok(Self(value.try_into()?))
     }
 }
@@ -177,6 +273,59 @@ impl Average for Speed {
     }
 }
 
+/// Pace in seconds per km
+/// Default display will format it as minutes and seconds per km
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Pace(pub f64);
+
+impl Pace {
+    /// Convert a speed to pace, None if not moving
+    pub fn from_speed(Speed(speed): Speed) -> Option<Self> {
+        (speed > 0.0).then(|| Self(1000.0 / speed))
+    }
+}
+
+impl Display for Pace {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        let seconds = self.0.round() as i64;
+        write!(f, "{}:{:02} /km", seconds / 60, seconds % 60)
+    }
+}
+
+/// Distance in meters
+/// Default display will convert it to km
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Add, Sum)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Distance(pub f64);
+
+impl Display for Distance {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "{:.2} km", self.0 / 1000.0)
+    }
+}
+
+impl TryFrom<Value> for Distance {
+    type Error = AnalyserError;
+    fn try_from(value: Value) -> Result<Self, AnalyserError> {
+        Ok(Self(value.try_into()?))
+    }
+}
+
+/// Gradient of the terrain as rise over run (0.1 is a 10% climb)
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Grade(pub f64);
+
+impl TryFrom<Value> for Grade {
+    type Error = AnalyserError;
+    /// FIT files record grade in percent
+    fn try_from(value: Value) -> Result<Self, AnalyserError> {
+        let percent: f64 = value.try_into()?;
+        Ok(Self(percent / 100.0))
+    }
+}
+
 /// Altitude in meters
 #[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
@@ -189,8 +338,8 @@ impl Display for Altitude {
 }
 
 impl TryFrom<Value> for Altitude {
-    type Error = Error;
-    fn try_from(value: Value) -> Result<Self, Error> {
+    type Error = AnalyserError;
+    fn try_from(value: Value) -> Result<Self, AnalyserError> {
         Ok(Self(value.try_into()?))
     }
 }
@@ -207,12 +356,27 @@ impl Display for AltitudeDiff {
 }
 
 impl TryFrom<Value> for AltitudeDiff {
-    type Error = Error;
-    fn try_from(value: Value) -> Result<Self, Error> {
+    type Error = AnalyserError;
+    fn try_from(value: Value) -> Result<Self, AnalyserError> {
         Ok(Self(value.try_into()?))
     }
 }
 
+impl Average for AltitudeDiff {
+    fn average<I>(elems: I) -> Option<Self>
+    where
+        I: AsRef<[Self]>,
+    {
+        let elems = elems.as_ref();
+        if !elems.is_empty() {
+            let avg = elems.iter().map(|Self(inner)| inner).sum::<f64>() / (elems.len() as f64);
+            Some(Self(avg))
+        } else {
+            None
+        }
+    }
+}
+
 impl From<Altitude> for AltitudeDiff {
     fn from(value: Altitude) -> AltitudeDiff {
         AltitudeDiff(value.0)
@@ -231,8 +395,233 @@ impl Display for Weight {
 }
 
 impl TryFrom<Value> for Weight {
-    type Error = Error;
-    fn try_from(value: Value) -> Result<Self, Error> {
+    type Error = AnalyserError;
+    fn try_from(value: Value) -> Result<Self, AnalyserError> {
+        Ok(Self(value.try_into()?))
+    }
+}
+
+/// Left/right power balance as the contribution of the right leg in percent
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LeftRightBalance(pub u8);
+
+impl LeftRightBalance {
+    /// Contribution of the left leg in percent
+    pub fn left(&self) -> u8 {
+        100 - self.0
+    }
+
+    /// Contribution of the right leg in percent
+    pub fn right(&self) -> u8 {
+        self.0
+    }
+}
+
+impl Display for LeftRightBalance {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "{}% / {}%", self.left(), self.right())
+    }
+}
+
+impl TryFrom<Value> for LeftRightBalance {
+    type Error = AnalyserError;
+    /// The highest bit of the raw FIT value flags that the percentage belongs to the right leg,
+    /// the lower 7 bits hold the percentage itself. Samples without the flag can't be attributed
+    /// to either side, so they are rejected.
+    fn try_from(value: Value) -> Result<Self, AnalyserError> {
+        let raw: i64 = match value {
+            // fitparser names the raw value 128 (right, 0%) instead of returning the number
+            Value::String(ref variant) if variant == "right" => 0x80,
+            value => value.try_into()?,
+        };
+
+        if raw & 0x80 == 0 {
+            return Err(AnalyserError::InvalidValue(
+                "left/right balance is not attributed to the right leg".to_string(),
+            ));
+        }
+
+        let right = raw & 0x7F;
+        if right > 100 {
+            return Err(AnalyserError::InvalidValue(format!(
+                "invalid left/right balance percentage: {}",
+                right
+            )));
+        }
+
+        Ok(Self(right as u8))
+    }
+}
+
+impl Average for LeftRightBalance {
+    fn average<I>(elems: I) -> Option<Self>
+    where
+        I: AsRef<[Self]>,
+    {
+        let elems = elems.as_ref();
+        if !elems.is_empty() {
+            let avg =
+                elems.iter().map(|Self(inner)| *inner as i64).sum::<i64>() / (elems.len() as i64);
+            Some(Self(avg as u8))
+        } else {
+            None
+        }
+    }
+}
+
+/// Torque effectiveness of one leg in percent
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TorqueEffectiveness(pub f64);
+
+impl Display for TorqueEffectiveness {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "{:.1}%", self.0)
+    }
+}
+
+impl TryFrom<Value> for TorqueEffectiveness {
+    type Error = AnalyserError;
+    fn try_from(value: Value) -> Result<Self, AnalyserError> {
+        Ok(Self(value.try_into()?))
+    }
+}
+
+impl Average for TorqueEffectiveness {
+    fn average<I>(elems: I) -> Option<Self>
+    where
+        I: AsRef<[Self]>,
+    {
+        let elems = elems.as_ref();
+        if !elems.is_empty() {
+            let avg = elems.iter().map(|Self(inner)| inner).sum::<f64>() / (elems.len() as f64);
+            Some(Self(avg))
+        } else {
+            None
+        }
+    }
+}
+
+/// Pedal smoothness of one leg in percent
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PedalSmoothness(pub f64);
+
+impl Display for PedalSmoothness {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "{:.1}%", self.0)
+    }
+}
+
+impl TryFrom<Value> for PedalSmoothness {
+    type Error = AnalyserError;
+    fn try_from(value: Value) -> Result<Self, AnalyserError> {
         Ok(Self(value.try_into()?))
     }
 }
+
+impl Average for PedalSmoothness {
+    fn average<I>(elems: I) -> Option<Self>
+    where
+        I: AsRef<[Self]>,
+    {
+        let elems = elems.as_ref();
+        if !elems.is_empty() {
+            let avg = elems.iter().map(|Self(inner)| inner).sum::<f64>() / (elems.len() as f64);
+            Some(Self(avg))
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod measurements_tests {
+    use super::*;
+    use crate::peak::Peak;
+    use chrono::{DateTime, Duration, Local};
+
+    #[test]
+    fn pace_from_speed() {
+        let pace = Pace::from_speed(Speed(4.0)).unwrap();
+        assert_eq!(pace, Pace(250.0));
+        assert_eq!(pace.to_string(), "4:10 /km");
+        assert_eq!(Pace::from_speed(Speed(0.0)), None);
+    }
+
+    #[test]
+    fn left_right_balance_decoding() {
+        let LeftRightBalance(right) = Value::SInt64(0x80 | 52).try_into().unwrap();
+        assert_eq!(right, 52);
+
+        let LeftRightBalance(right) = Value::String("right".to_string()).try_into().unwrap();
+        assert_eq!(right, 0);
+    }
+
+    #[test]
+    fn unattributed_left_right_balance_is_rejected() {
+        let balance: Result<LeftRightBalance, AnalyserError> = Value::SInt64(52).try_into();
+        assert!(balance.is_err());
+
+        let balance: Result<LeftRightBalance, AnalyserError> = Value::SInt64(0x80 | 120).try_into();
+        assert!(balance.is_err());
+    }
+
+    #[test]
+    fn left_right_balance_average() {
+        let balance: LeftRightBalance =
+            Average::average([LeftRightBalance(48), LeftRightBalance(52)]).unwrap();
+        assert_eq!((balance.left(), balance.right()), (50, 50));
+    }
+
+    #[test]
+    fn large_i64_average() {
+        assert_eq!(Average::average([i64::MAX, i64::MAX]), Some(i64::MAX));
+    }
+
+    #[test]
+    fn work_average() {
+        assert_eq!(Average::average([Work(1.0), Work(2.0)]), Some(Work(1.5)));
+        assert_eq!(Work::average(Vec::<Work>::new()), None);
+    }
+
+    #[test]
+    fn altitude_diff_average() {
+        assert_eq!(
+            Average::average([AltitudeDiff(10.0), AltitudeDiff(-4.0)]),
+            Some(AltitudeDiff(3.0))
+        );
+        assert_eq!(AltitudeDiff::average(Vec::<AltitudeDiff>::new()), None);
+    }
+
+    #[test]
+    fn nan_speed_is_comparable() {
+        let speeds = [Speed(1.0), Speed(f64::NAN), Speed(2.0)];
+        assert_eq!(
+            speeds.iter().filter(|speed| **speed < Speed(3.0)).count(),
+            2
+        );
+        assert!(speeds.iter().max().unwrap().0.is_nan());
+        assert_eq!(Speed(f64::NAN), Speed(f64::NAN));
+
+        let start = "2012-12-12T12:12:12Z".parse::<DateTime<Local>>().unwrap();
+        let timestamps = (0..6)
+            .map(|s| start + Duration::seconds(s))
+            .collect::<Vec<_>>();
+        let speed_data = [1.0, 2.0, 3.0, f64::NAN, 4.0, 5.0]
+            .into_iter()
+            .map(Speed)
+            .zip(&timestamps)
+            .collect::<Vec<_>>();
+        let peak = Peak::from_measurement_records(&speed_data, Duration::seconds(1)).unwrap();
+        assert!(peak.value.0.is_nan());
+        assert_eq!(
+            peak.value,
+            speed_data.iter().map(|(speed, _)| *speed).max().unwrap()
+        );
+        let peak = Peak::from_measurement_records(&speed_data[..3], Duration::seconds(2)).unwrap();
+        assert_eq!(peak.value, Speed(2.5));
+        assert_eq!(peak.timestamps, (timestamps[1], timestamps[2]));
+    }
+}
@@ -1,7 +1,16 @@
-use crate::measurements::{Altitude, AltitudeDiff, Average, HeartRate, Power, Work};
-use chrono::{Duration, NaiveDate};
+use crate::activity::MOVING_TIME_GAP_THRESHOLD;
+use crate::measurements::{
+    Altitude, AltitudeDiff, Average, Cadence, Distance, Grade, HeartRate, Pace, Power, Speed,
+    Weight, Work,
+};
+use crate::peak::Peak;
+use crate::zones::{time_in_zones, ZoneModel};
+use chrono::{DateTime, Duration, Local, NaiveDate};
 use derive_more::{Add, AddAssign, Display};
+use std::collections::HashMap;
+use std::f64::consts::PI;
 use std::fmt::{Display, Formatter};
+use std::ops::Range;
 // use crate::activity::Activity;
 
 /// Accumulated Training Stress Scores for a day
@@ -28,63 +37,139 @@ impl TSS {
         )
     }
 
+    /// Calculate Training Stress Score if all inputs are available, or tell which one is missing
+    /// A missing normalized power is reported as missing power data.
+    pub fn try_calculate(
+        ftp: Option<&Power>,
+        duration: Option<&Duration>,
+        normalized_power: Option<&Power>,
+    ) -> Result<TSS, TssUnavailableReason> {
+        let normalized_power = normalized_power.ok_or(TssUnavailableReason::MissingPower)?;
+        let ftp = ftp.ok_or(TssUnavailableReason::MissingFtp)?;
+        let duration = duration.ok_or(TssUnavailableReason::MissingDuration)?;
+        Ok(TSS::calculate(ftp, duration, normalized_power))
+    }
+
     /// Calculate user specific Heart Rate Training Stress Score
-    pub fn calculate_hr_tss(fthr: &HeartRate, heart_rate_data: &[HeartRate]) -> TSS {
-        let HeartRate(fthr) = fthr;
-        let zones = (
-            fthr * 73 / 100,
-            fthr * 77 / 100,
-            fthr * 81 / 100,
-            fthr * 85 / 100,
-            fthr * 89 / 100,
-            fthr * 93 / 100,
-            fthr,
-            fthr * 103 / 100,
-            fthr * 106 / 100,
-        );
+    pub fn calculate_hr_tss(
+        fthr: &HeartRate,
+        heart_rate_data: &[HeartRate],
+        model: &HrTssModel,
+    ) -> TSS {
+        let points = time_in_zones(heart_rate_data, *fthr, &model.zones)
+            .iter()
+            .zip(model.zone_weights)
+            .map(|(duration, weight)| duration.num_seconds() as f64 * weight)
+            .sum::<f64>();
 
-        let zones_count = heart_rate_data.iter().fold(
-            (0, 0, 0, 0, 0, 0, 0, 0, 0, 0),
-            |mut acc, HeartRate(hr)| {
-                if hr < &zones.0 {
-                    acc.0 += 1;
-                } else if hr < &zones.1 {
-                    acc.1 += 1;
-                } else if hr < &zones.2 {
-                    acc.2 += 1;
-                } else if hr < &zones.3 {
-                    acc.3 += 1;
-                } else if hr < &zones.4 {
-                    acc.4 += 1;
-                } else if hr < &zones.5 {
-                    acc.5 += 1;
-                } else if hr < zones.6 {
-                    acc.6 += 1;
-                } else if hr < &zones.7 {
-                    acc.7 += 1;
-                } else if hr < &zones.8 {
-                    acc.8 += 1;
-                } else {
-                    acc.9 += 1;
-                };
-                acc
-            },
-        );
+        TSS((points / 3_600.0).round() as i64)
+    }
+}
+
+/// The input missing for calculating Training Stress Score
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TssUnavailableReason {
+    /// The activity has no power data
+    MissingPower,
+    /// The power data is shorter than the normalized power window
+    InsufficientPower,
+    /// No FTP was measured, and none could be estimated as there is no 20 minute effort
+    MissingFtp,
+    /// The duration of the activity is unknown
+    MissingDuration,
+}
 
-        TSS((zones_count.0 * 20
-            + zones_count.1 * 30
-            + zones_count.2 * 40
-            + zones_count.3 * 50
-            + zones_count.4 * 60
-            + zones_count.5 * 75
-            + zones_count.6 * 100
-            + zones_count.7 * 105
-            + zones_count.8 * 110
-            + zones_count.9 * 120)
-            / 3600)
+impl Display for TssUnavailableReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        let reason = match self {
+            TssUnavailableReason::MissingPower => "the activity has no power data",
+            TssUnavailableReason::InsufficientPower => {
+                "the power data is too short for normalized power"
+            }
+            TssUnavailableReason::MissingFtp => {
+                "no FTP is set and there is no 20 minute effort to estimate it from"
+            }
+            TssUnavailableReason::MissingDuration => "the duration of the activity is unknown",
+        };
+        write!(f, "{}", reason)
     }
 }
 
+/// Number of heart rate zones used by hrTSS
+pub const HR_ZONE_COUNT: usize = 10;
+
+/// Heart rate zone model of hrTSS
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HrTssModel {
+    /// Heart rate zones in fractions of FTHr, zones beyond HR_ZONE_COUNT are ignored
+    pub zones: ZoneModel,
+    /// hrTSS points per hour spent in each heart rate zone
+    pub zone_weights: [f64; HR_ZONE_COUNT],
+}
+
+impl Default for HrTssModel {
+    fn default() -> Self {
+        Self {
+            zones: ZoneModel::hr_tss(),
+            zone_weights: [
+                20.0, 30.0, 40.0, 50.0, 60.0, 75.0, 100.0, 105.0, 110.0, 120.0,
+            ],
+        }
+    }
+}
+
+/// Calculate the time spent in each heart rate zone, assuming one sample per second
+pub fn heart_rate_zone_distribution(
+    fthr: &HeartRate,
+    heart_rate_data: &[HeartRate],
+    model: &HrTssModel,
+) -> [Duration; HR_ZONE_COUNT] {
+    zone_array(time_in_zones(heart_rate_data, *fthr, &model.zones))
+}
+
+/// Number of power zones in the Coggan model
+pub const POWER_ZONE_COUNT: usize = 7;
+
+/// Calculate the time spent in each power zone, assuming one sample per second
+/// Zones beyond POWER_ZONE_COUNT are ignored, see `ZoneModel::coggan_power` for the default model.
+pub fn power_zone_distribution(
+    ftp: &Power,
+    power_data: &[Power],
+    model: &ZoneModel,
+) -> [Duration; POWER_ZONE_COUNT] {
+    zone_array(time_in_zones(power_data, *ftp, model))
+}
+
+/// Fit the times spent in zones to a fixed number of zones, missing zones are zero
+fn zone_array<const N: usize>(zones: Vec<Duration>) -> [Duration; N] {
+    std::array::from_fn(|index| zones.get(index).copied().unwrap_or_else(Duration::zero))
+}
+
+/// Calculate the Polarization Index (Treff et al.) of a power zone distribution
+/// Coggan zones are grouped into a three zone model: below 76% FTP (zones 1-2), 76-105% FTP
+/// (zones 3-4) and above (zones 5-7). Values above 2.0 indicate polarized training.
+/// Returns None if there is no time spent in the middle zone or no data at all.
+pub fn polarization_index(power_zones: &[Duration; POWER_ZONE_COUNT]) -> Option<f64> {
+    let seconds = power_zones.map(|duration| duration.num_seconds() as f64);
+    let total = seconds.iter().sum::<f64>();
+    if total <= 0.0 {
+        return None;
+    }
+    let low = (seconds[0] + seconds[1]) / total;
+    let threshold = (seconds[2] + seconds[3]) / total;
+    // Without any high intensity time, the share of it is substituted with 0.01
+    let high = match (seconds[4] + seconds[5] + seconds[6]) / total {
+        high if high > 0.0 => high,
+        _ => 0.01,
+    };
+    if threshold <= 0.0 || low <= 0.0 {
+        return None;
+    }
+    Some((low / threshold * high * 100.0).log10())
+}
+
 /// Calculate training load with a given decay and impact constant
 fn calc_training_load(
     decay_const: i64,
@@ -194,41 +279,823 @@ impl Display for VI {
     }
 }
 
+/// Relative Intensity, the xPower based equivalent of Intensity Factor
+#[derive(Clone, Copy, PartialEq, PartialOrd, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RI(pub f64);
+
+impl RI {
+    /// Calculate Relative Intensity
+    pub fn calculate(ftp: &Power, xpower: &Power) -> Self {
+        let Power(ftp) = *ftp;
+        let Power(xpower) = *xpower;
+
+        Self(xpower as f64 / ftp as f64)
+    }
+}
+
+impl Display for RI {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "{:.2}", self.0)
+    }
+}
+
+/// Duration of the peak power FTP is estimated from
+pub const FTP_ESTIMATE_DURATION: Duration = Duration::seconds(20 * 60);
+
+/// Estimate FTP as 95% of the 20 minute peak power, for athletes without a measured FTP
+pub fn estimate_ftp(power_data_with_timestamps: &[(Power, &DateTime<Local>)]) -> Option<Power> {
+    let peak = Peak::from_measurement_records(power_data_with_timestamps, FTP_ESTIMATE_DURATION)?;
+    Some(estimate_ftp_from_peak(peak.value))
+}
+
+/// Estimate FTP from a 20 minute peak power
+pub fn estimate_ftp_from_peak(Power(peak_power): Power) -> Power {
+    Power(peak_power * 95 / 100)
+}
+
+/// Duration of the peak power VO2max is estimated from
+pub const VO2MAX_ESTIMATE_DURATION: Duration = Duration::seconds(5 * 60);
+
+/// Estimate VO2max in ml/kg/min from a 5 minute peak power
+/// Uses the regression of Sitko et al. (2022): VO2max = 16.6 + 8.87 * 5 minute power in W/kg.
+/// The model was fitted on trained road cyclists doing a maximal 5 minute test, so it is only a
+/// rough guide for other athletes, and it underestimates VO2max if the peak was not a maximal
+/// effort. Returns None for non-positive weights.
+pub fn estimate_vo2max_from_peak(Power(peak_power): Power, Weight(weight): Weight) -> Option<f64> {
+    (weight > 0.0).then(|| 16.6 + 8.87 * peak_power as f64 / weight)
+}
+
 /// Calculate total work
 pub fn calc_total_work(power_data: &[Power]) -> Work {
     power_data.iter().map(|power| Work::from(*power)).sum()
 }
 
-/// Calculate Normalized Power
-pub fn calc_normalized_power(power_data: &Vec<Power>) -> Option<Power> {
-    // Returning simple average, if data size doesn't hit threshold
-    if power_data.len() < 30 {
-        return Average::average(power_data);
+/// Typical gross efficiency of cycling, the fraction of the metabolic energy turned into work
+pub const GROSS_EFFICIENCY: f64 = 0.24;
+
+/// Kilojoules in a kilocalorie
+const KJ_PER_KCAL: f64 = 4.184;
+
+/// Estimate the energy expenditure in kcal from the mechanical work done
+/// At the typical gross efficiency this is close to 1 kcal per kJ of work.
+pub fn estimate_calories(Work(work): Work) -> i64 {
+    (work / KJ_PER_KCAL / GROSS_EFFICIENCY).round() as i64
+}
+
+/// Power below which the athlete is considered to be coasting (not pedaling)
+pub const COASTING_POWER_THRESHOLD: Power = Power(5);
+
+/// Calculate the percentage of power samples below the coasting threshold
+/// A high coasting percentage explains a high Variability Index on stop-and-go rides.
+pub fn calc_coasting_percentage(power_data: &[Power]) -> Option<f64> {
+    if power_data.is_empty() {
+        return None;
     }
+    let coasting = power_data
+        .iter()
+        .filter(|power| **power < COASTING_POWER_THRESHOLD)
+        .count();
+    Some(coasting as f64 / power_data.len() as f64 * 100.0)
+}
 
-    let avg: i64 = Average::average(
-        rolling_averages(power_data, 30)
-            .iter()
-            .map(|Power(x)| x.pow(4))
-            .collect::<Vec<i64>>(),
-    )?;
+/// Speed below which the athlete is considered to be stopped, 1.8 km/h
+pub const MOVING_SPEED_THRESHOLD: Speed = Speed(0.5);
+
+/// Average speed of the samples at or above the threshold, like the average speed of head units
+/// Plain averages include the stopped samples, giving the overall speed instead.
+pub fn moving_average_speed(speed_data: &[Speed], Speed(threshold): Speed) -> Option<Speed> {
+    let moving = speed_data
+        .iter()
+        .filter(|Speed(speed)| *speed >= threshold)
+        .copied()
+        .collect::<Vec<_>>();
+    Average::average(moving)
+}
+
+/// Default rolling average window of Normalized Power
+pub const NP_WINDOW: Duration = Duration::seconds(30);
+
+/// Calculate Normalized Power of 1 Hz power data
+/// The samples are timestamped one second apart, so the same windows are used as for
+/// timestamped data.
+pub fn calc_normalized_power(power_data: &[Power]) -> Option<Power> {
+    let start = DateTime::<Local>::default();
+    let timestamps = (0..power_data.len() as i64)
+        .map(|second| start + Duration::seconds(second))
+        .collect::<Vec<_>>();
+    let power_data_with_timestamps = power_data
+        .iter()
+        .copied()
+        .zip(timestamps.iter())
+        .collect::<Vec<_>>();
+
+    calc_normalized_power_with_window(&power_data_with_timestamps, NP_WINDOW)
+}
+
+/// Calculate Normalized Power with a rolling average window based on the timestamps of the
+/// samples, so data with a variable sample rate (smart recording) is smoothed correctly
+pub fn calc_normalized_power_with_window(
+    power_data: &[(Power, &DateTime<Local>)],
+    window: Duration,
+) -> Option<Power> {
+    let rolling_averages = rolling_averages_by_time(power_data, window);
+
+    // Returning simple average, if data doesn't cover a single window
+    if rolling_averages.is_empty() {
+        return Average::average(
+            power_data
+                .iter()
+                .map(|(power, _)| *power)
+                .collect::<Vec<_>>(),
+        );
+    }
+
+    fourth_power_mean_root(&rolling_averages)
+}
+
+/// Gradients outside of this range are clamped, as the cost of running model is only fitted
+/// to the -45% to +45% range
+const MAX_GRADE: f64 = 0.45;
+
+/// Energy cost of running on a gradient in J/kg/m (Minetti et al., 2002)
+fn running_cost(Grade(grade): Grade) -> f64 {
+    let i = grade.clamp(-MAX_GRADE, MAX_GRADE);
+    155.4 * i.powi(5) - 30.4 * i.powi(4) - 43.3 * i.powi(3) + 46.3 * i.powi(2) + 19.5 * i + 3.6
+}
+
+/// Calculate Normalized Graded Pace of a run
+/// Each speed sample is first adjusted to the equivalent speed on flat ground, by the ratio of
+/// the energy cost of running on the gradient of the sample to the cost on the flat. The adjusted
+/// speed is then normalized the same way as Normalized Power: NP_WINDOW rolling averages are
+/// raised to the fourth power, averaged and the fourth root is taken. Speed and grade samples are
+/// matched by timestamp, samples without a grade at the same timestamp are skipped.
+pub fn calc_ngp(
+    speed_data: &[(Speed, &DateTime<Local>)],
+    grade_data: &[(Grade, &DateTime<Local>)],
+) -> Option<Pace> {
+    let flat_cost = running_cost(Grade(0.0));
+    let grades = grade_data
+        .iter()
+        .map(|(grade, timestamp)| (*timestamp, *grade))
+        .collect::<HashMap<_, _>>();
+    let adjusted_speed = speed_data
+        .iter()
+        .filter_map(|(Speed(speed), timestamp)| {
+            let grade = grades.get(timestamp)?;
+            Some((Speed(speed * running_cost(*grade) / flat_cost), *timestamp))
+        })
+        .collect::<Vec<_>>();
+
+    let rolling_averages = rolling_averages_by_time(&adjusted_speed, NP_WINDOW);
+
+    // Returning simple average, if data doesn't cover a single window
+    if rolling_averages.is_empty() {
+        return Pace::from_speed(Average::average(
+            adjusted_speed
+                .iter()
+                .map(|(speed, _)| *speed)
+                .collect::<Vec<_>>(),
+        )?);
+    }
+
+    let mean_fourth_power = rolling_averages
+        .iter()
+        .map(|Speed(speed)| speed.powi(4))
+        .sum::<f64>()
+        / rolling_averages.len() as f64;
+    Pace::from_speed(Speed(mean_fourth_power.powf(0.25)))
+}
+
+/// Time constant of the exponentially weighted moving average of xPower
+pub const XPOWER_TIME_CONSTANT: Duration = Duration::seconds(25);
+
+/// Calculate xPower (Skiba)
+/// Unlike Normalized Power, which smooths power with a 30 second rolling average, xPower uses a
+/// 25 second exponentially weighted moving average, weighting each sample by the time elapsed
+/// since the previous one. Both then take the fourth root of the average fourth power, so on
+/// steady efforts they converge to the average power. The moving average is seeded with the first
+/// sample.
+pub fn calc_xpower(power_data: &[(Power, &DateTime<Local>)]) -> Option<Power> {
+    let ((Power(first_power), first_timestamp), rest) = power_data.split_first()?;
+    let time_constant = XPOWER_TIME_CONSTANT.num_seconds() as f64;
+
+    let (_, _, sum, count) = rest.iter().fold(
+        (*first_power as f64, *first_timestamp, 0.0, 0),
+        |(smoothed, prev_timestamp, sum, count), (Power(power), timestamp)| {
+            let elapsed = (**timestamp - *prev_timestamp).num_seconds() as f64;
+            let weight = 1.0 - (-elapsed / time_constant).exp();
+            let smoothed = smoothed + (*power as f64 - smoothed) * weight;
+            (smoothed, *timestamp, sum + smoothed.powi(4), count + 1)
+        },
+    );
+
+    if count == 0 {
+        return Some(Power(*first_power));
+    }
+    Some(Power((sum / count as f64).powf(0.25) as i64))
+}
+
+/// Fourth root of the average of the fourth powers
+fn fourth_power_mean_root(power_data: &[Power]) -> Option<Power> {
+    if power_data.is_empty() {
+        return None;
+    }
+    // Fourth powers are accumulated as i128, as their sum can overflow i64 on long activities
+    let sum = power_data
+        .iter()
+        .map(|Power(x)| (*x as i128).pow(4))
+        .sum::<i128>();
+    let avg = sum / power_data.len() as i128;
 
     let result = (avg as f64).powf(0.25) as i64;
     Some(Power(result))
 }
 
 /// Calculate rolling averages of a set window size
+/// Returns an empty vector if the window size is zero or larger than the data.
 pub fn rolling_averages<I, T>(data: T, size: usize) -> Vec<I>
 where
     T: AsRef<[I]>,
     I: Average,
 {
+    if size == 0 {
+        return Vec::new();
+    }
     data.as_ref()
         .windows(size)
-        .map(|window| Average::average(window).unwrap())
+        .filter_map(Average::average)
         .collect()
 }
 
+/// Sliding windows of a time span over timestamped samples, ending at each sample
+/// FIT timestamps have a resolution of one second, so a window of 30 seconds includes samples
+/// less than 30 seconds before the last one. Windows are bounded by time rather than by the
+/// number of samples, so sparse samples (smart recording) still fill a window. Only windows fully
+/// covered by the data are yielded: ones straddling a gap longer than MOVING_TIME_GAP_THRESHOLD
+/// (e.g. a pause), or not yet covered since the start of the data or the last gap, are skipped.
+pub fn time_windows<'a, 'b, T>(
+    data: &'a [(T, &'b DateTime<Local>)],
+    window: Duration,
+) -> impl Iterator<Item = &'a [(T, &'b DateTime<Local>)]> {
+    let mut start = 0;
+    // First sample after the last gap
+    let mut run_start = 0;
+
+    data.iter()
+        .enumerate()
+        .filter_map(move |(end, (_, timestamp))| {
+            if window <= Duration::zero() {
+                return None;
+            }
+            if end > 0 && **timestamp - *data[end - 1].1 > MOVING_TIME_GAP_THRESHOLD {
+                run_start = end;
+            }
+            while **timestamp - *data[start].1 >= window {
+                start += 1;
+            }
+            // The window is covered either by its own samples, or by a sample right before it
+            // carrying over into the window
+            let covered = start > run_start
+                || (start == run_start
+                    && **timestamp - *data[start].1 >= window - Duration::seconds(1));
+            covered.then(|| &data[start..=end])
+        })
+}
+
+/// Calculate rolling averages of a time window, ending at each sample
+/// See `time_windows` for how the windows are selected.
+pub fn rolling_averages_by_time<I>(data: &[(I, &DateTime<Local>)], window: Duration) -> Vec<I>
+where
+    I: Average + Copy,
+{
+    time_windows(data, window)
+        .filter_map(|samples| {
+            Average::average(samples.iter().map(|(value, _)| *value).collect::<Vec<_>>())
+        })
+        .collect()
+}
+
+/// Longest span of data the mean-maximal power curve is calculated for, longer spans point to
+/// bogus timestamps
+pub const MAX_MEAN_MAX_SPAN: Duration = Duration::days(1);
+
+/// Calculate the mean-maximal power curve at one second resolution, up to the length of the data
+/// Index i of the curve is the best average power over i + 1 seconds. Samples are placed on a one
+/// second grid by their timestamps, seconds without a sample (e.g. pauses) count as zero power.
+/// Data spanning more than MAX_MEAN_MAX_SPAN gives an empty curve.
+/// Every window of every duration is checked, taking O(n^2) time for n seconds of data. A running
+/// maximum over prefix sums doesn't give the best window of every length, and no exact
+/// sub-quadratic algorithm is known for it (it is as hard as a (max, +) convolution). Window sums
+/// are differences of prefix sums though, so each check is a single subtraction: a five hour ride
+/// takes about 1.6 * 10^8 of them, well under a second in release builds.
+pub fn mean_max_curve(power_data: &[(Power, &DateTime<Local>)]) -> Vec<Power> {
+    let timestamps = power_data.iter().map(|(_, timestamp)| **timestamp);
+    let (Some(first), Some(last)) = (timestamps.clone().min(), timestamps.max()) else {
+        return Vec::new();
+    };
+    if last - first > MAX_MEAN_MAX_SPAN {
+        return Vec::new();
+    }
+    let length = (last - first).num_seconds() as usize + 1;
+
+    let mut grid = vec![0; length];
+    for (Power(power), timestamp) in power_data {
+        grid[(**timestamp - first).num_seconds() as usize] = *power;
+    }
+    let prefix_sums = std::iter::once(0)
+        .chain(grid.iter().scan(0, |sum, power| {
+            *sum += power;
+            Some(*sum)
+        }))
+        .collect::<Vec<i64>>();
+
+    (1..=length)
+        .map(|duration| {
+            let best_sum = (duration..=length)
+                .map(|end| prefix_sums[end] - prefix_sums[end - duration])
+                .max()
+                .unwrap_or(0);
+            Power(best_sum / duration as i64)
+        })
+        .collect()
+}
+
+/// Calculate the ratio of mean power and mean heart rate in consecutive time windows
+/// An increasing heart rate at the same power (a decreasing ratio) shows cardiac drift.
+/// Only samples with both power and heart rate are used. Windows straddling a gap in the data
+/// (or not fully covered by it) are skipped. Each ratio is paired with the start of its window.
+pub fn hr_power_ratio_series(
+    power_data: &[(Power, &DateTime<Local>)],
+    heart_rate_data: &[(HeartRate, &DateTime<Local>)],
+    window: Duration,
+) -> Vec<(DateTime<Local>, f64)> {
+    let heart_rates = heart_rate_data
+        .iter()
+        .map(|(heart_rate, timestamp)| (*timestamp, *heart_rate))
+        .collect::<HashMap<_, _>>();
+    let aligned = power_data
+        .iter()
+        .filter_map(|(power, timestamp)| Some((*power, *heart_rates.get(timestamp)?, **timestamp)))
+        .collect::<Vec<_>>();
+
+    let Some((_, _, first_timestamp)) = aligned.first().copied() else {
+        return Vec::new();
+    };
+    if window.num_seconds() <= 0 {
+        return Vec::new();
+    }
+    let window_index = |timestamp: &DateTime<Local>| {
+        (*timestamp - first_timestamp).num_seconds() / window.num_seconds()
+    };
+
+    aligned
+        .chunk_by(|(_, _, a), (_, _, b)| window_index(a) == window_index(b))
+        .filter_map(|samples| {
+            let start = first_timestamp + window * window_index(&samples[0].2) as i32;
+            let last_second = start + window - Duration::seconds(1);
+            let timestamps = std::iter::once(start)
+                .chain(samples.iter().map(|(_, _, timestamp)| *timestamp))
+                .chain(std::iter::once(last_second))
+                .collect::<Vec<_>>();
+            if timestamps
+                .windows(2)
+                .any(|pair| pair[1] - pair[0] > MOVING_TIME_GAP_THRESHOLD)
+            {
+                return None;
+            }
+
+            let Power(power) = Average::average(
+                samples
+                    .iter()
+                    .map(|(power, _, _)| *power)
+                    .collect::<Vec<_>>(),
+            )?;
+            let HeartRate(heart_rate) = Average::average(
+                samples
+                    .iter()
+                    .map(|(_, heart_rate, _)| *heart_rate)
+                    .collect::<Vec<_>>(),
+            )?;
+            if heart_rate == 0 {
+                return None;
+            }
+            Some((start, power as f64 / heart_rate as f64))
+        })
+        .collect()
+}
+
+/// Minimum number of samples with both power and heart rate to relate them to each other
+pub const MIN_CORRELATION_SAMPLES: usize = 300;
+
+/// Linear relation between power and heart rate over an activity
+/// Tracked over weeks, more power at the same heart rate (a rising slope) shows an improving
+/// aerobic fitness.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PowerHeartRateRelation {
+    /// Pearson correlation coefficient between power and heart rate
+    pub correlation: f64,
+    /// Slope of the least squares fit of power to heart rate, in Watts per bpm
+    pub slope: f64,
+    /// Power of the least squares fit at 0 bpm, in Watts
+    pub intercept: f64,
+}
+
+/// Calculate the correlation and linear fit between power and heart rate samples with matching
+/// timestamps
+/// None if there are fewer than MIN_CORRELATION_SAMPLES aligned samples, or either series is
+/// constant.
+pub fn calc_power_heart_rate_relation(
+    power_data: &[(Power, &DateTime<Local>)],
+    heart_rate_data: &[(HeartRate, &DateTime<Local>)],
+) -> Option<PowerHeartRateRelation> {
+    let heart_rates = heart_rate_data
+        .iter()
+        .map(|(heart_rate, timestamp)| (*timestamp, *heart_rate))
+        .collect::<HashMap<_, _>>();
+    let aligned = power_data
+        .iter()
+        .filter_map(|(Power(power), timestamp)| {
+            let HeartRate(heart_rate) = heart_rates.get(timestamp)?;
+            Some((*heart_rate as f64, *power as f64))
+        })
+        .collect::<Vec<_>>();
+    if aligned.len() < MIN_CORRELATION_SAMPLES {
+        return None;
+    }
+
+    let count = aligned.len() as f64;
+    let mean_heart_rate = aligned.iter().map(|(x, _)| x).sum::<f64>() / count;
+    let mean_power = aligned.iter().map(|(_, y)| y).sum::<f64>() / count;
+    let (covariance, heart_rate_variance, power_variance) = aligned.iter().fold(
+        (0.0, 0.0, 0.0),
+        |(covariance, x_variance, y_variance), (x, y)| {
+            let dx = x - mean_heart_rate;
+            let dy = y - mean_power;
+            (
+                covariance + dx * dy,
+                x_variance + dx * dx,
+                y_variance + dy * dy,
+            )
+        },
+    );
+    if heart_rate_variance == 0.0 || power_variance == 0.0 {
+        return None;
+    }
+
+    let slope = covariance / heart_rate_variance;
+    Some(PowerHeartRateRelation {
+        correlation: covariance / (heart_rate_variance * power_variance).sqrt(),
+        slope,
+        intercept: mean_power - slope * mean_heart_rate,
+    })
+}
+
+/// Fractions of pedaling time spent in each quadrant of a quadrant analysis
+/// The quadrants are split by the pedal force and velocity of riding at FTP with threshold cadence:
+/// - I: high force, high velocity
+/// - II: high force, low velocity
+/// - III: low force, low velocity
+/// - IV: low force, high velocity
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct QuadrantAnalysis {
+    pub q1: f64,
+    pub q2: f64,
+    pub q3: f64,
+    pub q4: f64,
+}
+
+/// Circumferential pedal velocity (m/s) for a cadence and a crank length (m)
+fn pedal_velocity(Cadence(cadence): &Cadence, crank_length: f64) -> f64 {
+    *cadence as f64 * crank_length * 2.0 * PI / 60.0
+}
+
+/// Calculate (circumferential pedal velocity (m/s), average effective pedal force (N)) points
+/// from power and cadence samples with matching timestamps, using a crank length in meters.
+/// Samples without pedaling are skipped.
+pub fn calc_force_velocity(
+    power_data: &[(Power, &DateTime<Local>)],
+    cadence_data: &[(Cadence, &DateTime<Local>)],
+    crank_length: f64,
+) -> Vec<(f64, f64)> {
+    let cadence_by_timestamp = cadence_data
+        .iter()
+        .map(|(cadence, timestamp)| (*timestamp, cadence))
+        .collect::<HashMap<_, _>>();
+
+    power_data
+        .iter()
+        .filter_map(|(Power(power), timestamp)| {
+            let velocity = pedal_velocity(cadence_by_timestamp.get(timestamp)?, crank_length);
+            if velocity <= 0.0 {
+                return None;
+            }
+            Some((velocity, *power as f64 / velocity))
+        })
+        .collect()
+}
+
+/// Calculate the quadrant analysis of force/velocity points
+pub fn calc_quadrant_analysis(
+    force_velocity: &[(f64, f64)],
+    ftp: &Power,
+    threshold_cadence: &Cadence,
+    crank_length: f64,
+) -> Option<QuadrantAnalysis> {
+    if force_velocity.is_empty() {
+        return None;
+    }
+
+    let Power(ftp) = ftp;
+    let threshold_velocity = pedal_velocity(threshold_cadence, crank_length);
+    let threshold_force = *ftp as f64 / threshold_velocity;
+
+    let counts = force_velocity
+        .iter()
+        .fold([0; 4], |mut acc, (velocity, force)| {
+            let quadrant = match (*force >= threshold_force, *velocity >= threshold_velocity) {
+                (true, true) => 0,
+                (true, false) => 1,
+                (false, false) => 2,
+                (false, true) => 3,
+            };
+            acc[quadrant] += 1;
+            acc
+        });
+    let [q1, q2, q3, q4] = counts.map(|count| count as f64 / force_velocity.len() as f64);
+
+    Some(QuadrantAnalysis { q1, q2, q3, q4 })
+}
+
+/// Dips below the threshold power up to this long don't end an effort, so e.g. a gear change
+/// or a short descent doesn't split an interval
+pub const INTERVAL_DIP_TOLERANCE: Duration = Duration::seconds(10);
+
+/// Whether an interval is an effort or recovery
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum IntervalKind {
+    On,
+    Off,
+}
+
+/// A segment of an activity detected by `detect_intervals`
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Interval {
+    pub kind: IntervalKind,
+    pub start: DateTime<Local>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "crate::serialization::duration_seconds")
+    )]
+    pub duration: Duration,
+    pub average_power: Power,
+    pub normalized_power: Option<Power>,
+}
+
+impl Interval {
+    /// Create an interval from a range of the power samples
+    fn from_samples(
+        kind: IntervalKind,
+        power_data: &[(Power, &DateTime<Local>)],
+        range: Range<usize>,
+    ) -> Option<Self> {
+        let samples = power_data.get(range.clone())?;
+        let (_, start) = samples.first()?;
+        Some(Self {
+            kind,
+            start: **start,
+            duration: samples_duration(power_data, range.start, range.end - 1),
+            average_power: Average::average(
+                samples.iter().map(|(power, _)| *power).collect::<Vec<_>>(),
+            )?,
+            normalized_power: calc_normalized_power_with_window(samples, NP_WINDOW),
+        })
+    }
+}
+
+/// Time covered by a single sample
+/// A sample lasts until the next one, or as long as the interval before it at the end of the
+/// series or before a gap. Intervals longer than MOVING_TIME_GAP_THRESHOLD are gaps, not sample
+/// spacing.
+fn sample_duration<T>(data: &[(T, &DateTime<Local>)], index: usize) -> Duration {
+    let until_next = data.get(index + 1).map(|(_, next)| **next - *data[index].1);
+    let since_previous = index
+        .checked_sub(1)
+        .map(|previous| *data[index].1 - *data[previous].1);
+    until_next
+        .into_iter()
+        .chain(since_previous)
+        .find(|spacing| *spacing <= MOVING_TIME_GAP_THRESHOLD)
+        .unwrap_or_else(Duration::zero)
+}
+
+/// Time covered by the samples from `start` to `end` (inclusive), see sample_duration
+fn samples_duration<T>(data: &[(T, &DateTime<Local>)], start: usize, end: usize) -> Duration {
+    *data[end].1 - *data[start].1 + sample_duration(data, end)
+}
+
+/// Segment a power series into efforts at or above a threshold power and recovery between them
+/// An effort only ends once power stays below the threshold for longer than
+/// INTERVAL_DIP_TOLERANCE, and efforts shorter than `min_duration` are treated as recovery.
+/// The returned intervals cover the whole series in order, alternating between on and off.
+pub fn detect_intervals(
+    power_data: &[(Power, &DateTime<Local>)],
+    threshold: Power,
+    min_duration: Duration,
+) -> Vec<Interval> {
+    // Index ranges of the efforts, inclusive at both ends
+    let mut efforts: Vec<(usize, usize)> = Vec::new();
+    let mut current: Option<(usize, usize)> = None;
+    for (index, (power, timestamp)) in power_data.iter().enumerate() {
+        if *power >= threshold {
+            current = Some(current.map_or((index, index), |(start, _)| (start, index)));
+        } else if let Some((start, last_above)) = current {
+            if **timestamp - *power_data[last_above].1 > INTERVAL_DIP_TOLERANCE {
+                efforts.push((start, last_above));
+                current = None;
+            }
+        }
+    }
+    efforts.extend(current);
+    efforts.retain(|(start, end)| samples_duration(power_data, *start, *end) >= min_duration);
+
+    let mut intervals = Vec::new();
+    let mut next_start = 0;
+    for (start, end) in efforts {
+        intervals.extend(Interval::from_samples(
+            IntervalKind::Off,
+            power_data,
+            next_start..start,
+        ));
+        intervals.extend(Interval::from_samples(
+            IntervalKind::On,
+            power_data,
+            start..end + 1,
+        ));
+        next_start = end + 1;
+    }
+    intervals.extend(Interval::from_samples(
+        IntervalKind::Off,
+        power_data,
+        next_start..power_data.len(),
+    ));
+    intervals
+}
+
+/// Maximum difference between the average output of the two halves of an activity in percent,
+/// that still counts as an even split
+pub const EVEN_SPLIT_TOLERANCE: f64 = 2.0;
+
+/// Pacing of an activity by comparing its first and second half
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Split {
+    /// The second half was slower
+    Positive,
+    Even,
+    /// The second half was faster
+    Negative,
+}
+
+impl Display for Split {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        let split = match self {
+            Split::Positive => "Positive split",
+            Split::Even => "Even split",
+            Split::Negative => "Negative split",
+        };
+        write!(f, "{}", split)
+    }
+}
+
+/// Pacing of an activity, see calc_pacing
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Pacing {
+    pub split: Split,
+    /// Change of the average output (power or speed) of the second half compared to the first
+    /// half in percent, positive if the second half was faster
+    pub change_percentage: f64,
+}
+
+impl Display for Pacing {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "{} ({:+.1}%)", self.split, self.change_percentage)
+    }
+}
+
+/// Compare the average output (power or speed) of the first and second half of an activity
+/// Each sample is weighted by the time it covers (see sample_duration), and the halves are split
+/// at the midpoint of that time, so neither pauses nor a changing recording interval shift the
+/// midpoint or skew the averages. None if either half covers no time or the first half averages
+/// zero.
+pub fn calc_pacing<T>(data: &[(T, &DateTime<Local>)]) -> Option<Pacing>
+where
+    T: Copy + Into<f64>,
+{
+    let weighted = (0..data.len())
+        .map(|index| {
+            let (value, _) = data[index];
+            (
+                value.into(),
+                sample_duration(data, index).num_milliseconds() as f64,
+            )
+        })
+        .collect::<Vec<_>>();
+    let midpoint = weighted.iter().map(|(_, weight)| weight).sum::<f64>() / 2.0;
+
+    let (first_half, second_half): (Vec<_>, Vec<_>) = weighted
+        .iter()
+        .scan(0.0, |elapsed, (value, weight)| {
+            let first_half = *elapsed < midpoint;
+            *elapsed += weight;
+            Some((first_half, (*value, *weight)))
+        })
+        .partition(|(first_half, _)| *first_half);
+    let average = |samples: &[(bool, (f64, f64))]| {
+        let time = samples.iter().map(|(_, (_, weight))| weight).sum::<f64>();
+        (time > 0.0).then(|| {
+            samples
+                .iter()
+                .map(|(_, (value, weight))| value * weight)
+                .sum::<f64>()
+                / time
+        })
+    };
+    let first_average = average(&first_half).filter(|average| *average > 0.0)?;
+    let second_average = average(&second_half)?;
+
+    let change_percentage = (second_average - first_average) / first_average * 100.0;
+    let split = if change_percentage.abs() <= EVEN_SPLIT_TOLERANCE {
+        Split::Even
+    } else if change_percentage > 0.0 {
+        Split::Negative
+    } else {
+        Split::Positive
+    };
+    Some(Pacing {
+        split,
+        change_percentage,
+    })
+}
+
+/// Reconstruct a relative altitude series from distance and grade, for files without altitude
+/// Each sample climbs by its grade times the distance covered since the previous sample. The
+/// series starts at zero, so only the differences are meaningful.
+pub fn altitude_from_grade(distance_grade_data: &[(Distance, Grade)]) -> Vec<Altitude> {
+    let Some((Distance(first_distance), _)) = distance_grade_data.first() else {
+        return Vec::new();
+    };
+    distance_grade_data
+        .iter()
+        .scan(
+            (*first_distance, 0.0),
+            |(previous_distance, altitude), (Distance(distance), Grade(grade))| {
+                *altitude += grade * (distance - *previous_distance);
+                *previous_distance = *distance;
+                Some(Altitude(*altitude))
+            },
+        )
+        .collect()
+}
+
+/// Default minimum altitude change counted towards gain or loss, similar to head units
+pub const ALTITUDE_CHANGE_THRESHOLD: AltitudeDiff = AltitudeDiff(2.0);
+
+/// Calculate altitude gain and altitude loss of an activity, ignoring changes smaller than a
+/// threshold, so barometric noise on flat terrain doesn't accumulate into elevation gain
+/// Altitude is only compared to the last altitude where a change was registered.
+pub fn calc_altitude_changes_with_threshold(
+    altitude_data: &[Altitude],
+    AltitudeDiff(threshold): AltitudeDiff,
+) -> (Option<AltitudeDiff>, Option<AltitudeDiff>) {
+    let Some(Altitude(first)) = altitude_data.first() else {
+        return (None, None);
+    };
+    let (gain, loss, _) = altitude_data.iter().fold(
+        (0.0, 0.0, *first),
+        |(gain, loss, reference), Altitude(altitude)| {
+            let diff = altitude - reference;
+            if diff >= threshold && diff > 0.0 {
+                (gain + diff, loss, *altitude)
+            } else if -diff >= threshold && diff < 0.0 {
+                (gain, loss - diff, *altitude)
+            } else {
+                (gain, loss, reference)
+            }
+        },
+    );
+    let changes = altitude_data.len() > 1;
+    (
+        Some(AltitudeDiff(gain)).filter(|_| changes),
+        Some(AltitudeDiff(loss)).filter(|_| changes),
+    )
+}
+
 /// Calculate altitude gain and altitude loss of an activity
 pub fn calc_altitude_changes(
     altitude_data: &[Altitude],
@@ -270,8 +1137,68 @@ mod activity_analysis_tests {
     use super::*;
     use crate::activity::Activity;
     use assertables::{assert_in_delta, assert_in_delta_as_result};
+    use proptest::collection::vec;
+    use proptest::prelude::*;
     use std::fs::File;
 
+    fn arb_power_data() -> impl Strategy<Value = Vec<Power>> {
+        vec((0..2000i64).prop_map(Power), 0..600)
+    }
+
+    proptest! {
+        #[test]
+        fn np_of_constant_power(power in 0..2000i64, length in 1..3600usize) {
+            let power_data = vec![Power(power); length];
+            prop_assert_eq!(calc_normalized_power(&power_data), Some(Power(power)));
+        }
+    }
+
+    proptest! {
+        /// NP is at least the average of the 30 second rolling averages it is calculated from
+        /// (the power mean inequality). It is not compared to the plain average power, as the
+        /// first and last 29 samples are weighted less by the rolling averages.
+        #[test]
+        fn np_is_at_least_average_power(power_data in arb_power_data()) {
+            let np = calc_normalized_power(&power_data);
+            if power_data.len() < 30 {
+                prop_assert_eq!(np, Average::average(&power_data));
+            } else {
+                let rolling_average = Average::average(rolling_averages(&power_data, 30));
+                prop_assert!(np >= rolling_average);
+            }
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn np_is_monotonic_under_scaling(power_data in arb_power_data(), factor in 1..5i64) {
+            let scaled = power_data
+                .iter()
+                .map(|Power(power)| Power(power * factor))
+                .collect::<Vec<_>>();
+            prop_assert!(calc_normalized_power(&scaled) >= calc_normalized_power(&power_data));
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn timestamped_np_of_1hz_data(power_data in arb_power_data()) {
+            let start = "2012-12-12T12:12:12Z".parse::<DateTime<Local>>().unwrap();
+            let timestamps = (0..power_data.len() as i64)
+                .map(|s| start + Duration::seconds(s))
+                .collect::<Vec<_>>();
+            let power_data_with_timestamps = power_data
+                .iter()
+                .copied()
+                .zip(timestamps.iter())
+                .collect::<Vec<_>>();
+            prop_assert_eq!(
+                calc_normalized_power_with_window(&power_data_with_timestamps, NP_WINDOW),
+                calc_normalized_power(&power_data)
+            );
+        }
+    }
+
     #[test]
     /// Don't panic on small data (less than 30 seconds)
     fn small_data() {
@@ -297,6 +1224,398 @@ mod activity_analysis_tests {
         assert_eq!(calc_normalized_power(&power_data), Some(Power(200)));
     }
 
+    #[test]
+    /// Time based windows match sample based windows on 1 Hz data
+    fn timestamped_np_matches_1hz_np() {
+        let start = "2012-12-12T12:12:12Z".parse::<DateTime<Local>>().unwrap();
+        let timestamps = (0..600)
+            .map(|s| start + Duration::seconds(s))
+            .collect::<Vec<_>>();
+        let power_data = (0..600)
+            .map(|s| Power(150 + (s % 40) * 5))
+            .collect::<Vec<_>>();
+        let power_data_with_timestamps = power_data
+            .iter()
+            .copied()
+            .zip(timestamps.iter())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            calc_normalized_power_with_window(&power_data_with_timestamps, NP_WINDOW),
+            calc_normalized_power(&power_data)
+        );
+    }
+
+    #[test]
+    fn mean_max_curve_matches_rolling_averages() {
+        let start = "2012-12-12T12:12:12Z".parse::<DateTime<Local>>().unwrap();
+        let timestamps = (0..300)
+            .map(|s| start + Duration::seconds(s))
+            .collect::<Vec<_>>();
+        let power_data = (0..300)
+            .map(|s| Power(100 + (s * 37 % 300)))
+            .collect::<Vec<_>>();
+        let power_data_with_timestamps = power_data
+            .iter()
+            .copied()
+            .zip(timestamps.iter())
+            .collect::<Vec<_>>();
+
+        let curve = mean_max_curve(&power_data_with_timestamps);
+        assert_eq!(curve.len(), 300);
+        for (index, best) in curve.iter().enumerate() {
+            let expected = rolling_averages(&power_data, index + 1)
+                .into_iter()
+                .max()
+                .unwrap();
+            assert_eq!(*best, expected);
+        }
+        assert_eq!(mean_max_curve(&[]), Vec::new());
+    }
+
+    #[test]
+    fn mean_max_curve_with_gaps() {
+        let start = "2012-12-12T12:12:12Z".parse::<DateTime<Local>>().unwrap();
+        let timestamps = [0, 1, 4, 5].map(|s| start + Duration::seconds(s));
+        let power_data_with_timestamps = [Power(300), Power(200), Power(400), Power(100)]
+            .into_iter()
+            .zip(timestamps.iter())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            mean_max_curve(&power_data_with_timestamps),
+            vec![
+                Power(400),
+                Power(500 / 2),
+                Power(500 / 3),
+                Power(600 / 4),
+                Power(900 / 5),
+                Power(1000 / 6)
+            ]
+        );
+    }
+
+    #[test]
+    fn mean_max_curve_of_long_rides() {
+        let start = "2012-12-12T12:12:12Z".parse::<DateTime<Local>>().unwrap();
+        // An hour at 300 W, then two hours at 150 W
+        let timestamps = (0..3 * 3600)
+            .map(|s| start + Duration::seconds(s))
+            .collect::<Vec<_>>();
+        let power_data_with_timestamps = timestamps
+            .iter()
+            .enumerate()
+            .map(|(i, timestamp)| (Power(if i < 3600 { 300 } else { 150 }), timestamp))
+            .collect::<Vec<_>>();
+
+        let curve = mean_max_curve(&power_data_with_timestamps);
+        assert_eq!(curve.len(), 3 * 3600);
+        assert_eq!(curve[3599], Power(300));
+        assert_eq!(curve[7199], Power(225));
+        assert_eq!(curve[3 * 3600 - 1], Power(200));
+
+        // A bogus timestamp doesn't blow up the grid
+        let bogus = start + Duration::days(400);
+        let power_data_with_timestamps = [(Power(200), &start), (Power(200), &bogus)];
+        assert_eq!(mean_max_curve(&power_data_with_timestamps), Vec::new());
+    }
+
+    #[test]
+    /// Sparse samples (smart recording) still fill the window by time
+    fn timestamped_rolling_averages_with_gaps() {
+        let start = "2012-12-12T12:12:12Z".parse::<DateTime<Local>>().unwrap();
+        let timestamps = [0, 10, 20, 29, 40].map(|s| start + Duration::seconds(s));
+        let power_data = [100, 200, 300, 400, 500]
+            .into_iter()
+            .map(Power)
+            .zip(timestamps.iter())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            rolling_averages_by_time(&power_data, NP_WINDOW),
+            vec![Power(250), Power(400)]
+        );
+    }
+
+    #[test]
+    fn time_windows_by_timestamps() {
+        let start = "2012-12-12T12:12:12Z".parse::<DateTime<Local>>().unwrap();
+        let timestamps = [0, 1, 2, 3, 20, 21, 22].map(|s| start + Duration::seconds(s));
+        let data = [1, 2, 3, 4, 5, 6, 7]
+            .into_iter()
+            .zip(timestamps.iter())
+            .collect::<Vec<_>>();
+        let values = |window: Duration| {
+            time_windows(&data, window)
+                .map(|samples| samples.iter().map(|(value, _)| *value).collect::<Vec<_>>())
+                .collect::<Vec<_>>()
+        };
+
+        // Gap-free data gives the same windows as counting samples
+        assert_eq!(
+            values(Duration::seconds(3))[..2],
+            data[..4]
+                .windows(3)
+                .map(|samples| samples.iter().map(|(value, _)| *value).collect::<Vec<_>>())
+                .collect::<Vec<_>>()
+        );
+        // Windows after the gap are only yielded once the samples since the gap cover them
+        assert_eq!(
+            values(Duration::seconds(3)),
+            vec![vec![1, 2, 3], vec![2, 3, 4], vec![5, 6, 7]]
+        );
+        // Windows straddling the gap are skipped
+        assert!(values(Duration::seconds(20)).is_empty());
+        assert!(values(Duration::zero()).is_empty());
+    }
+
+    #[test]
+    fn quadrant_analysis() {
+        let start = "2012-12-12T12:12:12Z".parse::<DateTime<Local>>().unwrap();
+        let timestamps = (0..5)
+            .map(|s| start + Duration::seconds(s))
+            .collect::<Vec<_>>();
+        // Threshold is 250 W at 90 RPM
+        let power_data = [400, 400, 100, 100, 300]
+            .into_iter()
+            .map(Power)
+            .zip(timestamps.iter())
+            .collect::<Vec<_>>();
+        let cadence_data = [110, 60, 60, 110, 0]
+            .into_iter()
+            .map(Cadence)
+            .zip(timestamps.iter())
+            .collect::<Vec<_>>();
+
+        let force_velocity = calc_force_velocity(&power_data, &cadence_data, 0.1725);
+        assert_eq!(force_velocity.len(), 4);
+
+        let quadrants =
+            calc_quadrant_analysis(&force_velocity, &Power(250), &Cadence(90), 0.1725).unwrap();
+        assert_eq!(
+            quadrants,
+            QuadrantAnalysis {
+                q1: 0.25,
+                q2: 0.25,
+                q3: 0.25,
+                q4: 0.25
+            }
+        );
+    }
+
+    #[test]
+    /// xPower and Normalized Power converge on a steady effort
+    fn steady_effort_xpower_matches_np() {
+        let start = "2012-12-12T12:12:12Z".parse::<DateTime<Local>>().unwrap();
+        let timestamps = (0..3600)
+            .map(|s| start + Duration::seconds(s))
+            .collect::<Vec<_>>();
+        let power_data = timestamps
+            .iter()
+            .map(|timestamp| (Power(200), timestamp))
+            .collect::<Vec<_>>();
+
+        assert_eq!(calc_xpower(&power_data), Some(Power(200)));
+        assert_eq!(
+            calc_xpower(&power_data),
+            calc_normalized_power_with_window(&power_data, NP_WINDOW)
+        );
+    }
+
+    #[test]
+    fn polarized_training() {
+        let power_zones = [480, 0, 30, 0, 60, 30, 0].map(Duration::seconds);
+        assert_in_delta!(polarization_index(&power_zones).unwrap(), 2.38, 0.01);
+
+        let threshold_only = [0, 0, 600, 0, 0, 0, 0].map(Duration::seconds);
+        assert_eq!(polarization_index(&threshold_only), None);
+        assert_eq!(
+            polarization_index(&[Duration::zero(); POWER_ZONE_COUNT]),
+            None
+        );
+    }
+
+    #[test]
+    fn hr_power_ratio_skips_gaps() {
+        let start = "2012-12-12T12:12:12Z".parse::<DateTime<Local>>().unwrap();
+        // A 20 second pause, longer than MOVING_TIME_GAP_THRESHOLD
+        let timestamps = (0..120)
+            .filter(|s| !(40..60).contains(s))
+            .map(|s| start + Duration::seconds(s))
+            .collect::<Vec<_>>();
+        let power_data = timestamps
+            .iter()
+            .map(|timestamp| (Power(200), timestamp))
+            .collect::<Vec<_>>();
+        let heart_rate_data = timestamps
+            .iter()
+            .map(|timestamp| {
+                let heart_rate = if *timestamp < start + Duration::seconds(60) {
+                    100
+                } else {
+                    125
+                };
+                (HeartRate(heart_rate), timestamp)
+            })
+            .collect::<Vec<_>>();
+
+        let series = hr_power_ratio_series(&power_data, &heart_rate_data, Duration::seconds(30));
+
+        assert_eq!(
+            series,
+            vec![
+                (start, 2.0),
+                (start + Duration::seconds(60), 1.6),
+                (start + Duration::seconds(90), 1.6),
+            ]
+        );
+    }
+
+    #[test]
+    fn detect_on_off_intervals() {
+        let start = "2012-12-12T12:12:12Z".parse::<DateTime<Local>>().unwrap();
+        let timestamps = (0..600)
+            .map(|s| start + Duration::seconds(s))
+            .collect::<Vec<_>>();
+        let power_data = timestamps
+            .iter()
+            .enumerate()
+            .map(|(i, timestamp)| {
+                let power = match i {
+                    // a short dip inside the first effort
+                    120..=124 => 150,
+                    60..=239 => 300,
+                    // a spike too short to be an effort
+                    300..=309 => 400,
+                    360..=479 => 280,
+                    _ => 100,
+                };
+                (Power(power), timestamp)
+            })
+            .collect::<Vec<_>>();
+
+        let intervals = detect_intervals(&power_data, Power(250), Duration::seconds(30));
+        let summary = intervals
+            .iter()
+            .map(|interval| {
+                (
+                    interval.kind,
+                    interval.start - start,
+                    interval.duration.num_seconds(),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            summary,
+            vec![
+                (IntervalKind::Off, Duration::zero(), 60),
+                (IntervalKind::On, Duration::seconds(60), 180),
+                (IntervalKind::Off, Duration::seconds(240), 120),
+                (IntervalKind::On, Duration::seconds(360), 120),
+                (IntervalKind::Off, Duration::seconds(480), 120),
+            ]
+        );
+        assert_eq!(intervals[3].average_power, Power(280));
+        assert_eq!(intervals[3].normalized_power, Some(Power(280)));
+        assert!(detect_intervals(&[], Power(250), Duration::seconds(30)).is_empty());
+    }
+
+    #[test]
+    fn interval_durations_follow_sample_spacing() {
+        let start = "2012-12-12T12:12:12Z".parse::<DateTime<Local>>().unwrap();
+        // Smart recording, a sample every 5 seconds
+        let timestamps = (0..24)
+            .map(|i| start + Duration::seconds(i * 5))
+            .collect::<Vec<_>>();
+        let power_data = timestamps
+            .iter()
+            .enumerate()
+            .map(|(i, timestamp)| {
+                (
+                    Power(if (6..12).contains(&i) { 300 } else { 100 }),
+                    timestamp,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let intervals = detect_intervals(&power_data, Power(250), Duration::seconds(30));
+        let summary = intervals
+            .iter()
+            .map(|interval| (interval.kind, interval.duration.num_seconds()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            summary,
+            vec![
+                (IntervalKind::Off, 30),
+                (IntervalKind::On, 30),
+                (IntervalKind::Off, 60),
+            ]
+        );
+    }
+
+    #[test]
+    fn noisy_flat_altitude() {
+        let altitude_data = (0..600)
+            .map(|i| Altitude(100.0 + [0.0, 0.8, -0.6, 1.2, -1.0][i % 5]))
+            .collect::<Vec<_>>();
+
+        let (raw_gain, _) = calc_altitude_changes(&altitude_data);
+        assert!(raw_gain.unwrap() > AltitudeDiff(300.0));
+
+        let (gain, loss) =
+            calc_altitude_changes_with_threshold(&altitude_data, ALTITUDE_CHANGE_THRESHOLD);
+        assert_eq!(gain, Some(AltitudeDiff(0.0)));
+        assert_eq!(loss, Some(AltitudeDiff(0.0)));
+    }
+
+    #[test]
+    fn altitude_changes_above_threshold() {
+        let altitude_data = [100.0, 101.0, 102.5, 102.0, 110.0, 104.0].map(Altitude);
+        let (gain, loss) =
+            calc_altitude_changes_with_threshold(&altitude_data, ALTITUDE_CHANGE_THRESHOLD);
+        assert_eq!(gain, Some(AltitudeDiff(10.0)));
+        assert_eq!(loss, Some(AltitudeDiff(6.0)));
+    }
+
+    #[test]
+    fn coasting_percentage() {
+        let power_data = [Power(0), Power(4), Power(5), Power(250)];
+        assert_eq!(calc_coasting_percentage(&power_data), Some(50.0));
+        assert_eq!(calc_coasting_percentage(&[]), None);
+    }
+
+    #[test]
+    fn moving_average_speed_excludes_stops() {
+        let speed_data = [Speed(0.0), Speed(0.2), Speed(8.0), Speed(10.0)];
+        assert_eq!(
+            moving_average_speed(&speed_data, MOVING_SPEED_THRESHOLD),
+            Some(Speed(9.0))
+        );
+        assert_eq!(
+            moving_average_speed(&speed_data[..2], MOVING_SPEED_THRESHOLD),
+            None
+        );
+    }
+
+    #[test]
+    fn rolling_averages_window_sizes() {
+        let data = [Power(100), Power(200), Power(300)];
+
+        assert_eq!(rolling_averages(data, 0), Vec::<Power>::new());
+        assert_eq!(rolling_averages(data, 2), vec![Power(150), Power(250)]);
+        assert_eq!(rolling_averages(data, 3), vec![Power(200)]);
+        assert_eq!(rolling_averages(data, 4), Vec::<Power>::new());
+    }
+
+    #[test]
+    /// The sum of fourth powers of a long high power series exceeds i64::MAX
+    fn long_high_power_normalized_power() {
+        let power_data = vec![Power(1500); 2_000_000];
+        assert_eq!(calc_normalized_power(&power_data), Some(Power(1500)));
+    }
+
     #[test]
     fn one_hour_effort_tss() {
         let tss = TSS::calculate(&Power(260), &Duration::hours(1), &Power(260));
@@ -321,6 +1640,92 @@ mod activity_analysis_tests {
         assert_in_delta!(work, 26.0, 0.001);
     }
 
+    #[test]
+    fn heart_rate_zone_boundaries() {
+        let fthr = HeartRate(100);
+        let heart_rate_data = [
+            HeartRate(72),
+            HeartRate(73),
+            HeartRate(99),
+            HeartRate(100),
+            HeartRate(106),
+            HeartRate(180),
+        ];
+
+        let distribution =
+            heart_rate_zone_distribution(&fthr, &heart_rate_data, &HrTssModel::default());
+
+        assert_eq!(distribution[0], Duration::seconds(1));
+        assert_eq!(distribution[1], Duration::seconds(1));
+        assert_eq!(distribution[6], Duration::seconds(1));
+        assert_eq!(distribution[7], Duration::seconds(1));
+        assert_eq!(distribution[9], Duration::seconds(2));
+        assert_eq!(
+            distribution.iter().copied().sum::<Duration>(),
+            Duration::seconds(6)
+        );
+    }
+
+    #[test]
+    fn one_hour_sub_threshold_hr_tss() {
+        let heart_rate_data = vec![HeartRate(170); 3600];
+        let tss = TSS::calculate_hr_tss(&HeartRate(178), &heart_rate_data, &HrTssModel::default());
+        assert_eq!(tss, TSS(100))
+    }
+
+    #[test]
+    /// Fractional points of short efforts are rounded instead of truncated
+    fn sub_hour_effort_hr_tss() {
+        // 1000 seconds at 100 points per hour is 27.8 TSS
+        let heart_rate_data = vec![HeartRate(170); 1000];
+        let tss = TSS::calculate_hr_tss(&HeartRate(178), &heart_rate_data, &HrTssModel::default());
+        assert_eq!(tss, TSS(28))
+    }
+
+    #[test]
+    /// Zone boundaries are not truncated to whole beats
+    fn fractional_hr_zone_boundary() {
+        // 73% of 178 BPM is 129.94 BPM, so 129 BPM still belongs to the first zone
+        let distribution = heart_rate_zone_distribution(
+            &HeartRate(178),
+            &[HeartRate(129)],
+            &HrTssModel::default(),
+        );
+        assert_eq!(distribution[0], Duration::seconds(1));
+    }
+
+    #[test]
+    fn custom_hr_tss_model() {
+        let model = HrTssModel {
+            zone_weights: [0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 50.0, 0.0, 0.0, 0.0],
+            ..HrTssModel::default()
+        };
+        let heart_rate_data = vec![HeartRate(170); 3600];
+        let tss = TSS::calculate_hr_tss(&HeartRate(178), &heart_rate_data, &model);
+        assert_eq!(tss, TSS(50))
+    }
+
+    #[test]
+    fn power_zone_boundaries() {
+        let ftp = Power(200);
+        let power_data = [
+            Power(0),
+            Power(109),
+            Power(110),
+            Power(150),
+            Power(152),
+            Power(200),
+            Power(212),
+            Power(242),
+            Power(302),
+            Power(1000),
+        ];
+
+        let distribution = power_zone_distribution(&ftp, &power_data, &ZoneModel::coggan_power());
+
+        assert_eq!(distribution, [2, 2, 1, 1, 1, 1, 2].map(Duration::seconds));
+    }
+
     // Golden tests
 
     #[test]
@@ -337,7 +1742,7 @@ mod activity_analysis_tests {
         let mut fp = File::open("./tests/fixtures/Activity.fit").unwrap();
         let activity = Activity::from_reader(&mut fp).unwrap();
 
-        let Power(power) = Average::average(&activity.get_data("power")).unwrap();
+        let Power(power) = Average::average(activity.get_data("power")).unwrap();
         assert_eq!(power, 199);
     }
 
@@ -366,7 +1771,7 @@ mod activity_analysis_tests {
     fn activity_file_variability_index() {
         let mut fp = File::open("./tests/fixtures/Activity.fit").unwrap();
         let activity = Activity::from_reader(&mut fp).unwrap();
-        let avg_power = Average::average(&activity.get_data("power")).unwrap();
+        let avg_power = Average::average(activity.get_data("power")).unwrap();
         let np = calc_normalized_power(&activity.get_data("power")).unwrap();
 
         let VI(variability_index) = VI::calculate(&np, &avg_power);
@@ -374,6 +1779,235 @@ mod activity_analysis_tests {
         assert_in_delta!(variability_index, 1.075, 0.0005);
     }
 
+    #[test]
+    fn estimated_ftp() {
+        let start = Local::now();
+        let timestamps = (0..30 * 60)
+            .map(|i| start + Duration::seconds(i))
+            .collect::<Vec<_>>();
+        let power_data = timestamps
+            .iter()
+            .enumerate()
+            .map(|(i, timestamp)| (Power(if i < 20 * 60 { 200 } else { 100 }), timestamp))
+            .collect::<Vec<_>>();
+
+        assert_eq!(estimate_ftp(&power_data), Some(Power(190)));
+        assert_eq!(estimate_ftp(&power_data[..10 * 60]), None);
+    }
+
+    #[test]
+    fn ngp_of_flat_run() {
+        let start = "2012-12-12T12:12:12Z".parse::<DateTime<Local>>().unwrap();
+        let timestamps = (0..600)
+            .map(|s| start + Duration::seconds(s))
+            .collect::<Vec<_>>();
+        let speed_data = timestamps
+            .iter()
+            .map(|timestamp| (Speed(3.0), timestamp))
+            .collect::<Vec<_>>();
+        let grade_data = timestamps
+            .iter()
+            .map(|timestamp| (Grade(0.0), timestamp))
+            .collect::<Vec<_>>();
+
+        let Pace(ngp) = calc_ngp(&speed_data, &grade_data).unwrap();
+        let Pace(average_pace) = Pace::from_speed(Speed(3.0)).unwrap();
+        assert_in_delta!(ngp, average_pace, 0.001);
+    }
+
+    #[test]
+    fn ngp_of_hilly_run() {
+        let start = "2012-12-12T12:12:12Z".parse::<DateTime<Local>>().unwrap();
+        let timestamps = (0..600)
+            .map(|s| start + Duration::seconds(s))
+            .collect::<Vec<_>>();
+        let speed_data = timestamps
+            .iter()
+            .map(|timestamp| (Speed(3.0), timestamp))
+            .collect::<Vec<_>>();
+        let grade_data = timestamps
+            .iter()
+            .enumerate()
+            .map(|(i, timestamp)| (Grade(if i < 300 { 0.05 } else { 0.0 }), timestamp))
+            .collect::<Vec<_>>();
+
+        let Pace(ngp) = calc_ngp(&speed_data, &grade_data).unwrap();
+        let Pace(average_pace) = Pace::from_speed(Speed(3.0)).unwrap();
+        assert!(ngp < average_pace);
+        assert_eq!(calc_ngp(&[], &[]), None);
+    }
+
+    #[test]
+    fn ngp_matches_grades_by_timestamp() {
+        let start = "2012-12-12T12:12:12Z".parse::<DateTime<Local>>().unwrap();
+        let timestamps = (0..600)
+            .map(|s| start + Duration::seconds(s))
+            .collect::<Vec<_>>();
+        // Uphill in the first half, downhill in the second
+        let grade_at = |i: usize| Grade(if i < 300 { 0.1 } else { -0.1 });
+        let speed_data = timestamps
+            .iter()
+            .map(|timestamp| (Speed(3.0), timestamp))
+            .collect::<Vec<_>>();
+        let grade_data = timestamps
+            .iter()
+            .enumerate()
+            .map(|(i, timestamp)| (grade_at(i), timestamp))
+            .collect::<Vec<_>>();
+        // Grade dropping out for a minute shouldn't shift the later grades onto earlier speeds
+        let grade_data_with_dropout = grade_data
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| !(100..160).contains(i))
+            .map(|(_, sample)| *sample)
+            .collect::<Vec<_>>();
+        let speed_data_without_dropout = speed_data
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| !(100..160).contains(i))
+            .map(|(_, sample)| *sample)
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            calc_ngp(&speed_data, &grade_data_with_dropout),
+            calc_ngp(&speed_data_without_dropout, &grade_data),
+        );
+    }
+
+    #[test]
+    fn power_heart_rate_relation() {
+        let start = "2012-12-12T12:12:12Z".parse::<DateTime<Local>>().unwrap();
+        let timestamps = (0..600)
+            .map(|s| start + Duration::seconds(s))
+            .collect::<Vec<_>>();
+        let power_data = timestamps
+            .iter()
+            .enumerate()
+            .map(|(i, timestamp)| (Power(100 + (i as i64 % 20) * 10), timestamp))
+            .collect::<Vec<_>>();
+        // 1 bpm for every 5 W
+        let heart_rate_data = power_data
+            .iter()
+            .map(|(Power(power), timestamp)| (HeartRate(100 + power / 5), *timestamp))
+            .collect::<Vec<_>>();
+
+        let relation = calc_power_heart_rate_relation(&power_data, &heart_rate_data).unwrap();
+        assert_in_delta!(relation.correlation, 1.0, 1e-9);
+        assert_in_delta!(relation.slope, 5.0, 1e-9);
+        assert_in_delta!(relation.intercept, -500.0, 1e-9);
+
+        assert_eq!(
+            calc_power_heart_rate_relation(&power_data[..100], &heart_rate_data),
+            None
+        );
+        let constant_heart_rate = timestamps
+            .iter()
+            .map(|timestamp| (HeartRate(140), timestamp))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            calc_power_heart_rate_relation(&power_data, &constant_heart_rate),
+            None
+        );
+    }
+
+    #[test]
+    fn calories_from_work() {
+        assert_eq!(estimate_calories(Work(1000.0)), 996);
+        assert_eq!(estimate_calories(Work(0.0)), 0);
+    }
+
+    #[test]
+    fn pacing_is_split_by_time() {
+        let start = "2012-12-12T12:12:12Z".parse::<DateTime<Local>>().unwrap();
+        // The second half is recorded every 5 seconds, so it has fewer samples
+        let timestamps = (0..300)
+            .chain((300..=600).step_by(5))
+            .map(|s| start + Duration::seconds(s))
+            .collect::<Vec<_>>();
+        let power_data = timestamps
+            .iter()
+            .map(|timestamp| {
+                let power = if *timestamp < start + Duration::seconds(300) {
+                    200
+                } else {
+                    180
+                };
+                (Power(power), timestamp)
+            })
+            .collect::<Vec<_>>();
+
+        // The last sample lasts for a recording interval too, moving the midpoint to 302.5 s, so
+        // the first half also gets the first 5 s sample at 180 W
+        let pacing = calc_pacing(&power_data).unwrap();
+        assert_eq!(pacing.split, Split::Positive);
+        let first_half = (300.0 * 200.0 + 5.0 * 180.0) / 305.0;
+        assert_in_delta!(
+            pacing.change_percentage,
+            (180.0 - first_half) / first_half * 100.0,
+            1e-9
+        );
+
+        let speed_data = timestamps
+            .iter()
+            .map(|timestamp| (Speed(8.0), timestamp))
+            .collect::<Vec<_>>();
+        assert_eq!(calc_pacing(&speed_data).unwrap().split, Split::Even);
+
+        assert_eq!(calc_pacing::<Power>(&[]), None);
+        assert_eq!(calc_pacing(&power_data[..1]), None);
+    }
+
+    #[test]
+    fn pacing_skips_pauses() {
+        let start = "2012-12-12T12:12:12Z".parse::<DateTime<Local>>().unwrap();
+        // 100 s at 200 W, a 15 minute stop, 100 s at 200 W and 100 s at 180 W
+        let timestamps = (0..100)
+            .chain(1000..1200)
+            .map(|s| start + Duration::seconds(s))
+            .collect::<Vec<_>>();
+        let power_data = timestamps
+            .iter()
+            .map(|timestamp| {
+                let power = if *timestamp < start + Duration::seconds(1100) {
+                    200
+                } else {
+                    180
+                };
+                (Power(power), timestamp)
+            })
+            .collect::<Vec<_>>();
+
+        // The midpoint is 150 s into the moving time, so the second half averages 50 s at
+        // 200 W and 100 s at 180 W
+        let pacing = calc_pacing(&power_data).unwrap();
+        assert_in_delta!(pacing.change_percentage, -20.0 / 3.0, 1e-9);
+    }
+
+    #[test]
+    fn pacing_is_weighted_by_time() {
+        let start = "2012-12-12T12:12:12Z".parse::<DateTime<Local>>().unwrap();
+        // 300 s at 200 W, then 150 s at 220 W recorded every second and 160 s at 100 W
+        // recorded every 10 seconds
+        let power_data = (0..300)
+            .map(|s| (s, 200))
+            .chain((300..450).map(|s| (s, 220)))
+            .chain((450..=600).step_by(10).map(|s| (s, 100)))
+            .map(|(s, power)| (Power(power), start + Duration::seconds(s)))
+            .collect::<Vec<_>>();
+        let power_data = power_data
+            .iter()
+            .map(|(power, timestamp)| (*power, timestamp))
+            .collect::<Vec<_>>();
+
+        // The first half is 305 s at 61100 J, the second 305 s at 47900 J
+        let pacing = calc_pacing(&power_data).unwrap();
+        assert_in_delta!(
+            pacing.change_percentage,
+            (47900.0 - 61100.0) / 61100.0 * 100.0,
+            1e-9
+        );
+    }
+
     #[test]
     fn activity_file_tss() {
         let mut fp = File::open("./tests/fixtures/Activity.fit").unwrap();
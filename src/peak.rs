@@ -1,12 +1,18 @@
 use crate::measurements::Average;
+use crate::metrics::time_windows;
 use chrono::{DateTime, Duration, Local};
 use std::cmp::Ordering;
 
 /// Peak of a given metric for a given amount of seconds
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Peak<T> {
     pub value: T,
     pub timestamps: TimeInterval,
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "crate::serialization::duration_seconds")
+    )]
     pub duration: Duration,
 }
 
@@ -41,6 +47,9 @@ impl<T> Eq for Peak<T> where T: Eq {}
 
 type TimeInterval = (DateTime<Local>, DateTime<Local>);
 
+/// A measurement value with its timestamp
+type Sample<'a, T> = (T, &'a DateTime<Local>);
+
 impl<T> Peak<T>
 where
     T: Ord + Average + Copy,
@@ -50,13 +59,56 @@ where
         measurements: &[(T, &DateTime<Local>)],
         duration: Duration,
     ) -> Option<Self> {
-        let windows = measurements.windows(duration.num_seconds() as usize);
-        windows
+        Self::from_measurement_records_with_samples(measurements, duration).map(|(peak, _)| peak)
+    }
+
+    /// Find a peak performance of a given measurement of n seconds, together with the samples of
+    /// the best window (e.g. for plotting the trace of the effort)
+    /// Windows are bounded by time, see `time_windows`.
+    pub fn from_measurement_records_with_samples<'a, 'b>(
+        measurements: &'a [Sample<'b, T>],
+        duration: Duration,
+    ) -> Option<(Self, &'a [Sample<'b, T>])> {
+        time_windows(measurements, duration)
+            .filter_map(|window| Some((get_peak(window, duration)?, window)))
+            .max_by(|(a, _), (b, _)| a.cmp(b))
+    }
+
+    /// Find the best n peak performances of a given measurement of n seconds, without any two of
+    /// them overlapping in time
+    /// Peaks are selected greedily: the best window is taken, windows overlapping it are
+    /// discarded, and so on. Returns fewer peaks if the activity is too short for n of them.
+    pub fn top_n_non_overlapping(
+        measurements: &[(T, &DateTime<Local>)],
+        duration: Duration,
+        n: usize,
+    ) -> Vec<Self> {
+        let mut candidates = time_windows(measurements, duration)
             .filter_map(|window| get_peak(window, duration))
-            .max()
+            .collect::<Vec<_>>();
+        // Stable sort, so the earliest of equal windows is preferred
+        candidates.sort_by(|a, b| b.cmp(a));
+
+        candidates
+            .into_iter()
+            .fold(Vec::new(), |mut selected: Vec<Self>, candidate| {
+                if selected.len() < n
+                    && !selected
+                        .iter()
+                        .any(|peak| overlaps(&peak.timestamps, &candidate.timestamps))
+                {
+                    selected.push(candidate);
+                }
+                selected
+            })
     }
 }
 
+/// Check if two closed time intervals overlap
+fn overlaps((a_start, a_end): &TimeInterval, (b_start, b_end): &TimeInterval) -> bool {
+    a_start <= b_end && b_start <= a_end
+}
+
 fn get_peak<T>(measurements: &[(T, &DateTime<Local>)], duration: Duration) -> Option<Peak<T>>
 where
     T: Average + Copy,
@@ -71,3 +123,91 @@ where
         duration,
     })
 }
+
+#[cfg(test)]
+mod peak_tests {
+    use super::*;
+    use crate::measurements::Power;
+
+    #[test]
+    fn top_peaks_do_not_overlap() {
+        let start = "2012-12-12T12:12:12Z".parse::<DateTime<Local>>().unwrap();
+        let timestamps = (0..60)
+            .map(|s| start + Duration::seconds(s))
+            .collect::<Vec<_>>();
+        let power_data = timestamps
+            .iter()
+            .enumerate()
+            .map(|(i, timestamp)| {
+                let power = match i {
+                    10..=14 => 400,
+                    15..=19 => 350,
+                    40..=44 => 300,
+                    _ => 100,
+                };
+                (Power(power), timestamp)
+            })
+            .collect::<Vec<_>>();
+
+        let peaks = Peak::top_n_non_overlapping(&power_data, Duration::seconds(5), 3);
+        let values = peaks
+            .iter()
+            .map(|peak| (peak.value, peak.timestamps.0 - start))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            values,
+            vec![
+                (Power(400), Duration::seconds(10)),
+                (Power(350), Duration::seconds(15)),
+                (Power(300), Duration::seconds(40)),
+            ]
+        );
+        assert_eq!(
+            Peak::from_measurement_records(&power_data, Duration::seconds(5)),
+            peaks.first().cloned()
+        );
+        let (peak, samples) =
+            Peak::from_measurement_records_with_samples(&power_data, Duration::seconds(5)).unwrap();
+        assert_eq!(samples.len(), 5);
+        assert!(samples.iter().all(|(power, _)| *power == Power(400)));
+        assert_eq!(*samples[0].1, peak.timestamps.0);
+        assert_eq!(*samples[4].1, peak.timestamps.1);
+        assert_eq!(
+            Peak::top_n_non_overlapping(&power_data, Duration::seconds(40), 5).len(),
+            1
+        );
+    }
+
+    #[test]
+    fn peaks_do_not_span_pauses() {
+        let start = "2012-12-12T12:12:12Z".parse::<DateTime<Local>>().unwrap();
+        // 20 minutes at 200 W, a 2 hour stop, then 10 seconds at 1000 W
+        let timestamps = (0..1200)
+            .map(|s| start + Duration::seconds(s))
+            .chain((0..10).map(|s| start + Duration::hours(2) + Duration::seconds(1200 + s)))
+            .collect::<Vec<_>>();
+        let power_data = timestamps
+            .iter()
+            .enumerate()
+            .map(|(i, timestamp)| (Power(if i < 1200 { 200 } else { 1000 }), timestamp))
+            .collect::<Vec<_>>();
+
+        let peak = Peak::from_measurement_records(&power_data, Duration::minutes(20)).unwrap();
+        assert_eq!(peak.value, Power(200));
+        assert_eq!(
+            peak.timestamps.1 - peak.timestamps.0,
+            Duration::seconds(1199)
+        );
+        assert_eq!(
+            Peak::from_measurement_records(&power_data, Duration::seconds(5))
+                .unwrap()
+                .value,
+            Power(1000)
+        );
+        assert_eq!(
+            Peak::top_n_non_overlapping(&power_data, Duration::minutes(20), 3).len(),
+            1
+        );
+    }
+}
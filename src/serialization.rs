@@ -0,0 +1,231 @@
+use chrono::Duration;
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::HashMap;
+
+/// Serialize a Duration as whole seconds
+pub mod duration_seconds {
+    use super::*;
+
+    pub fn serialize<S>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_i64(duration.num_seconds())
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Ok(Duration::seconds(i64::deserialize(deserializer)?))
+    }
+}
+
+/// Serialize an optional Duration as whole seconds
+pub mod option_duration_seconds {
+    use super::*;
+
+    pub fn serialize<S>(duration: &Option<Duration>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        duration
+            .map(|duration| duration.num_seconds())
+            .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Ok(Option::<i64>::deserialize(deserializer)?.map(Duration::seconds))
+    }
+}
+
+/// Serialize an optional fixed size array of Durations as a list of whole seconds
+pub mod option_duration_seconds_array {
+    use super::*;
+
+    pub fn serialize<S, const N: usize>(
+        durations: &Option<[Duration; N]>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        durations
+            .as_ref()
+            .map(|durations| durations.map(|duration| duration.num_seconds()).to_vec())
+            .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D, const N: usize>(
+        deserializer: D,
+    ) -> Result<Option<[Duration; N]>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Option::<Vec<i64>>::deserialize(deserializer)?
+            .map(|seconds| {
+                let length = seconds.len();
+                <[i64; N]>::try_from(seconds)
+                    .map(|seconds| seconds.map(Duration::seconds))
+                    .map_err(|_| D::Error::invalid_length(length, &"a list of zone durations"))
+            })
+            .transpose()
+    }
+}
+
+/// Serialize a map keyed by Durations with whole seconds as keys
+pub mod duration_seconds_map {
+    use super::*;
+
+    pub fn serialize<S, V>(map: &HashMap<Duration, V>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+        V: Serialize,
+    {
+        serializer.collect_map(
+            map.iter()
+                .map(|(duration, value)| (duration.num_seconds(), value)),
+        )
+    }
+
+    pub fn deserialize<'de, D, V>(deserializer: D) -> Result<HashMap<Duration, V>, D::Error>
+    where
+        D: Deserializer<'de>,
+        V: Deserialize<'de>,
+    {
+        Ok(HashMap::<i64, V>::deserialize(deserializer)?
+            .into_iter()
+            .map(|(seconds, value)| (Duration::seconds(seconds), value))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod serialization_tests {
+    use super::*;
+    use crate::activity::Activity;
+    use crate::activity_analysis::ActivityAnalysis;
+    use crate::daily_stats::{DailyStats, SortedDailyTSS};
+    use crate::measurements::{
+        Altitude, AltitudeDiff, Cadence, Distance, FractionalCadence, Grade, HeartRate,
+        LeftRightBalance, Pace, PedalSmoothness, Power, Speed, TorqueEffectiveness, Weight, Work,
+    };
+    use crate::metrics::{detect_intervals, DailyTSS, TSS};
+    use chrono::NaiveDate;
+    use serde::de::DeserializeOwned;
+    use std::collections::HashSet;
+    use std::fs::File;
+
+    /// Check that a value is the same after serializing, deserializing and serializing it again
+    fn assert_round_trip<T>(value: &T)
+    where
+        T: Serialize + DeserializeOwned,
+    {
+        let json = serde_json::to_value(value).unwrap();
+        let deserialized = serde_json::from_value::<T>(json.clone()).unwrap();
+        assert_eq!(serde_json::to_value(&deserialized).unwrap(), json);
+    }
+
+    fn read_fixture(path: &str) -> Activity {
+        let mut fp = File::open(path).unwrap();
+        Activity::from_reader(&mut fp).unwrap()
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Durations {
+        #[serde(with = "duration_seconds")]
+        duration: Duration,
+        #[serde(with = "option_duration_seconds")]
+        moving_time: Option<Duration>,
+        #[serde(with = "option_duration_seconds_array")]
+        zones: Option<[Duration; 2]>,
+        #[serde(with = "duration_seconds_map")]
+        peaks: HashMap<Duration, i64>,
+    }
+
+    #[test]
+    fn durations_as_seconds() {
+        let durations = Durations {
+            duration: Duration::minutes(1),
+            moving_time: None,
+            zones: Some([Duration::seconds(5), Duration::seconds(10)]),
+            peaks: HashMap::from([(Duration::minutes(5), 250)]),
+        };
+
+        let json = serde_json::to_string(&durations).unwrap();
+        assert_eq!(
+            json,
+            r#"{"duration":60,"moving_time":null,"zones":[5,10],"peaks":{"300":250}}"#
+        );
+        assert_eq!(serde_json::from_str::<Durations>(&json).unwrap(), durations);
+    }
+
+    #[test]
+    fn measurements_round_trip() {
+        assert_round_trip(&Power(250));
+        assert_round_trip(&Work(512.5));
+        assert_round_trip(&HeartRate(150));
+        assert_round_trip(&Cadence(90));
+        assert_round_trip(&FractionalCadence(90.5));
+        assert_round_trip(&Speed(8.25));
+        assert_round_trip(&Pace(300.0));
+        assert_round_trip(&Distance(42195.0));
+        assert_round_trip(&Grade(0.05));
+        assert_round_trip(&Altitude(1250.5));
+        assert_round_trip(&AltitudeDiff(12.5));
+        assert_round_trip(&Weight(70.5));
+        assert_round_trip(&LeftRightBalance(52));
+        assert_round_trip(&TorqueEffectiveness(75.5));
+        assert_round_trip(&PedalSmoothness(20.5));
+    }
+
+    #[test]
+    fn analysis_round_trip() {
+        let activity = read_fixture("./tests/fixtures/Activity.fit");
+        let peak_durations = HashSet::from([Duration::seconds(5), Duration::minutes(20)]);
+        let analysis =
+            ActivityAnalysis::from_activity(&Some(Power(260)), &None, &activity, &peak_durations);
+        assert!(analysis.pacing.is_some());
+
+        assert_round_trip(&analysis);
+        assert_round_trip(&analysis.peak_performances);
+        assert_round_trip(&analysis.peak_performances.power[&Duration::minutes(20)]);
+        assert_round_trip(&analysis.pacing);
+        assert_round_trip(&analysis.ride_type());
+        assert_round_trip(&analysis.try_tss());
+        assert_round_trip(&analysis.to_summary());
+    }
+
+    #[test]
+    fn activity_data_round_trip() {
+        let activity = read_fixture("./tests/fixtures/Activity.fit");
+        assert_round_trip(&activity.sessions());
+        assert_round_trip(&activity.device_info());
+        assert_round_trip(&activity.calibration_events());
+        assert_round_trip(&activity.record_series()[..10].to_vec());
+
+        let power_data = activity.get_data_with_timestamps::<Power>("power");
+        let intervals = detect_intervals(&power_data, Power(200), Duration::minutes(1));
+        assert!(!intervals.is_empty());
+        assert_round_trip(&intervals);
+
+        let swim = read_fixture("./tests/fixtures/Swim.fit");
+        assert_round_trip(&swim.swim_analysis().unwrap());
+
+        let first_day = NaiveDate::from_ymd_opt(2023, 10, 7).unwrap();
+        let daily_tss = [DailyTSS(first_day, TSS(100))];
+        let daily_stats =
+            DailyStats::calc_rolling(SortedDailyTSS::from_unsorted(&daily_tss, None), None);
+        assert_round_trip(&daily_stats);
+    }
+
+    #[test]
+    fn zone_count_mismatch() {
+        let json = r#"{"duration":60,"moving_time":null,"zones":[5,10,15],"peaks":{}}"#;
+        assert!(serde_json::from_str::<Durations>(json).is_err());
+    }
+}
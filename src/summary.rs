@@ -0,0 +1,235 @@
+use crate::activity_analysis::ActivityAnalysis;
+use crate::measurements::{
+    AltitudeDiff, Distance, FractionalCadence, HeartRate, Pace, Power, Speed, Work,
+};
+use crate::metrics::{IF, RI, TSS, VI};
+use crate::peak::Peak;
+use chrono::Duration;
+use std::collections::HashMap;
+
+/// Flat summary of an activity analysis with plain values, in the shape most training tools
+/// expect. Peaks are flattened to the standard 5 second, 1, 5 and 20 minute durations.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Summary {
+    /// Duration in seconds
+    pub duration: Option<i64>,
+    /// Distance in meters
+    pub distance: Option<f64>,
+    pub np: Option<i64>,
+    #[cfg_attr(feature = "serde", serde(rename = "if"))]
+    pub intensity_factor: Option<f64>,
+    pub xpower: Option<i64>,
+    pub ri: Option<f64>,
+    pub vi: Option<f64>,
+    pub tss: Option<i64>,
+    pub hr_tss: Option<i64>,
+    /// TSS per hour, see ActivityAnalysis::intensity_density
+    pub tss_per_hour: Option<f64>,
+    pub tss_per_km: Option<f64>,
+    pub ftp_estimated: bool,
+    /// Total work in kJ
+    pub work: Option<f64>,
+    pub avg_power: Option<i64>,
+    pub max_power: Option<i64>,
+    pub avg_heart_rate: Option<i64>,
+    pub max_heart_rate: Option<i64>,
+    pub avg_speed: Option<f64>,
+    /// Average speed excluding stopped samples
+    pub avg_moving_speed: Option<f64>,
+    pub max_speed: Option<f64>,
+    /// Normalized Graded Pace in seconds per km
+    pub ngp: Option<f64>,
+    pub avg_cadence: Option<f64>,
+    pub coasting_percentage: Option<f64>,
+    pub elevation_gain: Option<f64>,
+    pub elevation_loss: Option<f64>,
+    pub peak_power_5s: Option<i64>,
+    pub peak_power_1min: Option<i64>,
+    pub peak_power_5min: Option<i64>,
+    pub peak_power_20min: Option<i64>,
+    pub peak_heart_rate_5s: Option<i64>,
+    pub peak_heart_rate_1min: Option<i64>,
+    pub peak_heart_rate_5min: Option<i64>,
+    pub peak_heart_rate_20min: Option<i64>,
+    pub peak_speed_5s: Option<f64>,
+    pub peak_speed_1min: Option<f64>,
+    pub peak_speed_5min: Option<f64>,
+    pub peak_speed_20min: Option<f64>,
+}
+
+impl ActivityAnalysis {
+    /// Flatten the analysis into a Summary
+    pub fn to_summary(&self) -> Summary {
+        let power = |Power(power): Power| power;
+        let heart_rate = |HeartRate(heart_rate): HeartRate| heart_rate;
+        let speed = |Speed(speed): Speed| speed;
+        let altitude_diff = |AltitudeDiff(diff): AltitudeDiff| diff;
+        let peaks = &self.peak_performances;
+
+        Summary {
+            duration: self.duration.map(|duration| duration.num_seconds()),
+            distance: self.distance.map(|Distance(distance)| distance),
+            np: self.normalized_power.map(power),
+            intensity_factor: self.intensity_factor.map(|IF(value)| value),
+            xpower: self.xpower.map(power),
+            ri: self.relative_intensity.map(|RI(value)| value),
+            vi: self.variability_index.map(|VI(value)| value),
+            tss: self.tss().map(|TSS(tss)| tss),
+            hr_tss: self.hr_tss.map(|TSS(tss)| tss),
+            tss_per_hour: self.intensity_density(),
+            tss_per_km: self.intensity_density_per_km(),
+            ftp_estimated: self.ftp_estimated,
+            work: self.total_work.map(|Work(work)| work),
+            avg_power: self.average_power.map(power),
+            max_power: self.maximum_power.map(power),
+            avg_heart_rate: self.average_heart_rate.map(heart_rate),
+            max_heart_rate: self.maximum_heart_rate.map(heart_rate),
+            avg_speed: self.average_speed.map(speed),
+            avg_moving_speed: self.moving_average_speed.map(speed),
+            max_speed: self.maximum_speed.map(speed),
+            ngp: self.normalized_graded_pace.map(|Pace(pace)| pace),
+            avg_cadence: self
+                .average_cadence
+                .map(|FractionalCadence(cadence)| cadence),
+            coasting_percentage: self.coasting_percentage,
+            elevation_gain: self.elevation_gain.map(altitude_diff),
+            elevation_loss: self.elevation_loss.map(altitude_diff),
+            peak_power_5s: peak_value(&peaks.power, Duration::seconds(5)).map(power),
+            peak_power_1min: peak_value(&peaks.power, Duration::minutes(1)).map(power),
+            peak_power_5min: peak_value(&peaks.power, Duration::minutes(5)).map(power),
+            peak_power_20min: peak_value(&peaks.power, Duration::minutes(20)).map(power),
+            peak_heart_rate_5s: peak_value(&peaks.heart_rate, Duration::seconds(5)).map(heart_rate),
+            peak_heart_rate_1min: peak_value(&peaks.heart_rate, Duration::minutes(1))
+                .map(heart_rate),
+            peak_heart_rate_5min: peak_value(&peaks.heart_rate, Duration::minutes(5))
+                .map(heart_rate),
+            peak_heart_rate_20min: peak_value(&peaks.heart_rate, Duration::minutes(20))
+                .map(heart_rate),
+            peak_speed_5s: peak_value(&peaks.speed, Duration::seconds(5)).map(speed),
+            peak_speed_1min: peak_value(&peaks.speed, Duration::minutes(1)).map(speed),
+            peak_speed_5min: peak_value(&peaks.speed, Duration::minutes(5)).map(speed),
+            peak_speed_20min: peak_value(&peaks.speed, Duration::minutes(20)).map(speed),
+        }
+    }
+}
+
+/// Totals of multiple activities, e.g. over a season
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SeasonSummary {
+    pub activity_count: usize,
+    /// Sum of TSS, blended with hrTSS where power data is missing (see
+    /// ActivityAnalysis::combined_tss)
+    pub total_tss: TSS,
+    pub total_distance: Distance,
+    pub total_elevation_gain: AltitudeDiff,
+    pub total_work: Work,
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "crate::serialization::option_duration_seconds")
+    )]
+    pub median_duration: Option<Duration>,
+}
+
+impl SeasonSummary {
+    /// Sum up the analyses of multiple activities
+    /// Metrics missing from an activity (e.g. distance of an indoor ride) count as zero.
+    pub fn from_analyses<'a, I>(analyses: I) -> Self
+    where
+        I: IntoIterator<Item = &'a ActivityAnalysis>,
+    {
+        let analyses = analyses.into_iter().collect::<Vec<_>>();
+        let mut durations = analyses
+            .iter()
+            .filter_map(|analysis| analysis.duration)
+            .collect::<Vec<_>>();
+        durations.sort();
+        let median_duration = match durations.len() {
+            0 => None,
+            len if len % 2 == 0 => Some((durations[len / 2 - 1] + durations[len / 2]) / 2),
+            len => Some(durations[len / 2]),
+        };
+
+        Self {
+            activity_count: analyses.len(),
+            total_tss: analyses
+                .iter()
+                .filter_map(|analysis| analysis.combined_tss())
+                .fold(TSS(0), |acc, tss| acc + tss),
+            total_distance: analyses
+                .iter()
+                .filter_map(|analysis| analysis.distance)
+                .sum(),
+            total_elevation_gain: analyses
+                .iter()
+                .filter_map(|analysis| analysis.elevation_gain)
+                .sum(),
+            total_work: analyses
+                .iter()
+                .filter_map(|analysis| analysis.total_work)
+                .sum(),
+            median_duration,
+        }
+    }
+}
+
+/// Get the value of the peak of a given duration, if it was computed
+fn peak_value<T: Copy>(peaks: &HashMap<Duration, Peak<T>>, duration: Duration) -> Option<T> {
+    peaks.get(&duration).map(|peak| peak.value)
+}
+
+#[cfg(test)]
+mod summary_tests {
+    use super::*;
+    use crate::activity::Activity;
+    use std::collections::HashSet;
+    use std::fs::File;
+
+    #[test]
+    fn summary_of_activity() {
+        let mut fp = File::open("./tests/fixtures/Activity.fit").unwrap();
+        let activity = Activity::from_reader(&mut fp).unwrap();
+        let peak_durations = HashSet::from([Duration::seconds(5), Duration::hours(1)]);
+        let analysis =
+            ActivityAnalysis::from_activity(&Some(Power(260)), &None, &activity, &peak_durations);
+
+        let summary = analysis.to_summary();
+        assert_eq!(summary.np, analysis.normalized_power.map(|Power(np)| np));
+        assert_eq!(summary.tss, Some(67));
+        assert!(!summary.ftp_estimated);
+        assert_eq!(
+            summary.peak_power_5s,
+            Some(
+                analysis.peak_performances.power[&Duration::seconds(5)]
+                    .value
+                    .0
+            )
+        );
+        assert_eq!(summary.peak_power_20min, None);
+    }
+
+    #[test]
+    fn season_summary() {
+        let analyses = ["Activity.fit", "PedalingDynamics.fit", "Spikes.fit"].map(|name| {
+            let mut fp = File::open(format!("./tests/fixtures/{}", name)).unwrap();
+            let activity = Activity::from_reader(&mut fp).unwrap();
+            ActivityAnalysis::from_activity(&Some(Power(260)), &None, &activity, &HashSet::new())
+        });
+
+        let summary = SeasonSummary::from_analyses(&analyses);
+        assert_eq!(summary.activity_count, 3);
+        assert_eq!(
+            summary.total_tss,
+            analyses
+                .iter()
+                .map(|analysis| analysis.tss().unwrap())
+                .fold(TSS(0), |acc, tss| acc + tss)
+        );
+        assert_eq!(summary.median_duration, Some(Duration::minutes(10)));
+
+        let empty = SeasonSummary::from_analyses(&[]);
+        assert_eq!(empty.activity_count, 0);
+        assert_eq!(empty.median_duration, None);
+    }
+}
@@ -0,0 +1,222 @@
+use crate::activity::{find_one_value, value_to_str, value_to_timestamp, Activity};
+use chrono::{DateTime, Duration, Local};
+use fitparser::profile::field_types::MesgNum;
+use fitparser::FitDataRecord;
+
+/// Length of a yard in meters
+const YARD: f64 = 0.9144;
+
+/// A single active pool length of a swim
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SwimLength {
+    pub start_time: Option<DateTime<Local>>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "crate::serialization::duration_seconds")
+    )]
+    pub duration: Duration,
+    pub strokes: Option<i64>,
+    pub stroke: Option<String>,
+}
+
+impl SwimLength {
+    /// SWOLF (swim golf) score, the number of strokes plus seconds taken to swim the length
+    /// Lower is more efficient.
+    pub fn swolf(&self) -> Option<i64> {
+        Some(self.strokes? + self.duration.num_seconds())
+    }
+
+    /// Read a swim length from a length message
+    fn from_record(record: &FitDataRecord) -> Option<Self> {
+        let records = std::slice::from_ref(record);
+        let seconds: f64 = find_one_value(records, &MesgNum::Length, "total_timer_time")
+            .or_else(|| find_one_value(records, &MesgNum::Length, "total_elapsed_time"))?
+            .clone()
+            .try_into()
+            .ok()?;
+        let strokes =
+            find_one_value(records, &MesgNum::Length, "total_strokes").and_then(|value| {
+                let strokes: f64 = value.clone().try_into().ok()?;
+                Some(strokes as i64)
+            });
+
+        Some(Self {
+            start_time: find_one_value(records, &MesgNum::Length, "start_time")
+                .and_then(value_to_timestamp)
+                .cloned(),
+            duration: Duration::milliseconds((seconds * 1000.0) as i64),
+            strokes,
+            stroke: find_one_value(records, &MesgNum::Length, "swim_stroke")
+                .and_then(value_to_str)
+                .cloned(),
+        })
+    }
+}
+
+/// Unit the pool length was set in on the device
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PoolLengthUnit {
+    #[default]
+    Metric,
+    /// Yards
+    Statute,
+}
+
+/// Results of a pool swim analysis
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SwimAnalysis {
+    /// Pool length in meters, regardless of the unit it was set in
+    pub pool_length: f64,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub pool_length_unit: PoolLengthUnit,
+    /// Active lengths, rest intervals (idle lengths) are excluded
+    pub lengths: Vec<SwimLength>,
+    pub average_swolf: Option<f64>,
+    /// Average time taken to swim 100 meters
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "crate::serialization::duration_seconds")
+    )]
+    pub pace_per_100m: Duration,
+}
+
+impl SwimAnalysis {
+    /// Pool length in the unit it was set in, e.g. 25 for a 25 yard pool
+    pub fn pool_length_in_unit(&self) -> f64 {
+        match self.pool_length_unit {
+            PoolLengthUnit::Metric => self.pool_length,
+            PoolLengthUnit::Statute => self.pool_length / YARD,
+        }
+    }
+
+    /// Average time taken to swim 100 of the pool length unit, i.e. 100 yards in yard pools
+    pub fn pace_per_100_units(&self) -> Duration {
+        match self.pool_length_unit {
+            PoolLengthUnit::Metric => self.pace_per_100m,
+            PoolLengthUnit::Statute => Duration::milliseconds(
+                (self.pace_per_100m.num_milliseconds() as f64 * YARD).round() as i64,
+            ),
+        }
+    }
+}
+
+impl Activity {
+    /// Analyse the lengths of a pool swim
+    /// FIT files store the pool length in meters, for yard pools as well, pool_length_unit only
+    /// tells the unit it was set in. Returns None for activities without a pool length or
+    /// without any active lengths (e.g. open water swims and other sports).
+    pub fn swim_analysis(&self) -> Option<SwimAnalysis> {
+        let pool_length: f64 = self
+            .find_one_value(&MesgNum::Session, "pool_length")?
+            .clone()
+            .try_into()
+            .ok()?;
+        if pool_length <= 0.0 {
+            return None;
+        }
+        let pool_length_unit = match self
+            .find_one_value(&MesgNum::Session, "pool_length_unit")
+            .and_then(value_to_str)
+        {
+            Some(unit) if unit == "statute" => PoolLengthUnit::Statute,
+            _ => PoolLengthUnit::Metric,
+        };
+
+        let lengths = self
+            .records
+            .iter()
+            .filter(|record| record.kind() == MesgNum::Length)
+            .filter(|record| {
+                find_one_value(
+                    std::slice::from_ref(record),
+                    &MesgNum::Length,
+                    "length_type",
+                )
+                .and_then(value_to_str)
+                .is_none_or(|length_type| length_type == "active")
+            })
+            .filter_map(SwimLength::from_record)
+            .collect::<Vec<_>>();
+        if lengths.is_empty() {
+            return None;
+        }
+
+        let swolfs = lengths
+            .iter()
+            .filter_map(SwimLength::swolf)
+            .collect::<Vec<_>>();
+        let average_swolf =
+            (!swolfs.is_empty()).then(|| swolfs.iter().sum::<i64>() as f64 / swolfs.len() as f64);
+
+        let total_time = lengths
+            .iter()
+            .map(|length| length.duration)
+            .sum::<Duration>();
+        let distance = pool_length * lengths.len() as f64;
+        let pace_per_100m = Duration::milliseconds(
+            (total_time.num_milliseconds() as f64 * 100.0 / distance) as i64,
+        );
+
+        Some(SwimAnalysis {
+            pool_length,
+            pool_length_unit,
+            lengths,
+            average_swolf,
+            pace_per_100m,
+        })
+    }
+}
+
+#[cfg(test)]
+mod swim_tests {
+    use super::*;
+    use assertables::{assert_in_delta, assert_in_delta_as_result};
+    use std::fs::File;
+
+    #[test]
+    fn pool_swim() {
+        let mut fp = File::open("./tests/fixtures/Swim.fit").unwrap();
+        let activity = Activity::from_reader(&mut fp).unwrap();
+        let swim_analysis = activity.swim_analysis().unwrap();
+
+        assert_eq!(swim_analysis.pool_length, 25.0);
+        assert_eq!(swim_analysis.lengths.len(), 4);
+        assert_eq!(swim_analysis.lengths[0].swolf(), Some(40));
+        assert_eq!(
+            swim_analysis.lengths[0].stroke.as_deref(),
+            Some("freestyle")
+        );
+        assert_eq!(swim_analysis.average_swolf, Some(40.0));
+        assert_eq!(swim_analysis.pace_per_100m, Duration::seconds(100));
+        assert_eq!(swim_analysis.pool_length_unit, PoolLengthUnit::Metric);
+        assert_eq!(swim_analysis.pace_per_100_units(), Duration::seconds(100));
+    }
+
+    #[test]
+    fn yard_pool_swim() {
+        let mut fp = File::open("./tests/fixtures/SwimYards.fit").unwrap();
+        let activity = Activity::from_reader(&mut fp).unwrap();
+        let swim_analysis = activity.swim_analysis().unwrap();
+
+        // 4 lengths of 25 yards in 80 seconds
+        assert_eq!(swim_analysis.pool_length_unit, PoolLengthUnit::Statute);
+        assert_in_delta!(swim_analysis.pool_length, 22.86, 1e-9);
+        assert_in_delta!(swim_analysis.pool_length_in_unit(), 25.0, 1e-9);
+        assert_eq!(swim_analysis.pace_per_100_units(), Duration::seconds(80));
+        assert_eq!(
+            swim_analysis.pace_per_100m,
+            Duration::milliseconds((80_000.0 / YARD) as i64)
+        );
+    }
+
+    #[test]
+    fn not_a_swim() {
+        let mut fp = File::open("./tests/fixtures/Activity.fit").unwrap();
+        let activity = Activity::from_reader(&mut fp).unwrap();
+
+        assert_eq!(activity.swim_analysis(), None);
+    }
+}
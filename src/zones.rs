@@ -0,0 +1,159 @@
+use chrono::Duration;
+
+/// A named training zone, bounded by fractions of a threshold (e.g. FTP or FTHr)
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Zone {
+    pub name: String,
+    /// Inclusive lower bound as a fraction of the threshold
+    pub lower: f64,
+    /// Exclusive upper bound as a fraction of the threshold, None for an open ended top zone
+    pub upper: Option<f64>,
+}
+
+impl Zone {
+    /// Check if a value, given as a fraction of the threshold, falls into the zone
+    fn contains(&self, fraction: f64) -> bool {
+        self.lower <= fraction && self.upper.is_none_or(|upper| fraction < upper)
+    }
+}
+
+/// Threshold based training zones, shared by heart rate and power zones
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ZoneModel {
+    pub zones: Vec<Zone>,
+}
+
+impl ZoneModel {
+    /// Create a model of adjacent zones from their names and lower bounds in ascending order
+    /// Each zone ends where the next one starts, the last one is open ended.
+    pub fn contiguous<S>(lower_bounds: impl IntoIterator<Item = (S, f64)>) -> Self
+    where
+        S: Into<String>,
+    {
+        let lower_bounds = lower_bounds
+            .into_iter()
+            .map(|(name, lower)| (name.into(), lower))
+            .collect::<Vec<_>>();
+        let zones = lower_bounds
+            .iter()
+            .enumerate()
+            .map(|(index, (name, lower))| Zone {
+                name: name.clone(),
+                lower: *lower,
+                upper: lower_bounds.get(index + 1).map(|(_, upper)| *upper),
+            })
+            .collect();
+        Self { zones }
+    }
+
+    /// Coggan power zones in fractions of FTP
+    /// Power is compared to the bounds as a fraction, not rounded to whole percentages, so e.g.
+    /// 150.5% of FTP is already Neuromuscular Power.
+    pub fn coggan_power() -> Self {
+        Self::contiguous([
+            ("Active Recovery", 0.0),
+            ("Endurance", 0.55),
+            ("Tempo", 0.76),
+            ("Lactate Threshold", 0.91),
+            ("VO2max", 1.06),
+            ("Anaerobic Capacity", 1.21),
+            ("Neuromuscular Power", 1.50),
+        ])
+    }
+
+    /// Heart rate zones of hrTSS in fractions of FTHr
+    pub fn hr_tss() -> Self {
+        Self::contiguous(
+            [0.0, 0.73, 0.77, 0.81, 0.85, 0.89, 0.93, 1.0, 1.03, 1.06]
+                .iter()
+                .enumerate()
+                .map(|(index, lower)| (format!("Zone {}", index + 1), *lower)),
+        )
+    }
+
+    /// Find the index of the zone a value belongs to
+    /// None if it falls outside of all zones, or if the threshold is not positive.
+    pub fn zone_of<T: Into<f64>>(&self, value: T, threshold: T) -> Option<usize> {
+        let threshold = threshold.into();
+        if threshold <= 0.0 {
+            return None;
+        }
+        let fraction = value.into() / threshold;
+        self.zones.iter().position(|zone| zone.contains(fraction))
+    }
+}
+
+/// Calculate the time spent in each zone of a model, assuming one sample per second
+/// Samples outside of all zones are not counted.
+pub fn time_in_zones<T>(samples: &[T], threshold: T, model: &ZoneModel) -> Vec<Duration>
+where
+    T: Ord + Copy + Into<f64>,
+{
+    samples
+        .iter()
+        .fold(vec![0; model.zones.len()], |mut acc, sample| {
+            if let Some(index) = model.zone_of(*sample, threshold) {
+                acc[index] += 1;
+            }
+            acc
+        })
+        .into_iter()
+        .map(Duration::seconds)
+        .collect()
+}
+
+#[cfg(test)]
+mod zones_tests {
+    use super::*;
+    use crate::measurements::{HeartRate, Power};
+
+    #[test]
+    fn contiguous_zones() {
+        let model = ZoneModel::coggan_power();
+
+        assert_eq!(model.zones.len(), 7);
+        assert_eq!(model.zones[1].lower, 0.55);
+        assert_eq!(model.zones[1].upper, Some(0.76));
+        assert_eq!(model.zones[6].upper, None);
+        assert_eq!(model.zone_of(Power(110), Power(200)), Some(1));
+        assert_eq!(model.zone_of(Power(109), Power(200)), Some(0));
+        assert_eq!(model.zone_of(Power(100), Power(0)), None);
+    }
+
+    #[test]
+    fn fractional_zone_boundaries() {
+        let model = ZoneModel::coggan_power();
+
+        // 55.9% and 150.5% of FTP
+        assert_eq!(model.zone_of(Power(559), Power(1000)), Some(1));
+        assert_eq!(model.zone_of(Power(549), Power(1000)), Some(0));
+        assert_eq!(model.zone_of(Power(1505), Power(1000)), Some(6));
+        assert_eq!(model.zone_of(Power(1499), Power(1000)), Some(5));
+    }
+
+    #[test]
+    fn time_in_custom_zones() {
+        let model = ZoneModel {
+            zones: vec![
+                Zone {
+                    name: "Easy".to_string(),
+                    lower: 0.5,
+                    upper: Some(0.8),
+                },
+                Zone {
+                    name: "Hard".to_string(),
+                    lower: 0.9,
+                    upper: None,
+                },
+            ],
+        };
+        let heart_rate_data = [80, 140, 170, 180, 190].map(HeartRate);
+
+        assert_eq!(
+            time_in_zones(&heart_rate_data, HeartRate(180), &model),
+            [1, 3].map(Duration::seconds)
+        );
+    }
+}